@@ -3,7 +3,7 @@ use vesuvius_gui::gui::{ObjFileConfig, TemplateApp, VesuviusConfig};
 
 use clap::Parser;
 use vesuvius_gui::model::{NewVolumeReference, VolumeReference};
-use vesuvius_gui::volume::{AffineTransform, ProjectionKind};
+use vesuvius_gui::volume::{AffineTransform, CacheConfig, FilterKind, ProjectionKind, TifXyzSurface};
 
 /// Vesuvius GUI, an app to visualize and explore 3D data of the Vesuvius Challenge (https://scrollprize.org)
 #[derive(Parser, Debug)]
@@ -13,14 +13,15 @@ pub struct Args {
     #[clap(short, long)]
     data_directory: Option<String>,
 
-    /// Browse segment from obj file. You need to also provide --width and --height. Provide the --volume if the segment does not target Scroll 1a / 20230205180739
+    /// Browse segment from an obj or ply file or a tifxyz surface directory (x.tif, y.tif, z.tif and meta.json). For
+    /// meshes you need to also provide --width and --height. Provide the --volume if the segment does not target Scroll 1a / 20230205180739
     #[clap(long)]
     obj: Option<String>,
 
-    /// Width of the segment file when browsing obj files
+    /// Width of the segment file when browsing obj files (not needed for tifxyz surfaces and flattened or projected meshes)
     #[clap(long)]
     width: Option<usize>,
-    /// Height of the segment file when browsing obj files
+    /// Height of the segment file when browsing obj files (not needed for tifxyz surfaces and flattened or projected meshes)
     #[clap(long)]
     height: Option<usize>,
 
@@ -34,17 +35,73 @@ pub struct Args {
     #[clap(long, default_value_t = false)]
     ortho_xz: bool,
 
+    /// Compute a low-distortion UV map (LSCM) instead of using the texture coordinates of the mesh. This happens
+    /// automatically for meshes without usable texture coordinates.
+    #[clap(long, default_value_t = false)]
+    flatten: bool,
+
+    /// Project the mesh around a cylinder axis through the given center, e.g. the umbilicus of the scroll, using
+    /// angle and height as texture coordinates. Format: `x,y,z` (axis along z) or `x,y,z,dx,dy,dz`
+    #[clap(long)]
+    cylindrical: Option<String>,
+
     /// Invert the transform before applying it
     #[clap(long)]
     invert_transform: bool,
 
-    /// A directory that contains data to overlay. Only zarr arrays are currently supported
+    /// A volume to show as an additional layer on top of the main volume (URL or local path as for --volume)
     #[clap(short, long)]
     overlay: Option<String>,
 
-    /// The id of a volume to open, URL to a zarr/ome-zarr volume, or local path to zarr/ome-zarr directory
+    /// Transform to apply to the overlay (same format as --transform)
+    #[clap(long)]
+    overlay_transform: Option<String>,
+
+    /// Invert the overlay transform before applying it
+    #[clap(long)]
+    invert_overlay_transform: bool,
+
+    /// The id of a volume to open, URL to a zarr/ome-zarr volume, or local path to a zarr/ome-zarr directory, a
+    /// directory of tif layers or cells, a multi-page tif, a nrrd file, or a raw .bin file with a .json header.
+    /// Use `synthetic:<pattern>` (rolled-sheets, checkerboard, gradient) for a generated test volume
     #[clap(short, long)]
     volume: Option<Option<String>>,
+
+    /// Transform to apply to the volume, e.g. to browse a scan in the coordinate frame of another scan of the same
+    /// scroll (same format as --transform)
+    #[clap(long)]
+    volume_transform: Option<String>,
+
+    /// Invert the volume transform before applying it
+    #[clap(long)]
+    invert_volume_transform: bool,
+
+    /// Filter to apply to the volume. One of gaussian:<sigma> (sigma 0.3 to 8), median:<radius>
+    /// (radius 1 to 3), gradient
+    #[clap(long)]
+    filter: Option<String>,
+
+    /// A binary mask volume (URL or local path to a zarr volume or a directory with 64-4 tiles). Only the parts of the
+    /// --volume where the mask is non-zero are downloaded and shown. Lower resolution masks are scaled to the volume
+    #[clap(long)]
+    mask: Option<String>,
+
+    /// Maximum amount of memory to use for cached tiles / decompressed zarr chunks / decoded TIFF data (in MB)
+    #[clap(long)]
+    cache_size_mb: Option<usize>,
+
+    /// Maximum number of tiles / zarr chunks / TIFF strips to keep in the cache
+    #[clap(long)]
+    cache_tiles: Option<usize>,
+}
+
+fn parse_transform(transform: &str, invert: bool) -> Result<AffineTransform, String> {
+    let t = AffineTransform::from_json_array_or_path(transform).map_err(|e| e.to_string())?;
+    if invert {
+        t.invert().map_err(|e| e.to_string())
+    } else {
+        Ok(t)
+    }
 }
 
 impl TryFrom<Args> for VesuviusConfig {
@@ -67,6 +124,8 @@ impl TryFrom<Args> for VesuviusConfig {
             // Try to parse as URL first
             if vol_str.starts_with("http") {
                 Some(NewVolumeReference::from_url(vol_str).map_err(|e| e.to_string())?)
+            } else if let Some(spec) = vol_str.strip_prefix("synthetic:") {
+                Some(NewVolumeReference::from_synthetic(spec).map_err(|e| e.to_string())?)
             } else if std::path::Path::new(&vol_str).exists() {
                 // Try to parse as local path
                 Some(NewVolumeReference::from_path(vol_str).map_err(|e| e.to_string())?)
@@ -76,7 +135,7 @@ impl TryFrom<Args> for VesuviusConfig {
                     Some(NewVolumeReference::Volume64x4(static_vol.owned()))
                 } else {
                     return Err(format!(
-                        "Error: Volume {} not found. Use one of:\n{}\n\nOr provide:\n- HTTP URL to zarr/ome-zarr volume\n- Local filesystem path to zarr/ome-zarr directory",
+                        "Error: Volume {} not found. Use one of:\n{}\n\nOr provide:\n- HTTP URL to zarr/ome-zarr volume\n- Local filesystem path to zarr/ome-zarr directory, tif layer or cell directory, multi-page tif, nrrd, or raw .bin file\n- synthetic:<pattern> for a generated test volume (rolled-sheets, checkerboard, gradient)",
                         vol_str,
                         <dyn VolumeReference>::VOLUMES
                             .iter()
@@ -89,24 +148,77 @@ impl TryFrom<Args> for VesuviusConfig {
         } else {
             None
         };
-        let obj_file = if let Some(obj_file) = args.obj {
-            let transform = if let Some(transform) = args.transform {
-                let mut t = AffineTransform::from_json_array_or_path(&transform).map_err(|e| e.to_string())?;
-                if args.invert_transform {
-                    t = t.invert().map_err(|e| e.to_string())?;
-                }
-                Some(t)
+        let volume = match (volume, args.volume_transform) {
+            (Some(volume), Some(transform)) => {
+                Some(volume.transformed(parse_transform(&transform, args.invert_volume_transform)?))
+            }
+            (None, Some(_)) => return Err("Error: --volume-transform requires --volume".to_string()),
+            (volume, None) => volume,
+        };
+        let volume = match (volume, args.mask) {
+            (Some(volume), Some(mask)) => Some(
+                volume.masked(
+                    if mask.starts_with("http") {
+                        NewVolumeReference::from_url(mask)
+                    } else {
+                        NewVolumeReference::from_path(mask)
+                    }
+                    .map_err(|e| e.to_string())?,
+                ),
+            ),
+            (None, Some(_)) => return Err("Error: --mask requires --volume".to_string()),
+            (volume, None) => volume,
+        };
+        let volume = match (volume, args.filter) {
+            (Some(volume), Some(filter)) => Some(volume.filtered(FilterKind::from_spec(&filter)?)),
+            (None, Some(_)) => return Err("Error: --filter requires --volume".to_string()),
+            (volume, None) => volume,
+        };
+        let overlay = if let Some(overlay) = args.overlay {
+            let overlay = if overlay.starts_with("http") {
+                NewVolumeReference::from_url(overlay)
+            } else if let Some(spec) = overlay.strip_prefix("synthetic:") {
+                NewVolumeReference::from_synthetic(spec)
             } else {
-                None
-            };
+                NewVolumeReference::from_path(overlay)
+            }
+            .map_err(|e| e.to_string())?;
+            if let Some(transform) = args.overlay_transform {
+                Some(overlay.transformed(parse_transform(&transform, args.invert_overlay_transform)?))
+            } else {
+                Some(overlay)
+            }
+        } else {
+            None
+        };
+        let obj_file = if let Some(obj_file) = args.obj {
+            let transform = args
+                .transform
+                .map(|t| parse_transform(&t, args.invert_transform))
+                .transpose()?;
 
-            let projection = if args.ortho_xz {
+            let projection = if args.flatten {
+                ProjectionKind::Flatten
+            } else if let Some(cylinder) = &args.cylindrical {
+                ProjectionKind::cylindrical_from_spec(cylinder)?
+            } else if args.ortho_xz {
                 ProjectionKind::OrthographicXZ
             } else {
                 ProjectionKind::None
             };
 
-            if let (Some(width), Some(height)) = (args.width, args.height) {
+            if TifXyzSurface::is_tifxyz(&obj_file)
+                || matches!(projection, ProjectionKind::Flatten | ProjectionKind::Cylindrical { .. })
+            {
+                // the size is derived from the surface grid or the flattened or projected mesh
+                Some(ObjFileConfig {
+                    obj_file,
+                    width: 0,
+                    height: 0,
+                    transform,
+                    projection,
+                })
+            } else if let (Some(width), Some(height)) = (args.width, args.height) {
                 Some(ObjFileConfig {
                     obj_file,
                     width,
@@ -121,11 +233,22 @@ impl TryFrom<Args> for VesuviusConfig {
             None
         };
 
+        let cache_config = if args.cache_size_mb.is_some() || args.cache_tiles.is_some() {
+            let default = CacheConfig::default();
+            Some(CacheConfig {
+                max_entries: args.cache_tiles.unwrap_or(default.max_entries),
+                max_bytes: args.cache_size_mb.map_or(default.max_bytes, |mb| mb << 20),
+            })
+        } else {
+            None
+        };
+
         Ok(VesuviusConfig {
             data_dir: args.data_directory,
             obj_file,
-            overlay_dir: args.overlay,
+            overlay,
             volume,
+            cache_config,
         })
     }
 }
@@ -1,8 +1,8 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use async_recursion::async_recursion;
 use clap::Parser;
 use directories::BaseDirs;
-use futures::{stream, StreamExt};
+use futures::{stream, StreamExt, TryStreamExt};
 use image::Luma;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use std::cell::RefCell;
@@ -12,10 +12,11 @@ use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use vesuvius_gui::downloader::{DownloadState as DS, Downloader};
 use vesuvius_gui::model::Quality;
-use vesuvius_gui::model::{FullVolumeReference, VolumeReference};
+use vesuvius_gui::model::{FullVolumeReference, NewVolumeReference, VolumeCreationParams, VolumeReference};
 use vesuvius_gui::volume::{
-    self, AffineTransform, DrawingConfig, Image, ObjFile, ObjVolume, PaintVolume, ProjectionKind, Volume, VolumeCons,
-    VoxelPaintVolume, VoxelVolume,
+    self, AffineTransform, CacheConfig, CompositingMode, DrawingConfig, FilterKind, FilteredVolume, Image,
+    MaskedVolume, ObjFile, ObjVolume, PaintVolume, ProjectionKind, TifXyzSurface, Volume, VolumeCons, VoxelPaintVolume,
+    VoxelVolume,
 };
 
 #[derive(Clone, Debug)]
@@ -60,16 +61,17 @@ impl clap::builder::TypedValueParser for CropParser {
 #[derive(Parser, Debug)]
 #[command(about, long_about = None)]
 pub struct Args {
-    /// Provide segment file to render
+    /// Provide segment file to render, either an obj or ply (ascii or binary little-endian) file or a tifxyz surface
+    /// directory
     #[clap(long)]
     obj: String,
 
-    /// Width of the segment file when browsing obj files
+    /// Width of the segment file when rendering meshes (tifxyz surfaces and flattened meshes provide their own size)
     #[clap(long)]
-    width: u32,
-    /// Height of the segment file when browsing obj files
+    width: Option<u32>,
+    /// Height of the segment file when rendering meshes (tifxyz surfaces and flattened meshes provide their own size)
     #[clap(long)]
-    height: u32,
+    height: Option<u32>,
 
     /// Transform to apply to the obj file (to map between different scans). You can either supply a filename to a transform json file
     /// (as defined in https://github.com/ScrollPrize/villa/blob/main/foundation/volume-registration/transform_schema.json) or supply
@@ -81,6 +83,16 @@ pub struct Args {
     #[clap(long, default_value_t = false)]
     ortho_xz: bool,
 
+    /// Compute a low-distortion UV map (LSCM) instead of using the texture coordinates of the mesh. This happens
+    /// automatically for meshes without usable texture coordinates.
+    #[clap(long, default_value_t = false)]
+    flatten: bool,
+
+    /// Project the mesh around a cylinder axis through the given center, e.g. the umbilicus of the scroll, using
+    /// angle and height as texture coordinates. Format: `x,y,z` (axis along z) or `x,y,z,dx,dy,dz`
+    #[clap(long)]
+    cylindrical: Option<String>,
+
     /// Invert the transform before applying it
     #[clap(long)]
     invert_transform: bool,
@@ -105,6 +117,54 @@ pub struct Args {
     #[clap(long, value_parser = CropParser)]
     crop: Option<Crop>,
 
+    /// Render a single composite image (composite.<format>) around the middle layer instead of individual layers.
+    /// One of max, min, mean, median, percentile, alpha
+    #[clap(long)]
+    composite: Option<String>,
+
+    /// Number of layers in front of the middle layer to composite (default 6)
+    #[clap(long)]
+    composite_layers_in_front: Option<u8>,
+
+    /// Number of layers behind the middle layer to composite (default 6)
+    #[clap(long)]
+    composite_layers_behind: Option<u8>,
+
+    /// Percentile to use with --composite percentile (default 90)
+    #[clap(long)]
+    composite_percentile: Option<u8>,
+
+    /// Reverse the compositing direction along the surface normal
+    #[clap(long)]
+    composite_reverse: bool,
+
+    /// Apply local contrast enhancement (CLAHE) to the rendered images
+    #[clap(long)]
+    clahe: bool,
+
+    /// CLAHE clip limit as a multiple of the average histogram bin height (default 3)
+    #[clap(long)]
+    clahe_clip_limit: Option<u8>,
+
+    /// CLAHE context region size in pixels, must divide the tile size (default 64)
+    #[clap(long)]
+    clahe_region_size: Option<u16>,
+
+    /// Filter to apply to the volume before rendering. One of gaussian:<sigma> (sigma 0.3 to 8), median:<radius>
+    /// (radius 1 to 3), gradient
+    #[clap(long)]
+    filter: Option<String>,
+
+    /// Local path to a binary mask volume (zarr or a directory with 64-4 tiles). Chunks outside of the mask are
+    /// neither downloaded nor rendered. Unlike in the GUI, URLs are not supported, download the mask first
+    #[clap(long)]
+    mask: Option<String>,
+
+    /// Number of volume voxels per mask voxel along each axis. By default derived from the shapes of the volume and
+    /// the mask, required if one of them is unknown
+    #[clap(long)]
+    mask_scale: Option<f64>,
+
     /// File extension / image format to use for layers (default png)
     #[clap(long)]
     target_format: Option<String>,
@@ -138,6 +198,10 @@ pub struct Args {
     /// CPU-bound worker threads to use (default number of cores/threads)
     #[clap(long)]
     worker_threads: Option<usize>,
+
+    /// Maximum amount of memory to use for cached volume tiles and mask data (in MB, default 2048)
+    #[clap(long)]
+    cache_size: Option<usize>,
 }
 
 fn main() -> Result<()> {
@@ -158,21 +222,19 @@ async fn main_run(args: Args) -> Result<()> {
     let multi = MultiProgress::new();
     monitor_runtime_stats(&multi).await;
 
-    let params = (&args).into();
+    let params = (&args).try_into()?;
     let settings = (&args).try_into()?;
 
-    let rendering = Rendering::new(params, settings);
+    let rendering = Rendering::new(params, settings)?;
     rendering.run(&multi).await?;
     Ok(())
 }
 
 #[derive(Clone)]
 struct RenderParams {
-    obj_file: String,
+    obj: Arc<ObjFile>,
     width: usize,
     height: usize,
-    transform: Option<AffineTransform>,
-    projection: ProjectionKind,
     tile_size: usize,
     w_range: RangeInclusive<usize>,
     crop: Option<Crop>,
@@ -180,7 +242,27 @@ struct RenderParams {
     target_dir: String,
     target_format: String,
     stream_buffer_size: usize,
+    drawing_config: DrawingConfig,
+    filter: Option<FilterKind>,
+    mask: Option<Arc<NewVolumeReference>>,
+    mask_scale: f64,
+    cache: CacheConfig,
+}
+
+thread_local! {
+    // volumes cannot be shared between threads, so every worker opens the mask once
+    static MASK: RefCell<Option<Volume>> = const { RefCell::new(None) };
 }
+
+fn open_mask(mask: &NewVolumeReference, cache: CacheConfig) -> Result<Volume> {
+    let params = VolumeCreationParams {
+        cache_dir: String::new(),
+        cache,
+    };
+    mask.volume(&params)
+        .map_err(|e| anyhow!("Cannot open mask {}: {}", mask.label(), e))
+}
+
 impl RenderParams {
     fn render_left(&self) -> usize {
         self.crop.as_ref().map(|c| c.left).unwrap_or(0)
@@ -194,33 +276,175 @@ impl RenderParams {
     fn render_height(&self) -> usize {
         self.crop.as_ref().map(|c| c.height).unwrap_or(self.height)
     }
+    fn is_composite(&self) -> bool {
+        self.drawing_config.compositing.mode != CompositingMode::None
+    }
+    fn with_filter(&self, volume: Volume) -> Volume {
+        match self.filter {
+            Some(filter) => FilteredVolume::new(volume, filter)
+                .with_cache_config(self.cache)
+                .into_volume(),
+            None => volume,
+        }
+    }
+    fn with_mask(&self, volume: Volume) -> Result<Volume> {
+        let Some(mask_ref) = &self.mask else {
+            return Ok(volume);
+        };
+        let mask = MASK.with(|mask| {
+            let mut mask = mask.borrow_mut();
+            if mask.is_none() {
+                *mask = Some(open_mask(mask_ref, self.cache)?);
+            }
+            Ok::<_, anyhow::Error>(mask.clone().unwrap())
+        })?;
+        Ok(MaskedVolume::new(volume, mask)
+            .with_mask_scale(self.mask_scale)
+            .into_volume())
+    }
 }
-impl From<&Args> for RenderParams {
-    fn from(args: &Args) -> Self {
-        Self {
-            obj_file: args.obj.clone(),
-            width: args.width as usize,
-            height: args.height as usize,
-            transform: args.transform.as_ref().map(|t| {
-                let mut transform = AffineTransform::from_json_array_or_path(t).unwrap();
+impl TryFrom<&Args> for RenderParams {
+    type Error = anyhow::Error;
+    fn try_from(args: &Args) -> std::result::Result<Self, Self::Error> {
+        let mut drawing_config = DrawingConfig {
+            trilinear_interpolation: true,
+            ..Default::default()
+        };
+        if let Some(mode) = &args.composite {
+            let compositing = &mut drawing_config.compositing;
+            compositing.mode = match mode.to_lowercase().as_str() {
+                "max" => CompositingMode::Max,
+                "min" => CompositingMode::Min,
+                "mean" => CompositingMode::Mean,
+                "median" => CompositingMode::Median,
+                "percentile" => CompositingMode::Percentile,
+                "alpha" => CompositingMode::Alpha,
+                _ => return Err(anyhow!("Unknown compositing mode: {}", mode)),
+            };
+            compositing.layers_in_front = args.composite_layers_in_front.unwrap_or(compositing.layers_in_front);
+            compositing.layers_behind = args.composite_layers_behind.unwrap_or(compositing.layers_behind);
+            compositing.percentile = args.composite_percentile.unwrap_or(compositing.percentile);
+            compositing.reverse_direction = args.composite_reverse;
+        }
+        drawing_config.clahe.enabled = args.clahe;
+        drawing_config.clahe.clip_limit = args.clahe_clip_limit.unwrap_or(drawing_config.clahe.clip_limit);
+        drawing_config.clahe.region_size = args.clahe_region_size.unwrap_or(drawing_config.clahe.region_size);
+        let tile_size = args.tile_size.unwrap_or(1024) as usize;
+        if args.clahe
+            && (drawing_config.clahe.region_size == 0 || tile_size % drawing_config.clahe.region_size as usize != 0)
+        {
+            return Err(anyhow!(
+                "CLAHE region size {} must divide the tile size {}",
+                drawing_config.clahe.region_size,
+                tile_size
+            ));
+        }
+        let cache = CacheConfig {
+            max_bytes: args.cache_size.map_or(CacheConfig::default().max_bytes, |mb| mb << 20),
+            ..CacheConfig::default()
+        };
+        let mid_layer = args.middle_layer.unwrap_or(32) as usize;
+        let w_range = if drawing_config.compositing.mode != CompositingMode::None {
+            // compositing happens along the normal around the surface
+            mid_layer..=mid_layer
+        } else {
+            args.min_layer.unwrap_or(25) as usize..=args.max_layer.unwrap_or(41) as usize
+        };
+
+        let mask = args
+            .mask
+            .as_ref()
+            .map(|m| {
+                if m.starts_with("http") {
+                    return Err(anyhow!("--mask only supports local paths, download {} first", m));
+                }
+                NewVolumeReference::from_path(m).map_err(|e| anyhow!(e))
+            })
+            .transpose()?;
+        let mask_scale = match (&mask, args.mask_scale) {
+            (_, Some(scale)) if scale > 0.0 => scale,
+            (_, Some(scale)) => return Err(anyhow!("--mask-scale must be positive but was {}", scale)),
+            (Some(mask), None) => {
+                // check early that the mask can be opened, workers open their own copy
+                let mask_metadata = open_mask(mask, cache)?.metadata();
+                let volume = volume_ref(args)?;
+                match (volume.metadata(), mask_metadata) {
+                    (Some(v), Some(m)) => MaskedVolume::scale_between(&v, &m),
+                    _ => None,
+                }
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Cannot derive the scale of mask {} for volume {}, please provide --mask-scale",
+                        mask.label(),
+                        volume.label()
+                    )
+                })?
+            }
+            (None, None) => 1.0,
+        };
+        if let (Some(mask), Some(_)) = (&mask, args.mask_scale) {
+            open_mask(mask, cache)?;
+        }
+
+        let transform = args
+            .transform
+            .as_ref()
+            .map(|t| {
+                let transform = AffineTransform::from_json_array_or_path(t)?;
                 if args.invert_transform {
-                    transform = transform.invert().unwrap();
+                    transform.invert()
+                } else {
+                    Ok(transform)
                 }
-                transform
-            }),
-            projection: if args.ortho_xz {
-                ProjectionKind::OrthographicXZ
+            })
+            .transpose()?;
+        let projection = if args.flatten {
+            ProjectionKind::Flatten
+        } else if let Some(cylinder) = &args.cylindrical {
+            ProjectionKind::cylindrical_from_spec(cylinder).map_err(|e| anyhow!(e))?
+        } else if args.ortho_xz {
+            ProjectionKind::OrthographicXZ
+        } else {
+            ProjectionKind::None
+        };
+        let (obj, width, height) = if TifXyzSurface::is_tifxyz(&args.obj) {
+            let surface = TifXyzSurface::load(&args.obj)?;
+            let obj = surface.to_obj_file(&transform, projection)?;
+            (obj, surface.width(), surface.height())
+        } else {
+            let obj = ObjVolume::load_obj(&args.obj, &transform, projection)?;
+            if let Some([width, height]) = obj.flattened_size() {
+                println!("Using size {}x{} of the flattened mesh", width, height);
+                (obj, width, height)
+            } else if let (Some(width), Some(height)) = (args.width, args.height) {
+                (obj, width as usize, height as usize)
             } else {
-                ProjectionKind::None
-            },
-            tile_size: args.tile_size.unwrap_or(1024) as usize,
-            w_range: args.min_layer.unwrap_or(25) as usize..=args.max_layer.unwrap_or(41) as usize,
+                return Err(anyhow!("--width and --height are required for obj and ply files"));
+            }
+        };
+
+        Ok(Self {
+            obj: Arc::new(obj),
+            width,
+            height,
+            tile_size,
+            w_range,
             crop: args.crop.clone(),
-            mid_layer: args.middle_layer.unwrap_or(32) as usize,
+            mid_layer,
             target_dir: args.target_dir.clone(),
             target_format: args.target_format.clone().unwrap_or("png".to_string()),
             stream_buffer_size: args.stream_buffer_size.unwrap_or(1024),
-        }
+            drawing_config,
+            filter: args
+                .filter
+                .as_ref()
+                .map(|f| FilterKind::from_spec(f))
+                .transpose()
+                .map_err(|e| anyhow!(e))?,
+            mask: mask.map(Arc::new),
+            mask_scale,
+            cache,
+        })
     }
 }
 
@@ -245,30 +469,38 @@ impl From<(usize, usize, usize)> for VolumeChunk {
 #[derive(Clone)]
 struct Rendering {
     params: RenderParams,
-    obj: Arc<ObjFile>,
+    // only used to create per-tile views sharing its tile cache
+    volume: Arc<Mutex<volume::VolumeGrid64x4Mapped>>,
     download_state: Arc<Mutex<DownloadState>>,
     downloader: Arc<AsyncDownloader>,
 }
 
+struct PanicDownloader {}
+impl Downloader for PanicDownloader {
+    fn queue(&self, task: (Arc<Mutex<DS>>, usize, usize, usize, Quality)) {
+        panic!("All files should be downloaded already but got {:?}", task);
+    }
+}
+
 const TILE_SERVER: &'static str = "https://vesuvius.virtual-void.net";
 
 impl Rendering {
-    fn new(params: RenderParams, download_settings: DownloadSettings) -> Self {
-        let obj = Arc::new(ObjVolume::load_obj(
-            &params.obj_file,
-            &params.transform,
-            params.projection,
-        ));
+    fn new(params: RenderParams, download_settings: DownloadSettings) -> Result<Self> {
+        // tiles are only read after they have been downloaded
+        std::fs::create_dir_all(&download_settings.cache_dir)?;
+        let volume =
+            volume::VolumeGrid64x4Mapped::from_data_dir(&download_settings.cache_dir, Arc::new(PanicDownloader {}))?
+                .with_cache_config(params.cache);
 
-        Self {
+        Ok(Self {
             params,
-            obj,
+            volume: Arc::new(Mutex::new(volume)),
             download_state: Arc::new(Mutex::new(DownloadState::new())),
             downloader: Arc::new(AsyncDownloader {
                 semaphore: tokio::sync::Semaphore::new(download_settings.concurrent_downloads),
                 settings: download_settings,
             }),
-        }
+        })
     }
     async fn run(&self, multi: &MultiProgress) -> Result<()> {
         std::fs::create_dir_all(&self.params.target_dir)?;
@@ -318,50 +550,61 @@ impl Rendering {
                 async move {
                     let self_clone = self.clone();
                     let chunks = tokio::task::spawn_blocking(move || self_clone.chunks_for(&tile))
-                        .await
-                        .unwrap();
+                        .await?
+                        .with_context(|| format!("Failed to map tile {:?}", tile))?;
                     map_bar.inc(1);
-                    (tile, chunks)
+                    Ok::<_, anyhow::Error>((tile, chunks))
                 }
             })
             .buffered(buf_size)
-            .map(|(tile, chunks)| {
+            .map_ok(|(tile, chunks)| {
                 let download_bar = download_bar.clone();
                 async move {
-                    // FIXME: handle download error more gracefully
-                    self.download_all_chunks(chunks, download_bar).await.unwrap();
-                    tile
+                    self.download_all_chunks(chunks, download_bar)
+                        .await
+                        .with_context(|| format!("Failed to download data for tile {:?}", tile))?;
+                    Ok(tile)
                 }
             })
-            .buffered(buf_size) // needs ordering because we deduplicate downloads here
-            .map(|tile| {
+            .try_buffered(buf_size) // needs ordering because we deduplicate downloads here
+            .map_ok(|tile| {
                 let self_clone = self.clone();
                 let render_bar = render_bar.clone();
                 async move {
-                    let tile_image = tokio::task::spawn_blocking(move || self_clone.render_tile(&tile).unwrap())
-                        .await
-                        .unwrap();
+                    let tile_image = tokio::task::spawn_blocking(move || self_clone.render_tile(&tile))
+                        .await?
+                        .with_context(|| format!("Failed to render tile {:?}", tile))?;
                     render_bar.inc(1);
-                    (tile, tile_image)
+                    Ok((tile, tile_image))
                 }
             })
-            .buffered(tiles_per_layer as usize)
-            .chunks(tiles_per_layer as usize)
-            .map(|tiles| {
+            .try_buffered(tiles_per_layer as usize)
+            .try_chunks(tiles_per_layer as usize)
+            .map_err(|e| e.1)
+            .map_ok(|tiles| {
                 let self_clone = self.clone();
                 let layers_bar = layers_bar.clone();
                 async move {
-                    let res = tokio::task::spawn_blocking(move || self_clone.render_layer_from_tiles(tiles))
-                        .await
-                        .unwrap();
+                    tokio::task::spawn_blocking(move || self_clone.render_layer_from_tiles(tiles)).await??;
                     layers_bar.inc(1);
-                    res
+                    Ok(())
                 }
             })
-            .buffer_unordered(buf_size)
-            .collect::<Vec<_>>()
-            .await;
-
+            .try_buffer_unordered(buf_size)
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        if let Some(stats) = self.volume.lock().unwrap().cache_stats() {
+            println!(
+                "Tile cache: {} tiles, {} MB, {:.0}% hits ({} hits, {} misses, {} evictions)",
+                stats.entries,
+                stats.bytes >> 20,
+                stats.hit_rate() * 100.0,
+                stats.hits,
+                stats.misses,
+                stats.evictions
+            );
+        }
         Ok(())
     }
     fn uv_tiles(&self) -> Vec<UVTile> {
@@ -381,32 +624,42 @@ impl Rendering {
         }
         res
     }
-    fn chunks_for(&self, UVTile { u, v, w }: &UVTile) -> BTreeSet<VolumeChunk> {
-        let dummy = Rc::new(TileCollectingVolume::new());
+    fn chunks_for(&self, UVTile { u, v, w }: &UVTile) -> Result<BTreeSet<VolumeChunk>> {
+        let dummy = TileCollectingVolume::new();
         let width = self.params.width;
         let height = self.params.height;
-        let tile_width = self.params.tile_size;
-        let tile_height = self.params.tile_size;
-        let world = ObjVolume::new(
-            self.obj.clone(),
-            Volume::from_ref(Arc::new(dummy.as_ref().clone())),
-            width,
-            height,
-        )
-        .into_volume();
+        // CLAHE needs the area around the tile as well
+        let margin = self.params.drawing_config.clahe.margin();
+        let tile_width = self.params.tile_size + 2 * margin;
+        let tile_height = self.params.tile_size + 2 * margin;
+        // filters also need the data around the rendered voxels, masked voxels are not requested
+        let base = self
+            .params
+            .with_filter(self.params.with_mask(dummy.clone().into_volume())?);
+        let world = ObjVolume::new(self.params.obj.clone(), base, width, height).into_volume();
 
         let mut image = Image::new(tile_width, tile_height);
         let xyz = [
-            *u as i32 + tile_width as i32 / 2,
-            *v as i32 + tile_height as i32 / 2,
+            *u as i32 + self.params.tile_size as i32 / 2,
+            *v as i32 + self.params.tile_size as i32 / 2,
             *w as i32 - self.params.mid_layer as i32,
         ];
-        let mut config = DrawingConfig::default();
-        config.trilinear_interpolation = true;
-        world.paint(xyz, 0, 1, 2, tile_width, tile_height, 1, 1, &config, &mut image);
+        // use the same config as for rendering to collect all chunks needed for compositing
+        world.paint(
+            xyz,
+            0,
+            1,
+            2,
+            tile_width,
+            tile_height,
+            1,
+            1,
+            &self.params.drawing_config,
+            &mut image,
+        );
         let res = dummy.state.replace(Default::default()).requested_tiles;
         //println!("Tile: {},{} [{:?}]-> {:?}", u, v, xyz, res.len());
-        res.into_iter().map(Into::into).collect()
+        Ok(res.into_iter().map(Into::into).collect())
     }
     async fn download_all_chunks(&self, mut chunks: BTreeSet<VolumeChunk>, bar: ProgressBar) -> Result<()> {
         let dir = self.downloader.settings.cache_dir.clone();
@@ -436,13 +689,14 @@ impl Rendering {
                 let bar = bar.clone();
                 let c = self.clone();
                 async move {
-                    c.downloader.download_chunk(chunk).await.unwrap();
+                    c.downloader.download_chunk(chunk).await?;
                     bar.inc(64 * 64 * 64);
+                    Ok::<_, anyhow::Error>(())
                 }
             })
             .buffered(32)
-            .collect::<Vec<_>>()
-            .await;
+            .try_collect::<Vec<_>>()
+            .await?;
 
         Ok(())
     }
@@ -473,12 +727,15 @@ impl Rendering {
             }
         }
 
+        let name = if self.params.is_composite() {
+            "composite".to_string()
+        } else {
+            format!("{:02}", w)
+        };
+        let file_name = format!("{}/{}.{}", self.params.target_dir, name, self.params.target_format);
         image
-            .save(format!(
-                "{}/{:02}.{}",
-                self.params.target_dir, w, self.params.target_format
-            ))
-            .unwrap();
+            .save(&file_name)
+            .with_context(|| format!("Failed to save {}", file_name))?;
 
         Ok(())
     }
@@ -486,26 +743,18 @@ impl Rendering {
         let paint_width = self.params.tile_size;
         let paint_height = self.params.tile_size;
 
-        struct PanicDownloader {}
-        impl Downloader for PanicDownloader {
-            fn queue(&self, task: (Arc<Mutex<DS>>, usize, usize, usize, Quality)) {
-                panic!("All files should be downloaded already but got {:?}", task);
-            }
-        }
-        let dir = self.downloader.settings.cache_dir.clone();
-
-        let vol = volume::VolumeGrid64x4Mapped::from_data_dir(&dir, Arc::new(PanicDownloader {}));
+        let vol = (self.volume.lock().unwrap().shared())();
         let world = ObjVolume::new(
-            self.obj.clone(),
-            vol.into_volume(),
+            self.params.obj.clone(),
+            self.params.with_filter(self.params.with_mask(vol)?),
             self.params.width,
             self.params.height,
         )
         .into_volume();
-        let mut config = DrawingConfig::default();
-        config.trilinear_interpolation = true;
-
-        let mut image = Image::new(paint_width, paint_height);
+        // paint a margin around the tile, so that CLAHE context regions around the tile edges are complete
+        let clahe = &self.params.drawing_config.clahe;
+        let margin = clahe.margin();
+        let mut image = Image::new(paint_width + 2 * margin, paint_height + 2 * margin);
         world.paint(
             [
                 *u as i32 + paint_width as i32 / 2,
@@ -515,13 +764,17 @@ impl Rendering {
             0,
             1,
             2,
-            paint_width,
-            paint_height,
+            paint_width + 2 * margin,
+            paint_height + 2 * margin,
             1,
             1,
-            &config,
+            &self.params.drawing_config,
             &mut image,
         );
+        if clahe.enabled {
+            clahe.apply(&mut image);
+            image = image.crop(margin, margin, paint_width, paint_height);
+        }
         Ok(image)
     }
 }
@@ -565,12 +818,13 @@ impl Default for TileCollectingVolumeState {
 /// A VoxelVolume implementation that just collects needed tiles
 #[derive(Clone)]
 struct TileCollectingVolume {
-    state: RefCell<TileCollectingVolumeState>,
+    // shared between clones, so that tiles requested through wrapping volumes can be collected
+    state: Rc<RefCell<TileCollectingVolumeState>>,
 }
 impl TileCollectingVolume {
     fn new() -> Self {
         Self {
-            state: TileCollectingVolumeState::default().into(),
+            state: Rc::new(TileCollectingVolumeState::default().into()),
         }
     }
     fn add_tile(&self, tile: (usize, usize, usize)) {
@@ -634,6 +888,14 @@ impl DownloadState {
     }
 }
 
+/// The volume to render against, Scroll 1A if none is given
+fn volume_ref(args: &Args) -> Result<&'static dyn VolumeReference> {
+    match args.volume.clone() {
+        Some(vol_id) => vol_id.try_into().map_err(|e| anyhow!("Cannot find volume: {}", e)),
+        None => Ok(&FullVolumeReference::SCROLL1),
+    }
+}
+
 #[derive(Clone, Debug)]
 struct DownloadSettings {
     tile_server_base: String,
@@ -645,11 +907,7 @@ struct DownloadSettings {
 impl TryFrom<&Args> for DownloadSettings {
     type Error = anyhow::Error;
     fn try_from(args: &Args) -> std::result::Result<Self, Self::Error> {
-        let vol: &'static dyn VolumeReference = if let Some(vol_id) = args.volume.clone() {
-            vol_id.try_into().map_err(|e| anyhow!("Cannot find volume: {}", e))?
-        } else {
-            &FullVolumeReference::SCROLL1
-        };
+        let vol = volume_ref(args)?;
 
         let cache_dir = if let Some(dir) = args.data_directory.clone() {
             dir
@@ -706,16 +964,16 @@ impl AsyncDownloader {
                     "{}/64-4/d{:02}/z{:03}/xyz-{:03}-{:03}-{:03}-b{:03}-d{:02}.bin",
                     self.settings.cache_dir, 1, z, x, y, z, 255, 1
                 );
-                std::fs::create_dir_all(format!("{}/64-4/d{:02}/z{:03}", self.settings.cache_dir, 1, z)).unwrap();
+                std::fs::create_dir_all(format!("{}/64-4/d{:02}/z{:03}", self.settings.cache_dir, 1, z))?;
                 let tmp_file = format!("{}.tmp", file_name);
-                std::fs::write(&tmp_file, bytes).unwrap();
-                std::fs::rename(tmp_file, file_name).unwrap();
+                std::fs::write(&tmp_file, bytes).with_context(|| format!("Failed to write {}", tmp_file))?;
+                std::fs::rename(tmp_file, file_name)?;
             } else if res.status == 420 {
                 // retry in 10 seconds
                 tokio::time::sleep(std::time::Duration::from_secs(10)).await;
                 return self.download_attempt(chunk, retries - 1).await;
             } else {
-                todo!();
+                return Err(anyhow!("Failed to download {}: status {}", url, res.status));
             }
         } else {
             return Err(anyhow!("Failed to download tile"));
@@ -0,0 +1,189 @@
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::sync::Arc;
+use vesuvius_gui::volume::{
+    AffineTransform, EmptyVolume, ObjFile, ObjVolume, ProjectionKind, TifXyzSurface, VoxelPaintVolume,
+};
+
+/// Vesuvius Tools, utilities to convert and inspect segments
+#[derive(Parser, Debug)]
+#[command(about, long_about = None)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert a segment (obj, ply or tifxyz) into a PPM file with the volume coordinates and normal for every pixel
+    Obj2ppm {
+        /// Segment file to convert, either an obj or ply file or a tifxyz surface directory
+        #[clap(long)]
+        obj: String,
+
+        /// Width of the segment when converting meshes (tifxyz surfaces and flattened meshes provide their own size)
+        #[clap(long)]
+        width: Option<usize>,
+
+        /// Height of the segment when converting meshes (tifxyz surfaces and flattened meshes provide their own size)
+        #[clap(long)]
+        height: Option<usize>,
+
+        /// Compute a low-distortion UV map (LSCM) instead of using the texture coordinates of the mesh. This happens
+        /// automatically for meshes without usable texture coordinates.
+        #[clap(long)]
+        flatten: bool,
+
+        /// Project the mesh around a cylinder axis through the given center, e.g. the umbilicus of the scroll, using
+        /// angle and height as texture coordinates. Format: `x,y,z` (axis along z) or `x,y,z,dx,dy,dz`
+        #[clap(long)]
+        cylindrical: Option<String>,
+
+        /// Transform to apply to the segment (same format as for vesuvius-render)
+        #[clap(long)]
+        transform: Option<String>,
+
+        /// Invert the transform before applying it
+        #[clap(long)]
+        invert_transform: bool,
+
+        /// The PPM file to write
+        #[clap(long)]
+        output: String,
+    },
+    /// Export the intersection of a segment with an axis-aligned plane as polylines in volume coordinates
+    Intersect {
+        /// Segment file, either an obj or ply file or a tifxyz surface directory
+        #[clap(long)]
+        obj: String,
+
+        /// Normal axis of the plane
+        #[clap(long)]
+        axis: Axis,
+
+        /// Position of the plane along the axis
+        #[clap(long)]
+        position: f64,
+
+        /// Transform to apply to the segment (same format as for vesuvius-render)
+        #[clap(long)]
+        transform: Option<String>,
+
+        /// Invert the transform before applying it
+        #[clap(long)]
+        invert_transform: bool,
+
+        /// The file to write, SVG for files ending in .svg, JSON otherwise
+        #[clap(long)]
+        output: String,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+fn parse_transform(transform: Option<String>, invert_transform: bool) -> Result<Option<AffineTransform>> {
+    transform
+        .map(|t| {
+            let transform = AffineTransform::from_json_array_or_path(&t).map_err(|e| anyhow!(e.to_string()))?;
+            if invert_transform {
+                transform.invert().map_err(|e| anyhow!(e.to_string()))
+            } else {
+                Ok(transform)
+            }
+        })
+        .transpose()
+}
+
+/// Loads the mesh of a segment, keeping its own texture coordinates
+fn load_mesh(path: &str, transform: &Option<AffineTransform>) -> Result<ObjFile> {
+    if TifXyzSurface::is_tifxyz(path) {
+        Ok(TifXyzSurface::load(path)?.to_obj_file(transform, ProjectionKind::None)?)
+    } else {
+        Ok(ObjVolume::load_obj(path, transform, ProjectionKind::None)?)
+    }
+}
+
+/// Loads a segment without any volume data, e.g. to convert it into another format
+fn load_segment(
+    path: &str,
+    width: Option<usize>,
+    height: Option<usize>,
+    transform: &Option<AffineTransform>,
+    projection: ProjectionKind,
+) -> Result<ObjVolume> {
+    let base = EmptyVolume {}.into_volume();
+    if TifXyzSurface::is_tifxyz(path) {
+        let surface = TifXyzSurface::load(path)?;
+        let obj = surface.to_obj_file(transform, projection)?;
+        let [width, height] = obj.flattened_size().unwrap_or([surface.width(), surface.height()]);
+        Ok(ObjVolume::new(Arc::new(obj), base, width, height))
+    } else {
+        let obj = ObjVolume::load_obj(path, transform, projection)?;
+        let size = obj.flattened_size().map(|[w, h]| (w, h));
+        let Some((width, height)) = size.or(width.zip(height)) else {
+            return Err(anyhow!("--width and --height are required for obj and ply files"));
+        };
+        Ok(ObjVolume::new(Arc::new(obj), base, width, height))
+    }
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    match args.command {
+        Command::Obj2ppm {
+            obj,
+            width,
+            height,
+            transform,
+            invert_transform,
+            flatten,
+            cylindrical,
+            output,
+        } => {
+            let transform = parse_transform(transform, invert_transform)?;
+            let projection = if flatten {
+                ProjectionKind::Flatten
+            } else if let Some(cylinder) = &cylindrical {
+                ProjectionKind::cylindrical_from_spec(cylinder).map_err(|e| anyhow!(e))?
+            } else {
+                ProjectionKind::None
+            };
+            let segment = load_segment(&obj, width, height, &transform, projection)?;
+            println!(
+                "Writing {}x{} PPM for {} to {}",
+                segment.width(),
+                segment.height(),
+                obj,
+                output
+            );
+            segment.write_ppm(&output)?;
+        }
+        Command::Intersect {
+            obj,
+            axis,
+            position,
+            transform,
+            invert_transform,
+            output,
+        } => {
+            let transform = parse_transform(transform, invert_transform)?;
+            let mesh = load_mesh(&obj, &transform)?;
+            let intersection = mesh.plane_intersection(axis as usize, position);
+            println!(
+                "Writing {} polylines of {} at {:?} = {} to {}",
+                intersection.polylines.len(),
+                obj,
+                axis,
+                position,
+                output
+            );
+            intersection.write(&output)?;
+        }
+    }
+    Ok(())
+}
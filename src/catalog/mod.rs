@@ -1,12 +1,14 @@
 pub mod obj_repository;
 
 use crate::{
-    model::{DynamicFullVolumeReference, VolumeReference},
+    model::{volume64x4_metadata, DynamicFullVolumeReference, VolumeReference},
+    volume::VolumeMetadata,
     zstd_decompress,
 };
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "camelCase")]
@@ -51,6 +53,11 @@ pub struct VolumeInfo {
     #[serde(rename = "energykeV")]
     pub energy_keV: usize,
 }
+impl VolumeInfo {
+    pub fn metadata(&self) -> VolumeMetadata {
+        volume64x4_metadata([self.max_x, self.max_y, self.max_z], self.voxel_size_nm)
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -71,7 +78,9 @@ pub struct Segment {
 
 impl Segment {
     pub fn volume_ref(&self) -> impl VolumeReference {
-        DynamicFullVolumeReference::new(self.scroll.old_id.clone(), self.volume.as_ref().unwrap().volume.clone())
+        let volume = self.volume.as_ref().unwrap();
+        DynamicFullVolumeReference::new(self.scroll.old_id.clone(), volume.volume.clone())
+            .with_metadata(volume.metadata())
     }
 }
 
@@ -129,3 +138,31 @@ pub fn load_segments() -> Vec<Segment> {
 pub fn load_catalog() -> Catalog {
     Catalog::from_segments(load_segments())
 }
+
+/// Metadata of a 64-4 volume as recorded for the segments in the bundled catalog, None if no segment references it
+pub fn volume_metadata(volume_id: &str) -> Option<VolumeMetadata> {
+    static METADATA: OnceLock<HashMap<String, VolumeMetadata>> = OnceLock::new();
+    METADATA
+        .get_or_init(|| {
+            load_segments()
+                .iter()
+                .filter_map(|segment| segment.volume.as_ref())
+                .map(|volume| (volume.volume.clone(), volume.metadata()))
+                .collect()
+        })
+        .get(volume_id)
+        .cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn volume_metadata_from_catalog() {
+        let metadata = volume_metadata("20230205180739").unwrap();
+        assert_eq!(metadata.full_shape(), [8096, 7888, 14376]);
+        assert_eq!(metadata.voxel_size_um, Some(7.91));
+        assert!(volume_metadata("unknown").is_none());
+    }
+}
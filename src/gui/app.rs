@@ -1,10 +1,9 @@
 use crate::catalog::obj_repository::ObjRepository;
 use crate::catalog::Catalog;
-use crate::catalog::Segment;
-use crate::gui::{PaneType, VolumePane};
+use crate::catalog::{Scroll, Segment};
+use crate::gui::{PaneContent, PaneType, SegmentOutline, VolumePane};
 use crate::model::*;
 use crate::volume::*;
-use crate::zarr::ZarrArray;
 use directories::BaseDirs;
 use egui::CollapsingHeader;
 use egui::Color32;
@@ -18,12 +17,19 @@ use egui::{Response, Ui, Widget};
 use egui_extras::Column;
 use egui_extras::TableBuilder;
 use std::ops::RangeInclusive;
+use std::rc::Rc;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
 
 pub(crate) const ZOOM_MIN: f32 = 0.025;
 pub(crate) const ZOOM_MAX: f32 = 8.0;
+/// navigation ranges for volumes of unknown size
+const DEFAULT_RANGES: [RangeInclusive<i32>; 3] = [0..=20000, 0..=20000, 0..=30000];
+/// how far from a segment surface clicks in the volume panes are located on the segment, in screen pixels
+const LOCATE_RADIUS: f32 = 10.0;
+
+type UvLocator = Box<dyn Fn([f64; 3], f64) -> Option<[i32; 3]>>;
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
@@ -37,14 +43,36 @@ pub struct SegmentMode {
     ranges: [RangeInclusive<i32>; 3],
     #[serde(skip)]
     world: Volume,
-    // This is the same reference as `world`. We need to add it just because upcasting between SurfaceVolume and VoxelPaintVolume is so hard.
+    // This is the same surface as `world`. We need to add it just because upcasting between SurfaceVolume and VoxelPaintVolume is so hard.
     // TODO: remove when there's a better way to upcast
     #[serde(skip)]
-    surface_volume: Arc<dyn SurfaceVolume>,
+    surface_volume: Rc<dyn SurfaceVolume>,
     #[serde(skip)]
     uv_pane: VolumePane,
     #[serde(skip)]
     convert_to_world_coords: Box<dyn Fn([i32; 3]) -> [i32; 3]>,
+    // finds the segment coordinates of the surface point closest to a volume position within a maximum distance
+    #[serde(skip)]
+    convert_to_uv_coords: UvLocator,
+    // maps a volume layer onto the segment surface
+    #[serde(skip)]
+    surface_layer: Box<dyn Fn(Volume) -> Volume>,
+    // the app's layers mapped onto the segment surface, in the same order
+    #[serde(skip)]
+    layer_volumes: Vec<Volume>,
+    // how the segment was opened, to be able to reopen it with another projection. The requested size can differ from
+    // the actual size for flattened or projected meshes.
+    #[serde(skip)]
+    requested_size: [usize; 2],
+    #[serde(skip)]
+    transform: Option<AffineTransform>,
+    #[serde(skip)]
+    projection: ProjectionKind,
+    #[serde(skip)]
+    distortion: Option<DistortionStats>,
+    // the mesh of obj, ply and tifxyz segments, e.g. to export plane intersections
+    #[serde(skip)]
+    mesh: Option<Arc<ObjFile>>,
 }
 
 impl Default for SegmentMode {
@@ -57,15 +85,88 @@ impl Default for SegmentMode {
             height: 1000,
             ranges: [0..=1000, 0..=1000, -40..=40],
             world: EmptyVolume {}.into_volume(),
-            surface_volume: Arc::new(EmptyVolume {}),
+            surface_volume: Rc::new(EmptyVolume {}),
             uv_pane: VolumePane::new(PaneType::UV, true),
             convert_to_world_coords: Box::new(|x| x),
+            convert_to_uv_coords: Box::new(|_, _| None),
+            surface_layer: Box::new(|x| x),
+            layer_volumes: vec![],
+            requested_size: [1000, 1000],
+            transform: None,
+            projection: ProjectionKind::None,
+            distortion: None,
+            mesh: None,
         }
     }
 }
 
 enum UINotification {
     ObjDownloadReady(Segment),
+    OutlineSegmentLoaded {
+        file: String,
+        segment: Option<Segment>,
+        result: Result<(Arc<ObjFile>, [usize; 2]), String>,
+    },
+    HistogramSampled(HistogramKey, Box<Histogram>),
+}
+
+/// Colors for the outlines of additional segments, distinct from the default outline color of the active segment and
+/// the highlight color
+const OUTLINE_COLORS: [[u8; 3]; 10] = [
+    [0x00, 0xff, 0xff],
+    [0xff, 0xff, 0x00],
+    [0x00, 0xff, 0x00],
+    [0xff, 0x40, 0x40],
+    [0x40, 0x80, 0xff],
+    [0xff, 0x80, 0xc0],
+    [0x80, 0xff, 0xc0],
+    [0xc0, 0x80, 0xff],
+    [0xff, 0xff, 0xff],
+    [0x80, 0x60, 0x00],
+];
+
+/// Maximum number of catalog segments added at once by "Show outlines", each of them may need a mesh download
+const MAX_CATALOG_OUTLINES: usize = 20;
+
+/// A segment that is only shown as an outline in the volume panes, e.g. to compare neighbouring segments
+struct OutlineSegment {
+    label: String,
+    file: String,
+    /// size to use when opening the segment as the active segment
+    size: [usize; 2],
+    /// the catalog entry if the segment was loaded from the catalog
+    segment: Option<Segment>,
+    volume: Rc<dyn SurfaceVolume>,
+    color: [u8; 3],
+    visible: bool,
+}
+
+/// Loads an obj or ply mesh or a tifxyz surface together with its size. Tifxyz surfaces and flattened or projected
+/// meshes come with their own size, otherwise the given width and height are used.
+fn load_surface(
+    segment_file: &str,
+    width: usize,
+    height: usize,
+    transform: &Option<AffineTransform>,
+    projection: ProjectionKind,
+) -> Result<(ObjFile, [usize; 2]), String> {
+    if TifXyzSurface::is_tifxyz(segment_file) {
+        let surface = TifXyzSurface::load(segment_file)
+            .map_err(|e| format!("Could not open surface {}:\n{}", segment_file, e))?;
+        let obj = surface
+            .to_obj_file(transform, projection)
+            .map_err(|e| format!("Could not open surface {}:\n{}", segment_file, e))?;
+        let size = obj.flattened_size().unwrap_or([surface.width(), surface.height()]);
+        Ok((obj, size))
+    } else {
+        let obj = ObjVolume::load_obj(segment_file, transform, projection)
+            .map_err(|e| format!("Could not open segment {}:\n{}", segment_file, e))?;
+        let [width, height] = obj.flattened_size().unwrap_or([width, height]);
+        if width == 0 || height == 0 {
+            return Err(format!("Segment {} needs a width and height", segment_file));
+        }
+        Ok((obj, [width, height]))
+    }
 }
 
 pub struct ObjFileConfig {
@@ -79,8 +180,9 @@ pub struct ObjFileConfig {
 pub struct VesuviusConfig {
     pub data_dir: Option<String>,
     pub obj_file: Option<ObjFileConfig>,
-    pub overlay_dir: Option<String>,
+    pub overlay: Option<NewVolumeReference>,
     pub volume: Option<NewVolumeReference>,
+    pub cache_config: Option<CacheConfig>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
@@ -92,6 +194,15 @@ enum GuiLayout {
     UV,
 }
 
+/// Parameters a sampled histogram depends on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HistogramKey {
+    source: usize,
+    layers: usize,
+    coord: [i32; 3],
+    half_extent: i32,
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
 pub struct TemplateApp {
@@ -102,12 +213,13 @@ pub struct TemplateApp {
     zoom: f32,
     data_dir: String,
     #[serde(skip)]
+    cache_config: CacheConfig,
+    #[serde(skip)]
     world: Volume,
     #[serde(skip)]
     download_notifier: Option<Receiver<(usize, usize, usize, Quality)>>,
     drawing_config: DrawingConfig,
     sync_coordinates: bool,
-    show_overlay: bool,
     #[serde(skip)]
     ranges: [RangeInclusive<i32>; 3],
     #[serde(skip)]
@@ -127,7 +239,51 @@ pub struct TemplateApp {
     #[serde(skip)]
     notification_receiver: Receiver<UINotification>,
     #[serde(skip)]
-    overlay: Option<Box<dyn PaintVolume>>,
+    layers: Vec<VolumeLayer>,
+    #[serde(skip)]
+    new_layer_location: String,
+    #[serde(skip)]
+    new_layer_filter: FilterKind,
+    #[serde(skip)]
+    layer_error: Option<String>,
+    #[serde(skip)]
+    volume_error: Option<String>,
+    #[serde(skip)]
+    histogram_source: usize,
+    /// last sampled histogram, shown until a newer one is available
+    #[serde(skip)]
+    histogram: Option<(HistogramKey, Histogram)>,
+    /// histogram that is currently sampled in the background
+    #[serde(skip)]
+    sampling_histogram: Option<HistogramKey>,
+    /// whether new data arrived since the histogram was sampled
+    #[serde(skip)]
+    histogram_outdated: bool,
+    /// projection used when opening segments from the catalog
+    #[serde(skip)]
+    segment_projection: ProjectionKind,
+    /// segments shown as outlines in addition to the active segment
+    #[serde(skip)]
+    outline_segments: Vec<OutlineSegment>,
+    /// files of outline segments that are currently loaded in the background
+    #[serde(skip)]
+    loading_outlines: Vec<String>,
+    /// catalog segments that are downloaded to be shown as outlines
+    #[serde(skip)]
+    outline_downloads: Vec<Segment>,
+    #[serde(skip)]
+    new_outline_location: String,
+    #[serde(skip)]
+    new_outline_size: [usize; 2],
+    #[serde(skip)]
+    outline_error: Option<String>,
+    /// normal axis of the plane through the current position to export the segment intersection for
+    #[serde(skip)]
+    intersection_axis: usize,
+    #[serde(skip)]
+    intersection_file: String,
+    #[serde(skip)]
+    intersection_message: Option<String>,
     catalog_panel_open: bool,
     layout: GuiLayout,
 }
@@ -143,12 +299,12 @@ impl Default for TemplateApp {
             coord: [2800, 2500, 10852],
             zoom: 1f32,
             data_dir: ".".to_string(),
+            cache_config: CacheConfig::default(),
             world: EmptyVolume {}.into_volume(),
             download_notifier: None,
             drawing_config: Default::default(),
             sync_coordinates: true,
-            show_overlay: true,
-            ranges: [0..=20000, 0..=20000, 0..=30000],
+            ranges: DEFAULT_RANGES,
             extra_resolutions: 1,
             segment_mode: None,
             catalog,
@@ -157,7 +313,25 @@ impl Default for TemplateApp {
             downloading_segment: None,
             notification_sender,
             notification_receiver,
-            overlay: None,
+            layers: vec![],
+            new_layer_location: "".to_string(),
+            new_layer_filter: FilterKind::DEFAULTS[0],
+            layer_error: None,
+            volume_error: None,
+            histogram_source: 0,
+            histogram: None,
+            sampling_histogram: None,
+            histogram_outdated: false,
+            segment_projection: ProjectionKind::None,
+            outline_segments: vec![],
+            loading_outlines: vec![],
+            outline_downloads: vec![],
+            new_outline_location: "".to_string(),
+            new_outline_size: [1000, 1000],
+            outline_error: None,
+            intersection_axis: 2,
+            intersection_file: "intersection.svg".to_string(),
+            intersection_message: None,
             catalog_panel_open: true,
             layout: GuiLayout::Grid,
         }
@@ -187,6 +361,10 @@ impl TemplateApp {
             std::fs::create_dir_all(&app.data_dir).unwrap();
         }
 
+        if let Some(cache_config) = config.cache_config {
+            app.cache_config = cache_config;
+        }
+
         if let Some(volume) = config.volume {
             app.load_volume(&volume);
         } else {
@@ -204,25 +382,8 @@ impl TemplateApp {
             app.setup_segment(&obj_file, width, height, transform, projection);
         }
 
-        if let Some(segment_file) = config.overlay_dir {
-            if segment_file.contains(".zarr") {
-                app.overlay = Some({
-                    if segment_file.starts_with("http") {
-                        println!("Loading zarr from url: {}", segment_file);
-                        Box::new(
-                            ZarrArray::from_url_to_default_cache_dir(&segment_file)
-                                .into_ctx()
-                                .into_ctx(),
-                        )
-                        // TODO: autodetect or allow to choose whether to use ome-zarr or zarr
-                        /* Box::new(OmeZarrContext::<FourColors>::from_url_to_default_cache_dir(
-                            &segment_file,
-                        )) */
-                    } else {
-                        Box::new(ZarrArray::from_path(&segment_file).into_ctx().into_ctx())
-                    }
-                });
-            }
+        if let Some(overlay) = config.overlay {
+            app.add_layer(&overlay);
         }
 
         app
@@ -237,14 +398,21 @@ impl TemplateApp {
         projection: ProjectionKind,
     ) {
         if segment_file.ends_with(".ppm") {
+            let ppm = match PPMVolume::try_new(segment_file, self.world.clone()) {
+                Ok(ppm) => ppm,
+                Err(e) => {
+                    let e = format!("Could not open segment {}:\n{}", segment_file, e);
+                    println!("{}", e);
+                    self.volume_error = Some(e);
+                    return;
+                }
+            };
             let mut segment: SegmentMode = self.segment_mode.take().unwrap_or_default();
-            let old = self.world.clone();
-            let base = old;
-            let ppm = PPMVolume::new(segment_file, base);
             let width = ppm.width() as i32;
             let height = ppm.height() as i32;
-            let ppm = Arc::new(ppm);
+            let ppm = Rc::new(ppm);
             let ppm2 = ppm.clone();
+            let ppm3 = ppm.clone();
             println!("Loaded PPM volume with size {}x{}", width, height);
 
             if segment.filename != segment_file {
@@ -255,24 +423,41 @@ impl TemplateApp {
             segment.width = width as usize;
             segment.height = height as usize;
             segment.ranges = [0..=width, 0..=height, -40..=40];
-            segment.world = Volume::from_ref(ppm.clone());
-            //segment.surface_volume = ppm;
+            segment.world = ppm.as_ref().clone().into_volume();
+            segment.distortion = None;
+            segment.mesh = None;
+            segment.surface_volume = ppm.clone();
             segment.convert_to_world_coords = Box::new(move |coord| ppm2.convert_to_world_coords(coord));
+            segment.convert_to_uv_coords =
+                Box::new(move |xyz, max_distance| ppm3.convert_to_uv_coords(xyz, max_distance));
+            segment.surface_layer = Box::new(move |v| ppm.with_base_volume(v).into_volume());
 
             self.segment_mode = Some(segment)
-        } else if segment_file.ends_with(".obj") {
+        } else if segment_file.ends_with(".obj")
+            || segment_file.ends_with(".ply")
+            || TifXyzSurface::is_tifxyz(segment_file)
+        {
+            let base = self.world.clone();
+            let (requested_width, requested_height) = (width, height);
+            let obj_volume = match load_surface(segment_file, width, height, &transform, projection) {
+                Ok((obj, [width, height])) => ObjVolume::new(Arc::new(obj), base, width, height),
+                Err(e) => {
+                    println!("{}", e);
+                    self.volume_error = Some(e);
+                    return;
+                }
+            };
             let mut segment: SegmentMode = self.segment_mode.take().unwrap_or_default();
-            let old = self.world.clone();
-            let base = old;
-            let obj_volume = ObjVolume::load_from_obj(&segment_file, base, width, height, &transform, projection);
             let width = obj_volume.width() as i32;
             let height = obj_volume.height() as i32;
 
-            let volume = Arc::new(obj_volume);
+            let volume = Rc::new(obj_volume);
             let obj2 = volume.clone();
+            let obj3 = volume.clone();
             println!("Loaded Obj volume with size {}x{}", width, height);
 
-            if segment.filename != segment_file {
+            // the uv coordinates change with the projection
+            if segment.filename != segment_file || segment.projection != projection {
                 segment.coord = [width / 2, height / 2, 0];
                 segment.filename = segment_file.to_string();
                 segment.info = segment_file.to_string();
@@ -280,19 +465,310 @@ impl TemplateApp {
             segment.width = width as usize;
             segment.height = height as usize;
             segment.ranges = [0..=width, 0..=height, -40..=40];
-            segment.world = Volume::from_ref(volume.clone());
+            let obj = volume.obj();
+            segment.world = volume.as_ref().clone().into_volume();
+            segment.distortion = Some(volume.distortion_stats());
+            segment.mesh = Some(obj.clone());
             segment.surface_volume = volume;
             segment.convert_to_world_coords = Box::new(move |coords| obj2.convert_to_volume_coords(coords));
+            segment.convert_to_uv_coords =
+                Box::new(move |xyz, max_distance| obj3.convert_to_uv_coords(xyz, max_distance));
+            segment.surface_layer =
+                Box::new(move |v| ObjVolume::new(obj.clone(), v, width as usize, height as usize).into_volume());
+            segment.requested_size = [requested_width, requested_height];
+            segment.transform = transform;
+            segment.projection = projection;
 
             self.segment_mode = Some(segment)
         }
+        self.update_segment_layers();
+    }
+
+    fn add_layer(&mut self, volume: &NewVolumeReference) {
+        let params = VolumeCreationParams {
+            cache_dir: self.data_dir.clone(),
+            cache: self.cache_config,
+        };
+        match volume.volume(&params) {
+            Ok(v) => {
+                self.layers.push(VolumeLayer::new(volume.label(), v));
+                self.layer_error = None;
+                self.update_segment_layers();
+            }
+            Err(e) => self.layer_error = Some(format!("Could not open {}: {}", volume.label(), e)),
+        }
+    }
+
+    fn add_layer_from_location(&mut self) {
+        let location = self.new_layer_location.trim().to_string();
+        let volume = if location.starts_with("http") {
+            NewVolumeReference::from_url(location)
+        } else if let Some(spec) = location.strip_prefix("synthetic:") {
+            NewVolumeReference::from_synthetic(spec)
+        } else {
+            NewVolumeReference::from_path(location)
+        };
+        match volume {
+            Ok(volume) => {
+                self.add_layer(&volume);
+                if self.layer_error.is_none() {
+                    self.new_layer_location.clear();
+                }
+            }
+            Err(e) => self.layer_error = Some(e.to_string()),
+        }
+    }
+
+    fn update_segment_layers(&mut self) {
+        if let Some(segment_mode) = self.segment_mode.as_mut() {
+            segment_mode.layer_volumes = self
+                .layers
+                .iter()
+                .map(|l| (segment_mode.surface_layer)(l.volume.clone()))
+                .collect();
+        }
+    }
+
+    /// Loads a segment to show its outline next to the active segment. Meshes and tifxyz surfaces are loaded in the
+    /// background.
+    fn add_outline_segment(&mut self, file: &str, size: [usize; 2], segment: Option<Segment>) {
+        if self.loading_outlines.iter().any(|f| f == file) || self.outline_segments.iter().any(|s| s.file == file) {
+            return;
+        }
+        if file.ends_with(".ppm") {
+            let ppm = match PPMVolume::try_new(file, EmptyVolume {}.into_volume()) {
+                Ok(ppm) => ppm,
+                Err(e) => {
+                    self.outline_error = Some(format!("Could not open segment {}:\n{}", file, e));
+                    return;
+                }
+            };
+            let size = [ppm.width(), ppm.height()];
+            self.push_outline_segment(file, size, segment, Rc::new(ppm));
+        } else if file.ends_with(".obj") || file.ends_with(".ply") || TifXyzSurface::is_tifxyz(file) {
+            self.loading_outlines.push(file.to_string());
+            let sender = self.notification_sender.clone();
+            let file = file.to_string();
+            let projection = self.segment_projection;
+            std::thread::spawn(move || {
+                let result =
+                    load_surface(&file, size[0], size[1], &None, projection).map(|(obj, size)| (Arc::new(obj), size));
+                let _ = sender.send(UINotification::OutlineSegmentLoaded { file, segment, result });
+            });
+        } else {
+            self.outline_error = Some(format!("Unsupported segment format: {}", file));
+        }
+    }
+
+    fn push_outline_segment(
+        &mut self,
+        file: &str,
+        size: [usize; 2],
+        segment: Option<Segment>,
+        volume: Rc<dyn SurfaceVolume>,
+    ) {
+        // prefer colors that are not in use yet
+        let color = OUTLINE_COLORS
+            .into_iter()
+            .find(|c| !self.outline_segments.iter().any(|s| s.color == *c))
+            .unwrap_or(OUTLINE_COLORS[self.outline_segments.len() % OUTLINE_COLORS.len()]);
+        let label = match &segment {
+            Some(segment) => segment.id.clone(),
+            None => std::path::Path::new(file)
+                .file_name()
+                .map_or(file.to_string(), |n| n.to_string_lossy().to_string()),
+        };
+        self.outline_segments.push(OutlineSegment {
+            label,
+            file: file.to_string(),
+            size,
+            segment,
+            volume,
+            color,
+            visible: true,
+        });
+        self.outline_error = None;
+    }
+
+    /// Shows the outline of a catalog segment, downloading it first if necessary
+    fn add_catalog_outline(&mut self, segment: &Segment) {
+        if self.outline_downloads.contains(segment) {
+            return;
+        }
+        let size = [segment.width, segment.height];
+        if let Some(obj_file) = self.obj_repository.get(segment) {
+            self.add_outline_segment(obj_file.to_str().unwrap(), size, Some(segment.clone()));
+        } else {
+            let sender = self.notification_sender.clone();
+            self.outline_downloads.push(segment.clone());
+            self.obj_repository.download(segment, move |segment| {
+                let _ = sender.send(UINotification::ObjDownloadReady(segment));
+            });
+        }
+    }
+
+    /// Adds outlines for the segments of a scroll that cross the current z plane
+    fn add_catalog_outlines(&mut self, scroll: &Scroll) {
+        let z = self.coord[2] as usize;
+        let segments = self
+            .catalog
+            .segments(scroll)
+            .filter(|s| matches!((s.min_z, s.max_z), (Some(min), Some(max)) if (min..=max).contains(&z)))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.outline_error = if segments.is_empty() {
+            Some(format!(
+                "No segments of {} with known bounds cross z = {}",
+                scroll.label(),
+                z
+            ))
+        } else if segments.len() > MAX_CATALOG_OUTLINES {
+            Some(format!(
+                "Showing {} of {} segments crossing z = {}",
+                MAX_CATALOG_OUTLINES,
+                segments.len(),
+                z
+            ))
+        } else {
+            None
+        };
+        for segment in segments.iter().take(MAX_CATALOG_OUTLINES) {
+            self.add_catalog_outline(segment);
+        }
+    }
+
+    /// Exports the intersection of the active segment with one of the planes through the current position
+    fn export_intersection_ui(&mut self, ui: &mut Ui, mesh: &ObjFile) {
+        const PLANES: [&str; 3] = ["YZ", "XZ", "XY"];
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("Intersection Plane")
+                .selected_text(PLANES[self.intersection_axis])
+                .width(50.0)
+                .show_ui(ui, |ui| {
+                    for (axis, label) in PLANES.iter().enumerate() {
+                        ui.selectable_value(&mut self.intersection_axis, axis, *label);
+                    }
+                });
+            ui.text_edit_singleline(&mut self.intersection_file)
+                .on_hover_text("SVG file, or JSON for other extensions");
+            if ui
+                .button("Export intersection")
+                .on_hover_text("Exports where the active segment crosses the plane through the current position")
+                .clicked()
+            {
+                let axis = self.intersection_axis;
+                let intersection = mesh.plane_intersection(axis, self.coord[axis] as f64);
+                self.intersection_message = Some(match intersection.write(&self.intersection_file) {
+                    Ok(()) => format!(
+                        "Wrote {} polylines to {}",
+                        intersection.polylines.len(),
+                        self.intersection_file
+                    ),
+                    Err(e) => format!("Could not write {}: {}", self.intersection_file, e),
+                });
+            }
+        });
+        if let Some(message) = &self.intersection_message {
+            ui.label(message);
+        }
+    }
+
+    /// Legend of the active and the additional segments shown as outlines
+    fn segments_panel(&mut self, ui: &mut Ui) {
+        if let Some(segment) = &self.segment_mode {
+            ui.horizontal(|ui| {
+                ui.color_edit_button_srgb(&mut self.drawing_config.segment_outline_color);
+                ui.label(RichText::new(&segment.info).strong())
+                    .on_hover_text("Active segment");
+            });
+        }
+        if let Some(mesh) = self.segment_mode.as_ref().and_then(|s| s.mesh.clone()) {
+            self.export_intersection_ui(ui, &mesh);
+        }
+        let mut open = None;
+        let mut remove = None;
+        for (i, outline) in self.outline_segments.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.color_edit_button_srgb(&mut outline.color);
+                ui.checkbox(&mut outline.visible, &outline.label)
+                    .on_hover_text(&outline.file);
+                if ui
+                    .small_button("Open")
+                    .on_hover_text("Make this the active segment")
+                    .clicked()
+                {
+                    open = Some(i);
+                }
+                if ui.small_button("🗑").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = open {
+            let outline = &self.outline_segments[i];
+            let (file, [width, height], segment) = (outline.file.clone(), outline.size, outline.segment.clone());
+            if let Some(segment) = &segment {
+                self.load_volume_by_ref(&segment.volume_ref());
+            }
+            self.setup_segment(&file, width, height, None, self.segment_projection);
+            self.selected_segment = segment;
+        }
+        if let Some(i) = remove {
+            self.outline_segments.remove(i);
+        }
+        let loading = self.loading_outlines.len() + self.outline_downloads.len();
+        if loading > 0 {
+            ui.label(format!("Loading {} segments...", loading));
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_outline_location)
+                .on_hover_text("Path to an obj, ply or ppm file or a tifxyz directory");
+            ui.add(egui::DragValue::new(&mut self.new_outline_size[0]).prefix("w "))
+                .on_hover_text("Width of meshes without their own size");
+            ui.add(egui::DragValue::new(&mut self.new_outline_size[1]).prefix("h "))
+                .on_hover_text("Height of meshes without their own size");
+            if ui.button("Add outline").clicked() {
+                let file = self.new_outline_location.trim().to_string();
+                self.add_outline_segment(&file, self.new_outline_size, None);
+                if self.outline_error.is_none() {
+                    self.new_outline_location.clear();
+                }
+            }
+        });
+        if !self.outline_segments.is_empty() && ui.button("Remove all outlines").clicked() {
+            self.outline_segments.clear();
+        }
+        if let Some(error) = &self.outline_error {
+            ui.colored_label(Color32::RED, error);
+        }
     }
 
     fn load_volume(&mut self, volume: &NewVolumeReference) {
         let params = VolumeCreationParams {
             cache_dir: self.data_dir.clone(),
+            cache: self.cache_config,
         };
-        self.world = volume.volume(&params);
+        self.world = match volume.volume(&params) {
+            Ok(world) => world,
+            Err(e) => {
+                println!("Could not open volume {}: {}", volume.label(), e);
+                // keep showing the previous volume
+                self.volume_error = Some(format!("Could not open volume {}:\n{}", volume.label(), e));
+                return;
+            }
+        };
+
+        // restrict navigation to the volume extent if it is known
+        if let Some(metadata) = self.world.metadata() {
+            let shape = metadata.full_shape();
+            for ((range, coord), size) in self.ranges.iter_mut().zip(self.coord.iter_mut()).zip(shape) {
+                *range = 0..=(size as i32 - 1).max(0);
+                *coord = (*coord).clamp(*range.start(), *range.end());
+            }
+        } else {
+            self.ranges = DEFAULT_RANGES;
+        }
     }
 
     fn load_volume_by_ref(&mut self, volume_ref: &dyn VolumeReference) {
@@ -328,6 +804,21 @@ impl TemplateApp {
             }
         }
     }
+    /// Moves the segment pane to the surface point closest to a position clicked in one of the volume panes
+    fn locate_on_segment(&mut self, xyz: [f64; 3]) {
+        let Some(segment_mode) = self.segment_mode.as_mut() else {
+            return;
+        };
+        let max_distance = (LOCATE_RADIUS / self.zoom) as f64;
+        if let Some(uvw) = (segment_mode.convert_to_uv_coords)(xyz, max_distance) {
+            for ((c, v), range) in segment_mode.coord.iter_mut().zip(uvw).zip(segment_mode.ranges.iter()) {
+                *c = v.clamp(*range.start(), *range.end());
+            }
+            if self.should_sync_coords() {
+                self.sync_coords();
+            }
+        }
+    }
     fn should_sync_coords(&self) -> bool {
         self.segment_mode.is_some() && self.sync_coordinates
     }
@@ -445,10 +936,6 @@ impl TemplateApp {
                     res
                 }
 
-                if self.overlay.is_some() {
-                    has_changed = has_changed || cb(ui, "Show overlay ('L')", &mut self.show_overlay).changed();
-                }
-
                 if self.is_segment_mode() {
                     let c = cb(
                         ui,
@@ -480,6 +967,22 @@ impl TemplateApp {
 
                     cb(ui, "XYZ outline ('X')", &mut self.drawing_config.draw_xyz_outlines);
 
+                    if let Some(stats) = self.segment_mode.as_ref().and_then(|s| s.distortion) {
+                        cb(ui, "Distortion overlay", &mut self.drawing_config.show_distortion);
+                        ui.label("Distortion");
+                        ui.label(format!(
+                            "stretch mean {:.2} max {:.2}, angle mean {:.2}",
+                            stats.mean_stretch, stats.max_stretch, stats.mean_angle
+                        ));
+                        ui.end_row();
+                        ui.label("Area");
+                        match self.world.metadata().and_then(|m| m.voxel_size_um) {
+                            Some(voxel_size) => ui.label(format!("{:.2} cm²", stats.area_cm2(voxel_size))),
+                            None => ui.label(format!("{:.0} voxels²", stats.area_voxels)),
+                        };
+                        ui.end_row();
+                    }
+
                     let mut header = CollapsingHeader::new("Compositing");
                     if self.drawing_config.compositing.mode != CompositingMode::None {
                         header = header.open(Some(true));
@@ -520,6 +1023,17 @@ impl TemplateApp {
                             true,
                         );
 
+                        if self.drawing_config.compositing.mode == CompositingMode::Percentile {
+                            slider(
+                                ui,
+                                "Percentile",
+                                &mut self.drawing_config.compositing.percentile,
+                                0..=100,
+                                false,
+                                true,
+                            );
+                        }
+
                         if self.drawing_config.compositing.mode == CompositingMode::Alpha
                             || self.drawing_config.compositing.mode == CompositingMode::AlphaHeightMap
                         {
@@ -562,6 +1076,36 @@ impl TemplateApp {
                             );
                         }
                     });
+
+                    let mut header = CollapsingHeader::new("Local Contrast (CLAHE)");
+                    if self.drawing_config.clahe.enabled {
+                        header = header.open(Some(true));
+                    }
+                    header.show(ui, |ui| {
+                        cb(ui, "Enable", &mut self.drawing_config.clahe.enabled);
+                        slider(
+                            ui,
+                            "Clip Limit",
+                            &mut self.drawing_config.clahe.clip_limit,
+                            1..=16,
+                            false,
+                            true,
+                        );
+                        ui.horizontal(|ui| {
+                            ui.label("Region Size");
+                            egui::ComboBox::from_id_salt("CLAHE Region Size")
+                                .selected_text(format!("{}", self.drawing_config.clahe.region_size))
+                                .show_ui(ui, |ui| {
+                                    for size in ClaheSettings::REGION_SIZES {
+                                        ui.selectable_value(
+                                            &mut self.drawing_config.clahe.region_size,
+                                            size,
+                                            format!("{}", size),
+                                        );
+                                    }
+                                });
+                        });
+                    });
                 }
 
                 if has_changed {
@@ -607,6 +1151,258 @@ impl TemplateApp {
                     });
             });
         });
+
+        let mut header = CollapsingHeader::new("Slab Projection");
+        if self.drawing_config.slab.mode != SlabMode::None {
+            header = header.open(Some(true));
+        }
+        header.show(ui, |ui| {
+            egui::Grid::new("slab_grid")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .show(ui, |ui| {
+                    ui.label("Mode");
+                    egui::ComboBox::from_id_salt("Slab Mode")
+                        .selected_text(self.drawing_config.slab.mode.label())
+                        .show_ui(ui, |ui| {
+                            for mode in SlabMode::VALUES {
+                                ui.selectable_value(&mut self.drawing_config.slab.mode, mode, mode.label());
+                            }
+                        });
+                    ui.end_row();
+                    slider(
+                        ui,
+                        "Slices In Front",
+                        &mut self.drawing_config.slab.slices_in_front,
+                        0..=100,
+                        false,
+                        true,
+                    );
+                    slider(
+                        ui,
+                        "Slices Behind",
+                        &mut self.drawing_config.slab.slices_behind,
+                        0..=100,
+                        false,
+                        true,
+                    );
+                });
+        });
+
+        ui.collapsing("Histogram", |ui| self.histogram_panel(ui));
+
+        ui.collapsing("Segments", |ui| self.segments_panel(ui));
+
+        ui.collapsing("Layers", |ui| {
+            let mut remove = None;
+            for (i, layer) in self.layers.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut layer.settings.visible, &layer.label);
+                    if ui.small_button("🗑").clicked() {
+                        remove = Some(i);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_salt(("Layer Colormap", i))
+                        .selected_text(layer.settings.colormap.label())
+                        .show_ui(ui, |ui| {
+                            for colormap in Colormap::VALUES {
+                                ui.selectable_value(&mut layer.settings.colormap, colormap, colormap.label());
+                            }
+                        });
+                    egui::ComboBox::from_id_salt(("Layer Blend Mode", i))
+                        .selected_text(layer.settings.blend_mode.label())
+                        .show_ui(ui, |ui| {
+                            for mode in BlendMode::VALUES {
+                                ui.selectable_value(&mut layer.settings.blend_mode, mode, mode.label());
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut layer.settings.opacity, 0..=255).text("Opacity"));
+                });
+                ui.separator();
+            }
+            if let Some(i) = remove {
+                self.layers.remove(i);
+                self.update_segment_layers();
+            }
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.new_layer_location).on_hover_text(
+                    "Path or URL to a zarr/ome-zarr volume, a directory of cells/layers, or a tif/nrrd/raw file, or synthetic:<pattern>",
+                );
+                if ui.button("Add layer").clicked() {
+                    self.add_layer_from_location();
+                }
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_salt("Layer Filter")
+                    .selected_text(self.new_layer_filter.name())
+                    .show_ui(ui, |ui| {
+                        for filter in FilterKind::DEFAULTS {
+                            if ui
+                                .selectable_label(self.new_layer_filter.name() == filter.name(), filter.name())
+                                .clicked()
+                            {
+                                self.new_layer_filter = filter;
+                            }
+                        }
+                    });
+                match &mut self.new_layer_filter {
+                    FilterKind::Gaussian { sigma } => {
+                        ui.add(egui::DragValue::new(sigma).range(FilterKind::SIGMA_RANGE).speed(0.1).prefix("σ "));
+                    }
+                    FilterKind::Median { radius } => {
+                        ui.add(egui::DragValue::new(radius).range(FilterKind::RADIUS_RANGE).prefix("r "));
+                    }
+                    FilterKind::GradientMagnitude => {}
+                }
+                if ui
+                    .button("Add filtered layer")
+                    .on_hover_text("Adds the main volume with the selected filter applied as a layer")
+                    .clicked()
+                {
+                    let volume = FilteredVolume::new(self.world.clone(), self.new_layer_filter)
+                        .with_cache_config(self.cache_config)
+                        .into_volume();
+                    self.layers
+                        .push(VolumeLayer::new(format!("Volume ({})", self.new_layer_filter.label()), volume));
+                    self.update_segment_layers();
+                }
+            });
+            if let Some(error) = &self.layer_error {
+                ui.colored_label(Color32::RED, error);
+            }
+        });
+    }
+
+    /// Histogram of the main volume or a layer sampled around the current position, with automatic windowing.
+    fn histogram_panel(&mut self, ui: &mut Ui) {
+        if self.histogram_source > self.layers.len() {
+            self.histogram_source = 0;
+        }
+        let source_label = |i: usize| {
+            if i == 0 {
+                "Volume".to_string()
+            } else {
+                self.layers[i - 1].label.clone()
+            }
+        };
+        egui::ComboBox::from_id_salt("Histogram Source")
+            .selected_text(source_label(self.histogram_source))
+            .show_ui(ui, |ui| {
+                for i in 0..=self.layers.len() {
+                    ui.selectable_value(&mut self.histogram_source, i, source_label(i));
+                }
+            });
+
+        // sample roughly the area visible in the panes
+        let half_extent = (256.0 / self.zoom).clamp(16.0, 8192.0) as i32;
+        let step = 2 * half_extent / Histogram::SAMPLES_PER_AXIS;
+        let downsampling = (1..=4).map(|l| 1 << l).take_while(|ds| *ds <= step).last().unwrap_or(1);
+        let key = HistogramKey {
+            source: self.histogram_source,
+            layers: self.layers.len(),
+            coord: self.coord,
+            half_extent,
+        };
+        if ui.button("Refresh").clicked() {
+            self.histogram_outdated = true;
+        }
+        let up_to_date = self.histogram.as_ref().is_some_and(|(k, _)| *k == key) && !self.histogram_outdated;
+        if !up_to_date && self.sampling_histogram.is_none() {
+            // sampling may need to wait for data, so it is done in the background
+            let volume = if self.histogram_source == 0 {
+                self.world.shared()
+            } else {
+                self.layers[self.histogram_source - 1].volume.shared()
+            };
+            let coord = self.coord;
+            let sender = self.notification_sender.clone();
+            let ctx = ui.ctx().clone();
+            std::thread::spawn(move || {
+                let histogram = Histogram::sample(&volume(), coord, half_extent, downsampling);
+                let _ = sender.send(UINotification::HistogramSampled(key, Box::new(histogram)));
+                ctx.request_repaint();
+            });
+            self.sampling_histogram = Some(key);
+            self.histogram_outdated = false;
+        }
+        let Some((_, histogram)) = &self.histogram else {
+            ui.label("Sampling...");
+            return;
+        };
+
+        let window = if self.histogram_source == 0 {
+            self.drawing_config.enable_filters.then_some([
+                self.drawing_config.threshold_min,
+                255 - self.drawing_config.threshold_max,
+            ])
+        } else {
+            Some(self.layers[self.histogram_source - 1].settings.window)
+        };
+
+        let (rect, _) = ui.allocate_exact_size(Vec2::new(256.0, 80.0), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_gray(20));
+        let max = histogram.bins.iter().skip(1).max().copied().unwrap_or(0).max(1) as f32;
+        for (v, count) in histogram.bins.iter().enumerate().skip(1) {
+            // log scale to make small bins visible
+            let h = (1.0 + *count as f32).ln() / (1.0 + max).ln() * rect.height();
+            let x = rect.left() + v as f32 * rect.width() / 256.0;
+            painter.line_segment(
+                [egui::pos2(x, rect.bottom()), egui::pos2(x, rect.bottom() - h)],
+                Stroke::new(1.0, Color32::LIGHT_GRAY),
+            );
+        }
+        if let Some(window) = window {
+            for v in window {
+                let x = rect.left() + v as f32 * rect.width() / 256.0;
+                painter.line_segment(
+                    [egui::pos2(x, rect.top()), egui::pos2(x, rect.bottom())],
+                    Stroke::new(1.0, Color32::YELLOW),
+                );
+            }
+        }
+        if histogram.total == 0 {
+            ui.label("No data at the current position");
+        } else {
+            ui.label(format!(
+                "{} samples, median {}, 0.5%: {}, 99.5%: {}",
+                histogram.total,
+                histogram.percentile(50.0),
+                histogram.percentile(0.5),
+                histogram.percentile(99.5)
+            ));
+        }
+        if histogram.missing > 0 {
+            ui.label(format!("{} samples still loading", histogram.missing));
+        }
+
+        ui.horizontal(|ui| {
+            let auto_window = histogram.auto_window(0.5, 99.5);
+            if ui
+                .add_enabled(auto_window.is_some(), egui::Button::new("Auto window"))
+                .on_hover_text("Stretch the values between the 0.5% and 99.5% percentiles to the full range")
+                .clicked()
+            {
+                let [min, max] = auto_window.unwrap();
+                if self.histogram_source == 0 {
+                    self.drawing_config.enable_filters = true;
+                    self.drawing_config.threshold_min = min;
+                    self.drawing_config.threshold_max = 255 - max;
+                } else {
+                    self.layers[self.histogram_source - 1].settings.window = [min, max];
+                }
+            }
+            if ui.button("Reset window").clicked() {
+                if self.histogram_source == 0 {
+                    self.drawing_config.threshold_min = 0;
+                    self.drawing_config.threshold_max = 0;
+                } else {
+                    self.layers[self.histogram_source - 1].settings.window = [0, 255];
+                }
+            }
+        });
     }
 
     fn try_recv_from_download_notifier(&mut self) -> bool {
@@ -618,18 +1414,44 @@ impl TemplateApp {
 
         if self.try_recv_from_download_notifier() {
             while self.try_recv_from_download_notifier() {} // clear queue
+            if self.histogram.as_ref().is_some_and(|(_, h)| h.missing > 0) {
+                self.histogram_outdated = true;
+            }
         }
 
         let mut switch_segment = None;
-        for n in self.notification_receiver.try_iter() {
+        let notifications = self.notification_receiver.try_iter().collect::<Vec<_>>();
+        for n in notifications {
             match n {
                 UINotification::ObjDownloadReady(segment) => {
                     if let Some(obj_file) = self.obj_repository.get(&segment) {
+                        if let Some(i) = self.outline_downloads.iter().position(|s| s == &segment) {
+                            self.outline_downloads.remove(i);
+                            let file = obj_file.to_str().unwrap().to_string();
+                            self.add_outline_segment(&file, [segment.width, segment.height], Some(segment.clone()));
+                        }
                         if self.downloading_segment.as_ref().map_or(false, |s| s == &segment) {
                             switch_segment = Some((segment, obj_file));
                         }
                     }
                 }
+                UINotification::OutlineSegmentLoaded { file, segment, result } => {
+                    self.loading_outlines.retain(|f| f != &file);
+                    match result {
+                        Ok((obj, [width, height])) => {
+                            let volume = ObjVolume::new(obj, EmptyVolume {}.into_volume(), width, height);
+                            self.push_outline_segment(&file, [width, height], segment, Rc::new(volume));
+                        }
+                        Err(e) => {
+                            println!("{}", e);
+                            self.outline_error = Some(e);
+                        }
+                    }
+                }
+                UINotification::HistogramSampled(key, histogram) => {
+                    self.sampling_histogram = None;
+                    self.histogram = Some((key, *histogram));
+                }
             }
         }
         if let Some((segment, obj_file)) = switch_segment {
@@ -639,7 +1461,7 @@ impl TemplateApp {
                 segment.width,
                 segment.height,
                 None,
-                ProjectionKind::None,
+                self.segment_projection,
             );
             self.selected_segment = Some(segment);
             self.downloading_segment = None;
@@ -649,13 +1471,31 @@ impl TemplateApp {
             self.catalog_panel(ctx);
         }
 
+        if let Some(error) = &self.volume_error {
+            let mut close = false;
+            egui::Window::new("Error")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, Vec2::ZERO)
+                .show(ctx, |ui| {
+                    ui.colored_label(Color32::RED, error);
+                    if ui.button("OK").clicked() {
+                        close = true;
+                    }
+                });
+            if close {
+                self.volume_error = None;
+            }
+        }
+
         if !ctx.wants_keyboard_input() {
             ctx.input(|i| {
                 if i.key_pressed(egui::Key::F) {
                     self.drawing_config.enable_filters = !self.drawing_config.enable_filters;
                 }
-                if self.overlay.is_some() && i.key_pressed(egui::Key::L) {
-                    self.show_overlay = !self.show_overlay;
+                if !self.layers.is_empty() && i.key_pressed(egui::Key::L) {
+                    let visible = !self.layers.iter().any(|l| l.settings.visible);
+                    self.layers.iter_mut().for_each(|l| l.settings.visible = visible);
                 }
                 if i.key_pressed(egui::Key::C) {
                     self.catalog_panel_open = !self.catalog_panel_open;
@@ -743,6 +1583,25 @@ impl TemplateApp {
                     1.0 / (_frame.info().cpu_usage.unwrap_or_default() + 1e-6)
                 ));
             });
+            if let Some(stats) = self.world.cache_stats() {
+                ui.horizontal(|ui| {
+                    ui.label("Cache");
+                    let max = stats
+                        .config
+                        .map_or("".to_string(), |c| format!(" / {}", c.max_bytes >> 20));
+                    ui.label(format!(
+                        "{} entries, {}{} MB, {:.0}% hits",
+                        stats.entries,
+                        stats.bytes >> 20,
+                        max,
+                        stats.hit_rate() * 100.0
+                    ))
+                    .on_hover_text(format!(
+                        "{} hits, {} misses, {} evictions",
+                        stats.hits, stats.misses, stats.evictions
+                    ));
+                });
+            }
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
@@ -804,36 +1663,131 @@ impl TemplateApp {
             None
         };
 
-        pane.render(
+        // the active segment is painted last to stay on top
+        let active_file = self.segment_mode.as_ref().map(|s| s.filename.as_str());
+        let mut outlines = self
+            .outline_segments
+            .iter()
+            .filter(|s| s.visible && Some(s.file.as_str()) != active_file)
+            .map(|s| SegmentOutline {
+                volume: s.volume.clone(),
+                color: s.color,
+                highlight: None,
+            })
+            .collect::<Vec<_>>();
+        if let Some(segment) = self.segment_mode.as_ref() {
+            outlines.push(SegmentOutline {
+                volume: segment.surface_volume.clone(),
+                color: self.drawing_config.segment_outline_color,
+                highlight: segment_outlines_coord,
+            });
+        }
+
+        let response = pane.render(
             ui,
             &mut self.coord,
-            &self.world,
-            self.segment_mode.as_ref().map(|s| s.surface_volume.clone()),
             &mut self.zoom,
-            &self.drawing_config,
-            self.extra_resolutions,
-            segment_outlines_coord,
             &self.ranges,
             cell_size,
+            &PaneContent {
+                world: &self.world,
+                layers: &self.layers,
+                outlines: &outlines,
+                drawing_config: &self.drawing_config,
+                extra_resolutions: self.extra_resolutions,
+                segment_outlines_coord,
+            },
         );
+        if let Some(xyz) = response.clicked {
+            self.locate_on_segment(xyz);
+        }
     }
     fn render_uv_pane(&mut self, ui: &mut Ui, cell_size: Vec2) {
         if let Some(segment_mode) = self.segment_mode.as_mut() {
-            if Self::UV_PANE.render(
-                ui,
-                &mut segment_mode.coord,
-                &segment_mode.world,
-                None,
-                &mut self.zoom,
-                &self.drawing_config,
-                self.extra_resolutions,
-                None,
-                &segment_mode.ranges,
-                cell_size,
-            ) {
-                if self.should_sync_coords() {
-                    self.sync_coords();
+            let layers = self
+                .layers
+                .iter()
+                .zip(segment_mode.layer_volumes.iter())
+                .map(|(l, volume)| VolumeLayer {
+                    volume: volume.clone(),
+                    ..l.clone()
+                })
+                .collect::<Vec<_>>();
+            if Self::UV_PANE
+                .render(
+                    ui,
+                    &mut segment_mode.coord,
+                    &mut self.zoom,
+                    &segment_mode.ranges,
+                    cell_size,
+                    &PaneContent {
+                        world: &segment_mode.world,
+                        layers: &layers,
+                        outlines: &[],
+                        drawing_config: &self.drawing_config,
+                        extra_resolutions: self.extra_resolutions,
+                        segment_outlines_coord: None,
+                    },
+                )
+                .changed
+                && self.should_sync_coords()
+            {
+                self.sync_coords();
+            }
+        }
+    }
+
+    /// Selects how the texture coordinates of segments are computed when opening them
+    fn projection_ui(&mut self, ui: &mut egui::Ui) {
+        let cylindrical = ProjectionKind::Cylindrical {
+            center: self.coord.map(|c| c as f64),
+            axis: [0.0, 0.0, 1.0],
+        };
+        ui.horizontal(|ui| {
+            ui.label("Projection");
+            egui::ComboBox::from_id_salt("Segment Projection")
+                .selected_text(self.segment_projection.label())
+                .show_ui(ui, |ui| {
+                    for projection in [
+                        ProjectionKind::None,
+                        ProjectionKind::Flatten,
+                        ProjectionKind::OrthographicXZ,
+                    ] {
+                        ui.selectable_value(&mut self.segment_projection, projection, projection.label());
+                    }
+                    let is_cylindrical = matches!(self.segment_projection, ProjectionKind::Cylindrical { .. });
+                    if ui.selectable_label(is_cylindrical, cylindrical.label()).clicked() && !is_cylindrical {
+                        self.segment_projection = cylindrical;
+                    }
+                });
+        });
+        if let ProjectionKind::Cylindrical { center, axis } = &mut self.segment_projection {
+            ui.horizontal(|ui| {
+                ui.label("Center");
+                for c in center.iter_mut() {
+                    ui.add(egui::DragValue::new(c).speed(1.0));
+                }
+                if ui.button("Use current position").clicked() {
+                    *center = self.coord.map(|c| c as f64);
                 }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Axis");
+                for c in axis.iter_mut() {
+                    ui.add(egui::DragValue::new(c).speed(0.01).range(-1.0..=1.0));
+                }
+            });
+        }
+
+        let reopen = self
+            .segment_mode
+            .as_ref()
+            .filter(|s| !s.filename.ends_with(".ppm") && s.projection != self.segment_projection);
+        if let Some(segment) = reopen {
+            let [width, height] = segment.requested_size;
+            let (file, transform) = (segment.filename.clone(), segment.transform.clone());
+            if ui.button("Reopen segment with projection").clicked() {
+                self.setup_segment(&file, width, height, transform, self.segment_projection);
             }
         }
     }
@@ -850,11 +1804,21 @@ impl TemplateApp {
                 ui.heading("📜 Catalog");
             });
             ui.separator();
+            self.projection_ui(ui);
+            ui.separator();
 
             {
                 let mut clicked = None;
+                let mut show_outlines = None;
                 self.catalog.scrolls().iter().for_each(|scroll| {
                     egui::CollapsingHeader::new(scroll.label()).show(ui, |ui| {
+                        if ui
+                            .button("Show outlines")
+                            .on_hover_text("Draws the outlines of the segments of this scroll that cross the current z plane in the volume panes, downloading missing ones")
+                            .clicked()
+                        {
+                            show_outlines = Some(scroll.clone());
+                        }
                         let mut table = TableBuilder::new(ui)
                             .vscroll(true)
                             .column(Column::auto())
@@ -935,10 +1899,13 @@ impl TemplateApp {
                             });
                     });
                 });
+                if let Some(scroll) = show_outlines {
+                    self.add_catalog_outlines(&scroll);
+                }
                 if let Some(segment) = clicked {
                     if let Some(obj_file) = self.obj_repository.get(&segment) {
                         self.load_volume_by_ref(&segment.volume_ref());
-                        self.setup_segment(&obj_file.to_str().unwrap().to_string(), segment.width, segment.height, None, ProjectionKind::None);
+                        self.setup_segment(obj_file.to_str().unwrap(), segment.width, segment.height, None, self.segment_projection);
                         self.selected_segment = Some(segment);
                     } else {
                         let sender = self.notification_sender.clone();
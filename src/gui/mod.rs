@@ -2,4 +2,4 @@ mod app;
 mod volume_pane;
 
 pub use app::{ObjFileConfig, TemplateApp, VesuviusConfig};
-pub use volume_pane::{PaneType, VolumePane};
+pub use volume_pane::{PaneContent, PaneResponse, PaneType, SegmentOutline, VolumePane};
@@ -1,9 +1,13 @@
 use crate::gui::app::{ZOOM_MAX, ZOOM_MIN};
-use crate::volume::{DrawingConfig, PaintVolume, SurfaceVolume, Volume, VoxelVolume};
+use crate::volume::{
+    paint_slab, DrawingConfig, Image, LayerSettings, PaintParams, PaintVolume, SurfaceVolume, Volume, VolumeLayer,
+    VoxelVolume,
+};
 use egui::cache::FramePublisher;
 use egui::{Color32, ColorImage, PointerButton, Response, Ui, Vec2};
 use std::ops::RangeInclusive;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
@@ -23,6 +27,8 @@ struct TileCacheKey {
     segment_outlines_coord: Option<[i32; 3]>,
     extra_resolutions: u32,
     volume_id: usize,
+    /// ids and settings of visible layers
+    layers: Vec<(usize, LayerSettings)>,
 }
 
 impl TileCacheKey {
@@ -33,12 +39,15 @@ impl TileCacheKey {
         w: i32,
         zoom: f32,
         paint_zoom: u8,
-        drawing_config: &DrawingConfig,
-        segment_outlines_coord: Option<[i32; 3]>,
-        extra_resolutions: u32,
-        world: &Volume,
+        content: &PaneContent<'_>,
     ) -> Self {
-        let volume_id = world as *const Volume as usize;
+        let volume_id = content.world.id();
+        let layers = content
+            .layers
+            .iter()
+            .filter(|l| l.settings.visible)
+            .map(|l| (l.volume.id(), l.settings.clone()))
+            .collect();
 
         let min_level = (32 - ((ZOOM_RES_FACTOR / zoom) as u32).leading_zeros()).min(4).max(0);
 
@@ -49,14 +58,58 @@ impl TileCacheKey {
             w,
             min_level,
             paint_zoom,
-            drawing_config: drawing_config.clone(),
-            segment_outlines_coord,
-            extra_resolutions,
+            drawing_config: content.drawing_config.clone(),
+            segment_outlines_coord: content.segment_outlines_coord,
+            extra_resolutions: content.extra_resolutions,
             volume_id,
+            layers,
         }
     }
 }
 
+/// Outlines only need to be repainted when the position, zoom or outlined segments change
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct OutlineCacheKey {
+    pane_type: PaneType,
+    coord: [i32; 3],
+    width: usize,
+    height: usize,
+    paint_zoom: u8,
+    drawing_config: DrawingConfig,
+    /// identity, color and highlight of each outlined segment
+    outlines: Vec<(usize, [u8; 3], Option<[i32; 3]>)>,
+}
+
+type OutlineCache = FramePublisher<OutlineCacheKey, egui::TextureHandle>;
+
+/// A segment whose intersection with the plane of a pane is drawn on top of the volume
+#[derive(Clone)]
+pub struct SegmentOutline {
+    pub volume: Rc<dyn SurfaceVolume>,
+    pub color: [u8; 3],
+    /// uv position around which the outline is highlighted, only set for the active segment
+    pub highlight: Option<[i32; 3]>,
+}
+
+/// Everything shown in a pane
+pub struct PaneContent<'a> {
+    pub world: &'a Volume,
+    pub layers: &'a [VolumeLayer],
+    pub outlines: &'a [SegmentOutline],
+    pub drawing_config: &'a DrawingConfig,
+    pub extra_resolutions: u32,
+    pub segment_outlines_coord: Option<[i32; 3]>,
+}
+
+/// What happened in a pane during a frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PaneResponse {
+    /// the position or zoom was changed by scrolling or dragging
+    pub changed: bool,
+    /// volume position of a click into the pane
+    pub clicked: Option<[f64; 3]>,
+}
+
 struct CancellableImageFuture {
     future: Pin<Box<dyn futures::Future<Output = Arc<ColorImage>> + Send + Sync>>,
     is_cancelled: Arc<AtomicBool>,
@@ -228,33 +281,25 @@ impl VolumePane {
         &self,
         ui: &mut Ui,
         coord: &mut [i32; 3],
-        world: &Volume,
-        surface_volume: Option<Arc<dyn SurfaceVolume>>,
         zoom: &mut f32,
-        drawing_config: &DrawingConfig,
-        extra_resolutions: u32,
-        segment_outlines_coord: Option<[i32; 3]>,
         ranges: &[RangeInclusive<i32>; 3],
         cell_size: Vec2,
-    ) -> bool {
+        content: &PaneContent<'_>,
+    ) -> PaneResponse {
         let frame_width = cell_size.x as usize;
         let frame_height = cell_size.y as usize;
-
-        // Get or create tiles
-        let tiles = self.get_or_create_tiles(
-            ui,
-            *coord,
+        let PaneContent {
             world,
-            *zoom,
-            frame_width,
-            frame_height,
+            outlines,
             drawing_config,
-            extra_resolutions,
-            segment_outlines_coord,
-        );
+            ..
+        } = *content;
+
+        // Get or create tiles
+        let tiles = self.get_or_create_tiles(ui, *coord, *zoom, frame_width, frame_height, content);
 
         // Allocate space for this pane using the proper egui pattern
-        let (response, painter) = ui.allocate_painter(cell_size, egui::Sense::drag());
+        let (response, painter) = ui.allocate_painter(cell_size, egui::Sense::click_and_drag());
 
         // Paint all tiles on the allocated space - tiles should use response.rect coordinate system
         for (texture, tile_rect) in tiles {
@@ -271,9 +316,8 @@ impl VolumePane {
         }
 
         // Add segment outlines if configured
-        if let (Some(surface_vol), Some(outlines_coord)) = (surface_volume, segment_outlines_coord) {
-            // paint segment outline on a new texture that is not cached or tiled
-            let (u_coord, v_coord, d_coord) = self.pane_type.coordinates();
+        if !outlines.is_empty() {
+            // paint segment outlines on a separate texture that is not tiled
             let paint_zoom = if *zoom >= 1.0 {
                 1u8
             } else {
@@ -287,22 +331,19 @@ impl VolumePane {
                 let width = (frame_width as f32 / scaling) as usize;
                 let height = (frame_height as f32 / scaling) as usize;
 
-                let mut image = crate::volume::Image::new_from_color(width, height, Color32::TRANSPARENT);
-                surface_vol.paint_plane_intersection(
-                    *coord,
-                    u_coord,
-                    v_coord,
-                    d_coord,
+                let key = OutlineCacheKey {
+                    pane_type: self.pane_type,
+                    coord: *coord,
                     width,
                     height,
-                    1,
                     paint_zoom,
-                    Some(outlines_coord),
-                    drawing_config,
-                    &mut image,
-                );
-                let image: egui::ColorImage = image.into();
-                let texture = ui.ctx().load_texture(self.pane_type.label(), image, Default::default());
+                    drawing_config: drawing_config.clone(),
+                    outlines: outlines
+                        .iter()
+                        .map(|o| (Rc::as_ptr(&o.volume) as *const () as usize, o.color, o.highlight))
+                        .collect(),
+                };
+                let texture = self.outline_texture(ui, key, outlines);
                 // Adjust rect to be relative to response.rect
                 let adjusted_rect = egui::Rect::from_min_size(response.rect.min, response.rect.size());
                 // Paint the segment outline texture
@@ -315,6 +356,10 @@ impl VolumePane {
             }
         }
 
+        if let Some(voxel_size_um) = world.metadata().and_then(|m| m.voxel_size_um) {
+            Self::paint_scale_bar(&painter, response.rect, voxel_size_um, *zoom);
+        }
+
         // Handle interactions and return whether textures need clearing
         let mut interaction_happened = false;
 
@@ -326,7 +371,90 @@ impl VolumePane {
             interaction_happened = true;
         }
 
-        interaction_happened
+        PaneResponse {
+            changed: interaction_happened,
+            clicked: self.handle_click(&response, *coord, *zoom),
+        }
+    }
+
+    fn outline_texture(&self, ui: &Ui, key: OutlineCacheKey, outlines: &[SegmentOutline]) -> egui::TextureHandle {
+        let cached = ui.memory_mut(|mem| mem.caches.cache::<OutlineCache>().get(&key).cloned());
+        let texture = cached.unwrap_or_else(|| {
+            let (u_coord, v_coord, d_coord) = self.pane_type.coordinates();
+            let mut image = crate::volume::Image::new_from_color(key.width, key.height, Color32::TRANSPARENT);
+            for outline in outlines {
+                let config = DrawingConfig {
+                    segment_outline_color: outline.color,
+                    ..key.drawing_config.clone()
+                };
+                outline.volume.paint_plane_intersection(
+                    key.coord,
+                    u_coord,
+                    v_coord,
+                    d_coord,
+                    key.width,
+                    key.height,
+                    1,
+                    key.paint_zoom,
+                    outline.highlight,
+                    &config,
+                    &mut image,
+                );
+            }
+            let image: egui::ColorImage = image.into();
+            ui.ctx().load_texture(self.pane_type.label(), image, Default::default())
+        });
+        // refresh the entry, so that it is kept for the next frame
+        ui.memory_mut(|mem| mem.caches.cache::<OutlineCache>().set(key, texture.clone()));
+        texture
+    }
+
+    /// Converts the position of a primary click into volume coordinates
+    fn handle_click(&self, response: &Response, coord: [i32; 3], zoom: f32) -> Option<[f64; 3]> {
+        if !response.clicked_by(PointerButton::Primary) {
+            return None;
+        }
+        let pos = response.interact_pointer_pos()?;
+        let (u_coord, v_coord, _) = self.pane_type.coordinates();
+        let offset = (pos - response.rect.center()) / zoom;
+        let mut xyz = coord.map(|c| c as f64);
+        xyz[u_coord] += offset.x as f64;
+        xyz[v_coord] += offset.y as f64;
+        Some(xyz)
+    }
+
+    /// Paints a scale bar of a round length (1, 2, or 5 times a power of ten) of roughly 100 pixels
+    fn paint_scale_bar(painter: &egui::Painter, rect: egui::Rect, voxel_size_um: f64, zoom: f32) {
+        let um_per_pixel = voxel_size_um / zoom as f64;
+        let target_um = 100.0 * um_per_pixel;
+        let magnitude = 10f64.powf(target_um.log10().floor());
+        let length_um = [5.0, 2.0, 1.0]
+            .iter()
+            .map(|f| f * magnitude)
+            .find(|l| *l <= target_um)
+            .unwrap_or(magnitude);
+        let length_px = (length_um / um_per_pixel) as f32;
+
+        let label = if length_um >= 1000.0 {
+            format!("{} mm", length_um / 1000.0)
+        } else {
+            format!("{} µm", length_um)
+        };
+
+        let right = rect.right() - 10.0;
+        let bottom = rect.bottom() - 10.0;
+        let stroke = egui::Stroke::new(2.0, Color32::WHITE);
+        painter.line_segment(
+            [egui::pos2(right - length_px, bottom), egui::pos2(right, bottom)],
+            stroke,
+        );
+        painter.text(
+            egui::pos2(right - length_px / 2.0, bottom - 4.0),
+            egui::Align2::CENTER_BOTTOM,
+            label,
+            egui::FontId::proportional(12.0),
+            Color32::WHITE,
+        );
     }
 
     pub fn handle_scroll(
@@ -384,14 +512,12 @@ impl VolumePane {
         &self,
         ui: &Ui,
         coord: [i32; 3],
-        world: &Volume,
         zoom: f32,
         frame_width: usize,
         frame_height: usize,
-        drawing_config: &DrawingConfig,
-        extra_resolutions: u32,
-        segment_outlines_coord: Option<[i32; 3]>,
+        content: &PaneContent<'_>,
     ) -> Vec<(egui::TextureHandle, egui::Rect)> {
+        let PaneContent { world, layers, .. } = *content;
         let visible_tiles = self.calculate_visible_tiles(coord, zoom, frame_width, frame_height);
         let paint_zoom = if zoom >= 1.0 {
             1u8
@@ -411,30 +537,27 @@ impl VolumePane {
                     coord[self.pane_type.coordinates().2],
                     zoom,
                     paint_zoom,
-                    drawing_config,
-                    segment_outlines_coord,
-                    extra_resolutions,
-                    world,
+                    content,
                 );
                 (key, *tile_rect)
             })
             .collect::<Vec<_>>();
 
         for (key, _) in keys_and_rects.iter() {
-            self.ensure_tile_async(ui, key.clone(), world);
+            self.ensure_tile_async(ui, key.clone(), world, layers);
         }
 
         let millis = 20; //if self.pane_type == PaneType::UV { 10 } else { 20 };
         let deadline = std::time::Instant::now() + std::time::Duration::from_millis(millis);
         let mut ready_tiles = Vec::new();
         for (key, tile_rect) in keys_and_rects {
-            if let Some(texture) = self.get_or_create_tile_async(ui, key, world, deadline) {
+            if let Some(texture) = self.get_or_create_tile_async(ui, key, world, layers, deadline) {
                 ready_tiles.push((texture, tile_rect));
             }
         }
         ready_tiles
     }
-    fn ensure_tile_async(&self, ui: &Ui, key: TileCacheKey, world: &Volume) {
+    fn ensure_tile_async(&self, ui: &Ui, key: TileCacheKey, world: &Volume, layers: &[VolumeLayer]) {
         // Check if tile exists in cache
         let cached_value = ui.memory_mut(|mem| {
             let cache: &mut TileCache = mem.caches.cache::<TileCache>();
@@ -449,7 +572,7 @@ impl VolumePane {
 
         match cached_value {
             None => {
-                let handle = self.create_tile_async(&key, world);
+                let handle = self.create_tile_async(&key, world, layers);
 
                 set(ui, key, AsyncTexture::Loading(handle));
             }
@@ -462,6 +585,7 @@ impl VolumePane {
         ui: &Ui,
         key: TileCacheKey,
         world: &Volume,
+        layers: &[VolumeLayer],
         deadline: std::time::Instant,
     ) -> Option<egui::TextureHandle> {
         // Calculate paint_zoom for cache key (same logic as in create_tile)
@@ -533,7 +657,7 @@ impl VolumePane {
             }
             None => {
                 // Start async rendering
-                let handle = self.create_tile_async(&key, world);
+                let handle = self.create_tile_async(&key, world, layers);
 
                 set(ui, key, AsyncTexture::Loading(handle));
                 ui.ctx().request_repaint();
@@ -542,11 +666,21 @@ impl VolumePane {
         }
     }
 
-    fn create_tile_async(&self, key: &TileCacheKey, world: &Volume) -> Arc<Mutex<CancellableImageFuture>> {
+    fn create_tile_async(
+        &self,
+        key: &TileCacheKey,
+        world: &Volume,
+        layers: &[VolumeLayer],
+    ) -> Arc<Mutex<CancellableImageFuture>> {
         let pane_type = self.pane_type;
         let is_segment_pane = self.is_segment_pane;
         let key_clone = key.clone();
         let shared = world.shared();
+        let shared_layers = layers
+            .iter()
+            .filter(|l| l.settings.visible)
+            .map(|l| (l.volume.shared(), l.settings.clone()))
+            .collect::<Vec<_>>();
         let is_cancelled = Arc::new(AtomicBool::new(false));
         let is_cancelled_clone = is_cancelled.clone();
 
@@ -556,7 +690,8 @@ impl VolumePane {
             }
 
             let volume_pane = VolumePane::new(pane_type, is_segment_pane);
-            let image = volume_pane.create_tile_sync(&key_clone, shared());
+            let layers = shared_layers.into_iter().map(|(v, s)| (v(), s)).collect();
+            let image = volume_pane.create_tile_sync(&key_clone, shared(), layers);
             Arc::new(image)
         });
 
@@ -580,7 +715,12 @@ impl VolumePane {
         }))
     }
 
-    fn create_tile_sync(&self, key: &TileCacheKey, world: Volume) -> egui::ColorImage {
+    fn create_tile_sync(
+        &self,
+        key: &TileCacheKey,
+        world: Volume,
+        layers: Vec<(Volume, LayerSettings)>,
+    ) -> egui::ColorImage {
         use std::time::Instant;
         let _start = Instant::now();
 
@@ -592,7 +732,7 @@ impl VolumePane {
         // Always use fixed tile size - let paint_zoom handle the scaling
         let tile_width = TILE_SIZE;
         let tile_height = TILE_SIZE;
-        let mut image = crate::volume::Image::new(tile_width, tile_height);
+        let mut image = Image::new(tile_width, tile_height);
 
         // Calculate world coordinates for this tile
         // When paint_zoom > 1, each tile covers a larger world area
@@ -611,21 +751,52 @@ impl VolumePane {
         let min_level = key.min_level;
         let max_level: u32 = (min_level + key.extra_resolutions).min(4);
 
-        for level in (min_level..=max_level).rev() {
-            let sfactor = 1 << level as u8;
-            world.reset_for_painting();
-            world.paint(
-                tile_coord,
-                u_coord,
-                v_coord,
-                d_coord,
-                tile_width,
-                tile_height,
-                sfactor,
-                paint_zoom,
-                &key.drawing_config,
-                &mut image,
-            );
+        let paint_levels = |volume: &Volume, config: &DrawingConfig, image: &mut Image| {
+            let (tile_width, tile_height) = (image.width(), image.height());
+            for level in (min_level..=max_level).rev() {
+                let params = PaintParams {
+                    xyz: tile_coord,
+                    u_coord,
+                    v_coord,
+                    plane_coord: d_coord,
+                    width: tile_width,
+                    height: tile_height,
+                    sfactor: 1 << level as u8,
+                    paint_zoom,
+                };
+                volume.reset_for_painting();
+                if self.is_segment_pane {
+                    params.paint(volume, config, image);
+                } else {
+                    paint_slab(volume, &params, config, image);
+                }
+            }
+        };
+
+        let clahe = &key.drawing_config.clahe;
+        if self.is_segment_pane && clahe.enabled {
+            // paint a margin around the tile, so that context regions around the tile edges are complete
+            let margin = clahe.margin();
+            let mut extended = Image::new(tile_width + 2 * margin, tile_height + 2 * margin);
+            paint_levels(&world, &key.drawing_config, &mut extended);
+            key.drawing_config.apply_filters_to(&mut extended);
+            clahe.apply(&mut extended);
+            image = extended.crop(margin, margin, tile_width, tile_height);
+        } else {
+            paint_levels(&world, &key.drawing_config, &mut image);
+            key.drawing_config.apply_filters_to(&mut image);
+        }
+
+        if !layers.is_empty() {
+            // outlines are only drawn once for the main volume
+            let mut layer_config = key.drawing_config.clone();
+            layer_config.draw_xyz_outlines = false;
+            layer_config.show_distortion = false;
+            for (volume, settings) in layers {
+                let mut layer_image = Image::new(tile_width, tile_height);
+                paint_levels(&volume, &layer_config, &mut layer_image);
+                settings.blend(&layer_image, &mut image);
+            }
         }
 
         let image: egui::ColorImage = image.into();
@@ -1,7 +1,11 @@
 use crate::{
-    downloader::SimpleDownloader,
-    volume::{LayersMappedVolume, Volume, VolumeGrid500Mapped, VolumeGrid64x4Mapped, VoxelPaintVolume},
-    zarr::{default_cache_dir_for_url, GrayScale, OmeZarrContext, ZarrArray},
+    downloader::{DownloadTask, Downloader, SimpleDownloader},
+    volume::{
+        AffineTransform, CacheConfig, DataType, FilterKind, FilteredVolume, LayersMappedVolume, MaskedVolume,
+        MultiPageTiffVolume, RawVolume, SyntheticVolume, TransformedVolume, Volume, VolumeError, VolumeGrid500Mapped,
+        VolumeGrid64x4Mapped, VolumeMetadata, VoxelPaintVolume,
+    },
+    zarr::{default_cache_dir_for_url, GrayScale, OmeZarrContext, ZarrArray, ZarrContextCache},
 };
 use std::{path::Path, sync::Arc};
 
@@ -23,6 +27,9 @@ pub trait VolumeReference: Send + Sync {
     fn label(&self) -> String;
     fn url_path_base(&self) -> String;
     fn owned(&self) -> Box<dyn VolumeReference>;
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        None
+    }
 }
 impl dyn VolumeReference {
     pub const VOLUMES: [&'static dyn VolumeReference; 25] = [
@@ -64,14 +71,31 @@ impl TryFrom<String> for &'static dyn VolumeReference {
     }
 }
 
+/// Metadata of a 64-4 volume given its max x/y/z (inclusive) and voxel size in nm
+pub(crate) fn volume64x4_metadata(max: [usize; 3], voxel_size_nm: usize) -> VolumeMetadata {
+    // 64-4 data is served as 8-bit and downsampled up to 16x
+    VolumeMetadata::new(max.map(|m| m + 1), DataType::UInt8)
+        .with_voxel_size_um(voxel_size_nm as f64 / 1000.0)
+        .with_pyramid(5)
+}
+
 #[derive(Debug, Clone)]
 pub struct DynamicFullVolumeReference {
     pub scroll_id: String,
     pub volume: String,
+    pub metadata: Option<VolumeMetadata>,
 }
 impl DynamicFullVolumeReference {
     pub fn new(scroll_id: String, volume: String) -> DynamicFullVolumeReference {
-        DynamicFullVolumeReference { scroll_id, volume }
+        DynamicFullVolumeReference {
+            scroll_id,
+            volume,
+            metadata: None,
+        }
+    }
+    pub fn with_metadata(mut self, metadata: VolumeMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
     }
 }
 impl VolumeReference for DynamicFullVolumeReference {
@@ -90,6 +114,9 @@ impl VolumeReference for DynamicFullVolumeReference {
     fn owned(&self) -> Box<dyn VolumeReference> {
         Box::new(self.clone())
     }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        self.metadata.clone()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -209,6 +236,10 @@ impl VolumeReference for FullVolumeReference {
     fn owned(&self) -> Box<dyn VolumeReference> {
         Box::new(self.clone())
     }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        // 64-4 tiles carry no metadata of their own, use what the segment catalog records for the volume
+        crate::catalog::volume_metadata(self.volume)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -247,6 +278,8 @@ impl VolumeReference for SurfaceVolumeReference {
 
 pub struct VolumeCreationParams {
     pub cache_dir: String,
+    /// bounds for the in-memory cache of 64-4 tiles, zarr chunks and decoded TIFF strips / tiles
+    pub cache: CacheConfig,
 }
 
 pub enum VolumeLocation {
@@ -256,10 +289,24 @@ pub enum VolumeLocation {
 
 pub enum NewVolumeReference {
     Volume64x4(Box<dyn VolumeReference>),
+    // previously downloaded or locally converted 64-4 tiles, missing tiles are not downloaded
+    Local64x4 { id: String, path: String },
     OmeZarr { id: String, location: VolumeLocation },
     Zarr { id: String, location: VolumeLocation },
     Cells { id: String, path: String },
     Layers { id: String, path: String },
+    MultiPageTiff { id: String, path: String },
+    Nrrd { id: String, path: String },
+    // raw file with a json sidecar header
+    Raw { id: String, path: String },
+    // procedurally generated volume, see `SyntheticVolume::from_spec`
+    Synthetic { id: String, spec: String },
+    // another volume viewed through an affine transform (mapping from the base volume into the browsed frame)
+    Transformed(Box<NewVolumeReference>, AffineTransform),
+    // another volume with a filter applied
+    Filtered(Box<NewVolumeReference>, FilterKind),
+    // another volume restricted to where the mask volume (second) is non-zero
+    Masked(Box<NewVolumeReference>, Box<NewVolumeReference>),
 }
 impl NewVolumeReference {
     const TILE_SERVER: &'static str = "https://vesuvius.virtual-void.net";
@@ -267,23 +314,39 @@ impl NewVolumeReference {
     pub fn id(&self) -> String {
         match self {
             NewVolumeReference::Volume64x4(v) => v.id(),
+            NewVolumeReference::Local64x4 { id, .. } => id.clone(),
             NewVolumeReference::OmeZarr { id, .. } => id.clone(),
             NewVolumeReference::Zarr { id, .. } => id.clone(),
             NewVolumeReference::Cells { id, .. } => id.clone(),
             NewVolumeReference::Layers { id, .. } => id.clone(),
+            NewVolumeReference::MultiPageTiff { id, .. } => id.clone(),
+            NewVolumeReference::Nrrd { id, .. } => id.clone(),
+            NewVolumeReference::Raw { id, .. } => id.clone(),
+            NewVolumeReference::Synthetic { id, .. } => id.clone(),
+            NewVolumeReference::Transformed(base, _) => format!("{}-transformed", base.id()),
+            NewVolumeReference::Filtered(base, filter) => format!("{}-{}", base.id(), filter.label()),
+            NewVolumeReference::Masked(base, mask) => format!("{}-masked-{}", base.id(), mask.id()),
         }
     }
     pub fn label(&self) -> String {
         match self {
             NewVolumeReference::Volume64x4(v) => v.label(),
+            NewVolumeReference::Local64x4 { id, .. } => id.clone(),
             NewVolumeReference::OmeZarr { id, .. } => id.clone(),
             NewVolumeReference::Zarr { id, .. } => id.clone(),
             NewVolumeReference::Cells { id, .. } => id.clone(),
             NewVolumeReference::Layers { id, .. } => id.clone(),
+            NewVolumeReference::MultiPageTiff { id, .. } => id.clone(),
+            NewVolumeReference::Nrrd { id, .. } => id.clone(),
+            NewVolumeReference::Raw { id, .. } => id.clone(),
+            NewVolumeReference::Synthetic { id, .. } => id.clone(),
+            NewVolumeReference::Transformed(base, _) => format!("{} (transformed)", base.label()),
+            NewVolumeReference::Filtered(base, filter) => format!("{} ({})", base.label(), filter.label()),
+            NewVolumeReference::Masked(base, mask) => format!("{} (masked by {})", base.label(), mask.label()),
         }
     }
-    pub fn volume(&self, params: &VolumeCreationParams) -> Volume {
-        match self {
+    pub fn volume(&self, params: &VolumeCreationParams) -> Result<Volume, VolumeError> {
+        Ok(match self {
             NewVolumeReference::Volume64x4(v) => {
                 let (sender, _) = std::sync::mpsc::channel::<(usize, usize, usize, Quality)>();
 
@@ -297,26 +360,76 @@ impl NewVolumeReference {
                     sender,
                     false,
                 ));
-                let v = VolumeGrid64x4Mapped::from_data_dir(&volume_dir, downloader);
+                let v = VolumeGrid64x4Mapped::from_data_dir(&volume_dir, downloader)?
+                    .with_metadata(v.metadata())
+                    .with_cache_config(params.cache);
                 v.into_volume()
             }
-            NewVolumeReference::OmeZarr { location, .. } => match location {
-                VolumeLocation::RemoteUrl(url) => {
-                    OmeZarrContext::<GrayScale>::from_url_to_default_cache_dir(url).into_volume()
+            NewVolumeReference::Local64x4 { path, .. } => {
+                struct NoDownloader;
+                impl Downloader for NoDownloader {
+                    fn queue(&self, _task: DownloadTask) {}
                 }
-                VolumeLocation::LocalPath(path) => OmeZarrContext::<GrayScale>::from_path(path).into_volume(),
-            },
-            NewVolumeReference::Zarr { location, .. } => match location {
-                VolumeLocation::RemoteUrl(url) => ZarrArray::from_url_to_default_cache_dir(url)
-                    .into_ctx()
-                    .into_ctx()
+                VolumeGrid64x4Mapped::from_data_dir(path, Arc::new(NoDownloader))?
+                    .with_cache_config(params.cache)
+                    .into_volume()
+            }
+            NewVolumeReference::OmeZarr { location, .. } => match location {
+                VolumeLocation::RemoteUrl(url) => OmeZarrContext::<GrayScale>::from_url_to_default_cache_dir(url)?
+                    .with_cache_config(params.cache)
+                    .into_volume(),
+                VolumeLocation::LocalPath(path) => OmeZarrContext::<GrayScale>::from_path(path)?
+                    .with_cache_config(params.cache)
                     .into_volume(),
-                VolumeLocation::LocalPath(path) => ZarrArray::from_path(path).into_ctx().into_ctx().into_volume(),
             },
+            NewVolumeReference::Zarr { location, .. } => {
+                let array = match location {
+                    VolumeLocation::RemoteUrl(url) => ZarrArray::from_url_to_default_cache_dir(url)?,
+                    VolumeLocation::LocalPath(path) => ZarrArray::from_path(path)?,
+                };
+                array
+                    .into_ctx_with_cache(&Arc::new(ZarrContextCache::new(params.cache)))
+                    .into_ctx()
+                    .into_volume()
+            }
 
-            NewVolumeReference::Cells { path, .. } => VolumeGrid500Mapped::from_data_dir(path).into_volume(),
-            NewVolumeReference::Layers { path, .. } => LayersMappedVolume::from_data_dir(path).into_volume(),
-        }
+            NewVolumeReference::Cells { path, .. } => VolumeGrid500Mapped::from_data_dir(path)?
+                .with_cache_config(params.cache)
+                .into_volume(),
+            NewVolumeReference::Layers { path, .. } => LayersMappedVolume::from_data_dir(path)?
+                .with_cache_config(params.cache)
+                .into_volume(),
+            NewVolumeReference::MultiPageTiff { path, .. } => MultiPageTiffVolume::from_file(path)?
+                .with_cache_config(params.cache)
+                .into_volume(),
+            NewVolumeReference::Nrrd { path, .. } => RawVolume::from_nrrd(path)?.into_volume(),
+            NewVolumeReference::Raw { path, .. } => RawVolume::from_raw(path)?.into_volume(),
+            NewVolumeReference::Synthetic { spec, .. } => SyntheticVolume::from_spec(spec)?.into_volume(),
+            NewVolumeReference::Transformed(base, transform) => {
+                TransformedVolume::new(base.volume(params)?, transform.clone())?.into_volume()
+            }
+            NewVolumeReference::Filtered(base, filter) => FilteredVolume::new(base.volume(params)?, *filter)
+                .with_cache_config(params.cache)
+                .into_volume(),
+            NewVolumeReference::Masked(base, mask) => {
+                MaskedVolume::new(base.volume(params)?, mask.volume(params)?).into_volume()
+            }
+        })
+    }
+
+    /// View this volume through the given transform
+    pub fn transformed(self, transform: AffineTransform) -> Self {
+        NewVolumeReference::Transformed(Box::new(self), transform)
+    }
+
+    /// View this volume with the given filter applied
+    pub fn filtered(self, filter: FilterKind) -> Self {
+        NewVolumeReference::Filtered(Box::new(self), filter)
+    }
+
+    /// Hide everything outside of the given mask volume
+    pub fn masked(self, mask: NewVolumeReference) -> Self {
+        NewVolumeReference::Masked(Box::new(self), Box::new(mask))
     }
 
     pub fn from_url(url: impl Into<String>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
@@ -339,6 +452,17 @@ impl NewVolumeReference {
         Self::from_location(VolumeLocation::LocalPath(normalized_path))
     }
 
+    /// A procedurally generated volume, e.g. `rolled-sheets` or `checkerboard,cell=32`
+    pub fn from_synthetic(spec: impl Into<String>) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let spec = spec.into();
+        // validate early to report errors when parsing arguments
+        SyntheticVolume::from_spec(&spec)?;
+        Ok(NewVolumeReference::Synthetic {
+            id: format!("synthetic:{}", spec),
+            spec,
+        })
+    }
+
     fn from_location(location: VolumeLocation) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         fn check_file_content(location: &VolumeLocation, file: &str, content_check: &str) -> bool {
             match location {
@@ -384,6 +508,28 @@ impl NewVolumeReference {
             return Ok(NewVolumeReference::Zarr { id, location });
         }
 
+        // Try single file formats (only for local paths)
+        if let VolumeLocation::LocalPath(path) = &location {
+            let p = Path::new(path);
+            if p.is_file() {
+                let extension = p.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+                let path = path.clone();
+                match extension.as_str() {
+                    "nrrd" | "nhdr" => return Ok(NewVolumeReference::Nrrd { id, path }),
+                    "tif" | "tiff" => return Ok(NewVolumeReference::MultiPageTiff { id, path }),
+                    "bin" | "raw" => return Ok(NewVolumeReference::Raw { id, path }),
+                    _ => {}
+                }
+            }
+        }
+
+        // Try 64-4 tiles (only for local paths)
+        if let VolumeLocation::LocalPath(path) = &location {
+            if Path::new(path).join("64-4").is_dir() {
+                return Ok(NewVolumeReference::Local64x4 { id, path: path.clone() });
+            }
+        }
+
         // Try probing for cells and layers (only for local paths)
         if let VolumeLocation::LocalPath(path) = &location {
             if let Ok(entries) = std::fs::read_dir(path) {
@@ -418,7 +564,7 @@ impl NewVolumeReference {
         };
 
         Err(format!(
-            "{} {} is not a valid volume format (no .zattrs, .zarray, cell files, or layer files found)",
+            "{} {} is not a valid volume format (no .zattrs, .zarray, 64-4 tiles, cell files, layer files, tif, nrrd, or raw file found)",
             location_type, location_str
         )
         .into())
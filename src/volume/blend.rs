@@ -0,0 +1,215 @@
+use super::{Image, Volume};
+use egui::Color32;
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum BlendMode {
+    Alpha,
+    Additive,
+    Max,
+}
+impl BlendMode {
+    pub fn label(&self) -> &str {
+        match self {
+            BlendMode::Alpha => "Alpha",
+            BlendMode::Additive => "Additive",
+            BlendMode::Max => "Max",
+        }
+    }
+    pub const VALUES: [BlendMode; 3] = [BlendMode::Alpha, BlendMode::Additive, BlendMode::Max];
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum Colormap {
+    Gray,
+    Red,
+    Green,
+    Blue,
+    Cyan,
+    Magenta,
+    Yellow,
+    Fire,
+}
+impl Colormap {
+    pub fn label(&self) -> &str {
+        match self {
+            Colormap::Gray => "Gray",
+            Colormap::Red => "Red",
+            Colormap::Green => "Green",
+            Colormap::Blue => "Blue",
+            Colormap::Cyan => "Cyan",
+            Colormap::Magenta => "Magenta",
+            Colormap::Yellow => "Yellow",
+            Colormap::Fire => "Fire",
+        }
+    }
+    pub const VALUES: [Colormap; 8] = [
+        Colormap::Gray,
+        Colormap::Red,
+        Colormap::Green,
+        Colormap::Blue,
+        Colormap::Cyan,
+        Colormap::Magenta,
+        Colormap::Yellow,
+        Colormap::Fire,
+    ];
+
+    pub fn map(&self, v: u8) -> Color32 {
+        match self {
+            Colormap::Gray => Color32::from_gray(v),
+            Colormap::Red => Color32::from_rgb(v, 0, 0),
+            Colormap::Green => Color32::from_rgb(0, v, 0),
+            Colormap::Blue => Color32::from_rgb(0, 0, v),
+            Colormap::Cyan => Color32::from_rgb(0, v, v),
+            Colormap::Magenta => Color32::from_rgb(v, 0, v),
+            Colormap::Yellow => Color32::from_rgb(v, v, 0),
+            Colormap::Fire => {
+                // black -> red -> yellow -> white
+                let v = v as u16 * 3;
+                Color32::from_rgb(
+                    v.min(255) as u8,
+                    v.saturating_sub(255).min(255) as u8,
+                    v.saturating_sub(510).min(255) as u8,
+                )
+            }
+        }
+    }
+}
+
+/// How a layer is composed on top of the layers below it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub struct LayerSettings {
+    pub visible: bool,
+    pub opacity: u8,
+    pub colormap: Colormap,
+    pub blend_mode: BlendMode,
+    /// values between `window[0]` and `window[1]` are stretched to the full range before applying the colormap
+    pub window: [u8; 2],
+}
+impl Default for LayerSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            opacity: 128,
+            colormap: Colormap::Magenta,
+            blend_mode: BlendMode::Alpha,
+            window: [0, 255],
+        }
+    }
+}
+impl LayerSettings {
+    fn apply_window(&self, v: u8) -> u8 {
+        let [min, max] = self.window;
+        if v == 0 || (min == 0 && max == 255) {
+            v
+        } else {
+            ((v.saturating_sub(min) as u32 * 255) / (max.saturating_sub(min)).max(1) as u32).min(255) as u8
+        }
+    }
+    /// Blends a gray-scale layer image into `target`. Layer pixels with value 0 are treated as transparent.
+    pub fn blend(&self, layer: &Image, target: &mut Image) {
+        let opacity = self.opacity as u32;
+        for (t, l) in target.data.iter_mut().zip(layer.data.iter()) {
+            let v = self.apply_window(l.r());
+            if v == 0 {
+                continue;
+            }
+            let c = self.colormap.map(v);
+            let scaled = |x: u8| (x as u32 * opacity / 255) as u8;
+            *t = match self.blend_mode {
+                BlendMode::Alpha => {
+                    let mix = |a: u8, b: u8| ((a as u32 * (255 - opacity) + b as u32 * opacity) / 255) as u8;
+                    Color32::from_rgb(mix(t.r(), c.r()), mix(t.g(), c.g()), mix(t.b(), c.b()))
+                }
+                BlendMode::Additive => Color32::from_rgb(
+                    t.r().saturating_add(scaled(c.r())),
+                    t.g().saturating_add(scaled(c.g())),
+                    t.b().saturating_add(scaled(c.b())),
+                ),
+                BlendMode::Max => Color32::from_rgb(
+                    t.r().max(scaled(c.r())),
+                    t.g().max(scaled(c.g())),
+                    t.b().max(scaled(c.b())),
+                ),
+            };
+        }
+    }
+}
+
+/// An additional volume (e.g. another scan, predictions, or labels) shown on top of the main volume.
+#[derive(Clone)]
+pub struct VolumeLayer {
+    pub label: String,
+    pub volume: Volume,
+    pub settings: LayerSettings,
+}
+impl VolumeLayer {
+    pub fn new(label: impl Into<String>, volume: Volume) -> Self {
+        Self {
+            label: label.into(),
+            volume,
+            settings: LayerSettings::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn blend(settings: &LayerSettings, target: Color32, layer: u8) -> Color32 {
+        let mut target = Image::new_from_color(1, 1, target);
+        let mut layer_image = Image::new(1, 1);
+        layer_image.set_gray(0, 0, layer);
+        settings.blend(&layer_image, &mut target);
+        target.data[0]
+    }
+
+    #[test]
+    fn blend_modes() {
+        let base = Color32::from_gray(100);
+        let settings = |blend_mode| LayerSettings {
+            opacity: 255,
+            colormap: Colormap::Red,
+            blend_mode,
+            ..Default::default()
+        };
+        assert_eq!(
+            blend(&settings(BlendMode::Alpha), base, 200),
+            Color32::from_rgb(200, 0, 0)
+        );
+        assert_eq!(
+            blend(&settings(BlendMode::Additive), base, 200),
+            Color32::from_rgb(255, 100, 100)
+        );
+        assert_eq!(
+            blend(&settings(BlendMode::Max), base, 50),
+            Color32::from_rgb(100, 100, 100)
+        );
+        assert_eq!(
+            blend(&settings(BlendMode::Max), base, 200),
+            Color32::from_rgb(200, 100, 100)
+        );
+
+        let half = LayerSettings {
+            opacity: 128,
+            colormap: Colormap::Gray,
+            ..Default::default()
+        };
+        assert_eq!(blend(&half, Color32::from_gray(0), 254), Color32::from_gray(127));
+    }
+
+    #[test]
+    fn zero_and_windowed_values() {
+        let base = Color32::from_gray(100);
+        let settings = LayerSettings {
+            opacity: 255,
+            colormap: Colormap::Gray,
+            window: [100, 200],
+            ..Default::default()
+        };
+        // missing layer data is transparent
+        assert_eq!(blend(&settings, base, 0), base);
+        assert_eq!(blend(&settings, base, 150), Color32::from_gray(127));
+        assert_eq!(blend(&settings, base, 250), Color32::from_gray(255));
+    }
+}
@@ -0,0 +1,159 @@
+use super::Image;
+use egui::Color32;
+
+/// Contrast limited adaptive histogram equalization (CLAHE) of painted surface images
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ClaheSettings {
+    pub enabled: bool,
+    /// maximum height of a histogram bin as a multiple of the average bin height
+    pub clip_limit: u8,
+    /// edge length of the context regions in pixels, must divide the tile size to avoid seams between tiles
+    pub region_size: u16,
+}
+impl Default for ClaheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            clip_limit: 3,
+            region_size: 64,
+        }
+    }
+}
+impl ClaheSettings {
+    pub const REGION_SIZES: [u16; 4] = [32, 64, 128, 256];
+
+    /// Number of pixels that need to be painted around a tile so that CLAHE results are independent of the tiling.
+    pub fn margin(&self) -> usize {
+        if self.enabled {
+            self.region_size as usize
+        } else {
+            0
+        }
+    }
+
+    /// Equalizes gray pixels of the image in place. Context regions start at the top-left corner of the image, black
+    /// pixels (no data) and colored pixels (overlays) are left untouched.
+    pub fn apply(&self, image: &mut Image) {
+        let region = self.region_size.max(1) as usize;
+        let (width, height) = (image.width(), image.height());
+        let nx = (width + region - 1) / region;
+        let ny = (height + region - 1) / region;
+        let gray = |c: &Color32| (c.r() == c.g() && c.g() == c.b() && c.r() > 0).then_some(c.r());
+
+        // mapping for each context region
+        let mut luts = vec![[0u8; 256]; nx * ny];
+        for ry in 0..ny {
+            for rx in 0..nx {
+                let mut hist = [0u32; 256];
+                for y in ry * region..((ry + 1) * region).min(height) {
+                    for x in rx * region..((rx + 1) * region).min(width) {
+                        if let Some(v) = gray(&image.data[y * width + x]) {
+                            hist[v as usize] += 1;
+                        }
+                    }
+                }
+                luts[ry * nx + rx] = Self::equalization_lut(&mut hist, self.clip_limit as u32);
+            }
+        }
+
+        // interpolate between the mappings of the closest region centers
+        let weights = |p: usize, n: usize| {
+            let f = (p as f32 + 0.5) / region as f32 - 0.5;
+            let i0 = (f.floor().max(0.0) as usize).min(n - 1);
+            let i1 = (i0 + 1).min(n - 1);
+            (i0, i1, (f - i0 as f32).clamp(0.0, 1.0))
+        };
+        for y in 0..height {
+            let (y0, y1, wy) = weights(y, ny);
+            for x in 0..width {
+                let pixel = &mut image.data[y * width + x];
+                if let Some(v) = gray(pixel) {
+                    let (x0, x1, wx) = weights(x, nx);
+                    let v = v as usize;
+                    let top = luts[y0 * nx + x0][v] as f32 * (1.0 - wx) + luts[y0 * nx + x1][v] as f32 * wx;
+                    let bottom = luts[y1 * nx + x0][v] as f32 * (1.0 - wx) + luts[y1 * nx + x1][v] as f32 * wx;
+                    // keep 0 for missing data
+                    let res = (top * (1.0 - wy) + bottom * wy).round().max(1.0) as u8;
+                    *pixel = Color32::from_gray(res);
+                }
+            }
+        }
+    }
+
+    fn equalization_lut(hist: &mut [u32; 256], clip_limit: u32) -> [u8; 256] {
+        let total = hist.iter().sum::<u32>();
+        let mut lut = [0u8; 256];
+        if total == 0 {
+            lut.iter_mut().enumerate().for_each(|(i, l)| *l = i as u8);
+            return lut;
+        }
+
+        // clip bins and redistribute the excess evenly
+        let limit = (clip_limit * total / 256).max(1);
+        let mut excess = 0;
+        for h in hist.iter_mut() {
+            if *h > limit {
+                excess += *h - limit;
+                *h = limit;
+            }
+        }
+        let (per_bin, remainder) = (excess / 256, excess % 256);
+        for (i, h) in hist.iter_mut().enumerate() {
+            *h += per_bin + u32::from((i as u32) < remainder);
+        }
+
+        let mut sum = 0;
+        for (l, h) in lut.iter_mut().zip(hist.iter()) {
+            sum += h;
+            *l = (sum as u64 * 255 / total as u64) as u8;
+        }
+        lut
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clahe_is_independent_of_tiling() {
+        let settings = ClaheSettings {
+            enabled: true,
+            clip_limit: 3,
+            region_size: 16,
+        };
+        let value = |x: usize, y: usize| (40 + (x * 7 + y * 3) % 50 + x / 2) as u8;
+        let paint = |x0: usize, y0: usize, w: usize, h: usize| {
+            let mut image = Image::new(w, h);
+            for y in 0..h {
+                for x in 0..w {
+                    image.set_gray(x, y, value(x0 + x, y0 + y));
+                }
+            }
+            image
+        };
+
+        // a full image vs. two tiles painted with a margin of one region
+        let margin = settings.margin();
+        let mut full = paint(0, 0, 96, 64);
+        settings.apply(&mut full);
+        for tile_x in [16, 48] {
+            let mut tile = paint(tile_x - margin, 16 - margin, 32 + 2 * margin, 32 + 2 * margin);
+            settings.apply(&mut tile);
+            let tile = tile.crop(margin, margin, 32, 32);
+            for y in 0..32 {
+                for x in 0..32 {
+                    assert_eq!(tile.data[y * 32 + x], full.data[(16 + y) * 96 + tile_x + x]);
+                }
+            }
+        }
+
+        // contrast is stretched
+        let range = |image: &Image| {
+            let values = image.data.iter().map(|c| c.r());
+            values.clone().max().unwrap() - values.min().unwrap()
+        };
+        assert!(range(&full) > range(&paint(0, 0, 96, 64)));
+    }
+}
@@ -0,0 +1,466 @@
+use super::{CompositingMode, CompositingSettings, DrawingConfig, Image, PaintVolume, SlabMode};
+use egui::Color32;
+
+pub(crate) trait CompositionState {
+    fn update(&mut self, a: u8) -> bool;
+    fn result(&self, num_layers: u32) -> u8;
+    fn reset(&mut self);
+}
+#[derive(Clone)]
+struct MaxCompositionState {
+    value: u8,
+}
+impl MaxCompositionState {
+    fn new() -> Self {
+        Self { value: 0 }
+    }
+}
+impl CompositionState for MaxCompositionState {
+    fn update(&mut self, a: u8) -> bool {
+        self.value = self.value.max(a);
+        true
+    }
+    fn result(&self, _num_layers: u32) -> u8 {
+        self.value
+    }
+    fn reset(&mut self) {
+        self.value = 0;
+    }
+}
+#[derive(Clone)]
+struct NoCompositionState;
+impl CompositionState for NoCompositionState {
+    fn update(&mut self, _a: u8) -> bool {
+        false
+    }
+    fn result(&self, _num_layers: u32) -> u8 {
+        0
+    }
+    fn reset(&mut self) {}
+}
+
+#[derive(Clone)]
+struct AlphaCompositionState {
+    min: f32,
+    max: f32,
+    alpha_cutoff: f32,
+    opacity: f32,
+    value: f32,
+    alpha: f32,
+}
+impl AlphaCompositionState {
+    fn new(min: f32, max: f32, alpha_cutoff: f32, opacity: f32) -> Self {
+        Self {
+            min,
+            max,
+            alpha_cutoff,
+            opacity,
+            value: 0.0,
+            alpha: 0.0,
+        }
+    }
+}
+impl CompositionState for AlphaCompositionState {
+    fn update(&mut self, a: u8) -> bool {
+        let value = ((a as f32 / 255.0 - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
+
+        if value == 0.0 {
+            // speed through empty area
+            return true;
+        }
+
+        let weight = (1.0 - self.alpha) * (value * self.opacity).min(1.0);
+        self.value += weight * value;
+        self.alpha += weight;
+
+        self.alpha < self.alpha_cutoff
+    }
+    fn result(&self, _num_layers: u32) -> u8 {
+        (self.value * 255.0).clamp(0.0, 255.0) as u8
+    }
+    fn reset(&mut self) {
+        self.value = 0.0;
+        self.alpha = 0.0;
+    }
+}
+
+#[derive(Clone)]
+struct AlphaHeightMapCompositionState {
+    min: f32,
+    max: f32,
+    alpha_cutoff: f32,
+    opacity: f32,
+    alpha: f32,
+    depth: f32,
+    weighted_depth: f32,
+}
+impl AlphaHeightMapCompositionState {
+    fn new(min: f32, max: f32, alpha_cutoff: f32, opacity: f32) -> Self {
+        Self {
+            min,
+            max,
+            alpha_cutoff,
+            opacity,
+            alpha: 0.0,
+            depth: 0.0,
+            weighted_depth: 0.0,
+        }
+    }
+}
+impl CompositionState for AlphaHeightMapCompositionState {
+    fn update(&mut self, a: u8) -> bool {
+        let value = ((a as f32 / 255.0 - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
+
+        if value == 0.0 {
+            // speed through empty area
+            self.depth += 1.0;
+            return true;
+        }
+
+        let weight = (1.0 - self.alpha) * (value * self.opacity).min(1.0);
+        self.alpha += weight;
+        self.weighted_depth += weight * self.depth;
+        self.depth += 1.0;
+
+        self.alpha < self.alpha_cutoff
+    }
+    fn result(&self, num_layers: u32) -> u8 {
+        //((1.0 - self.weighted_depth * 4.0 / self.alpha / num_layers as f32) * 255.0).clamp(0.0, 255.0) as u8
+        (255.0 - self.weighted_depth / self.alpha * 255.0 / num_layers as f32).clamp(0.0, 255.0) as u8
+    }
+    fn reset(&mut self) {
+        self.depth = 0.0;
+        self.weighted_depth = 0.0;
+        self.alpha = 0.0;
+    }
+}
+
+#[derive(Clone)]
+struct MinCompositionState {
+    value: u8,
+}
+impl MinCompositionState {
+    fn new() -> Self {
+        Self { value: 255 }
+    }
+}
+impl CompositionState for MinCompositionState {
+    fn update(&mut self, a: u8) -> bool {
+        self.value = self.value.min(a);
+        true
+    }
+    fn result(&self, _num_layers: u32) -> u8 {
+        self.value
+    }
+    fn reset(&mut self) {
+        self.value = 255;
+    }
+}
+
+#[derive(Clone)]
+struct MeanCompositionState {
+    sum: u32,
+    count: u32,
+}
+impl MeanCompositionState {
+    fn new() -> Self {
+        Self { sum: 0, count: 0 }
+    }
+}
+impl CompositionState for MeanCompositionState {
+    fn update(&mut self, a: u8) -> bool {
+        self.sum += a as u32;
+        self.count += 1;
+        true
+    }
+    fn result(&self, _num_layers: u32) -> u8 {
+        self.sum.checked_div(self.count).unwrap_or(0) as u8
+    }
+    fn reset(&mut self) {
+        self.sum = 0;
+        self.count = 0;
+    }
+}
+
+#[derive(Clone)]
+struct PercentileCompositionState {
+    histogram: [u32; 256],
+    count: u32,
+    percentile: u8,
+}
+impl PercentileCompositionState {
+    fn new(percentile: u8) -> Self {
+        Self {
+            histogram: [0; 256],
+            count: 0,
+            percentile: percentile.min(100),
+        }
+    }
+}
+impl CompositionState for PercentileCompositionState {
+    fn update(&mut self, a: u8) -> bool {
+        self.histogram[a as usize] += 1;
+        self.count += 1;
+        true
+    }
+    fn result(&self, _num_layers: u32) -> u8 {
+        if self.count == 0 {
+            return 0;
+        }
+        // nearest-rank percentile
+        let rank = ((self.count * self.percentile as u32 + 99) / 100).max(1);
+        let mut seen = 0;
+        for (value, count) in self.histogram.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return value as u8;
+            }
+        }
+        255
+    }
+    fn reset(&mut self) {
+        self.histogram = [0; 256];
+        self.count = 0;
+    }
+}
+
+pub(crate) fn composition_state(settings: &CompositingSettings) -> Box<dyn CompositionState> {
+    match settings.mode {
+        CompositingMode::Max => Box::new(MaxCompositionState::new()),
+        CompositingMode::Alpha => Box::new(AlphaCompositionState::new(
+            settings.alpha_min as f32 / 255.0,
+            settings.alpha_max as f32 / 255.0,
+            settings.alpha_threshold as f32 / 10000.0,
+            settings.opacity as f32 / 100.0,
+        )),
+        CompositingMode::AlphaHeightMap => Box::new(AlphaHeightMapCompositionState::new(
+            settings.alpha_min as f32 / 255.0,
+            settings.alpha_max as f32 / 255.0,
+            settings.alpha_threshold as f32 / 10000.0,
+            settings.opacity as f32 / 100.0,
+        )),
+        CompositingMode::Mean => Box::new(MeanCompositionState::new()),
+        CompositingMode::Min => Box::new(MinCompositionState::new()),
+        CompositingMode::Median => Box::new(PercentileCompositionState::new(50)),
+        CompositingMode::Percentile => Box::new(PercentileCompositionState::new(settings.percentile)),
+        CompositingMode::None => Box::new(NoCompositionState {}),
+    }
+}
+
+/// Composites the layers along the normal of a surface as configured in the compositing settings, or samples a single
+/// layer if compositing is disabled.
+pub(crate) struct LayerComposition {
+    state: Option<Box<dyn CompositionState>>,
+    layers_in_front: i32,
+    layers_behind: i32,
+    direction: i32,
+}
+impl LayerComposition {
+    pub fn new(settings: &CompositingSettings) -> Self {
+        Self {
+            state: (settings.mode != CompositingMode::None).then(|| composition_state(settings)),
+            layers_in_front: settings.layers_in_front as i32,
+            layers_behind: settings.layers_behind as i32,
+            direction: if settings.reverse_direction { -1 } else { 1 },
+        }
+    }
+    pub fn is_enabled(&self) -> bool {
+        self.state.is_some()
+    }
+    /// The value at layer `w` or the composition of the layers around it, `sample` returns the value at a layer
+    pub fn value(&mut self, w: i32, mut sample: impl FnMut(f64) -> u8) -> u8 {
+        let Some(state) = &mut self.state else {
+            return sample(w as f64);
+        };
+        state.reset();
+        let start = w + self.direction * self.layers_in_front;
+        let end = w - self.direction * (self.layers_behind + 1);
+        let step = if start < end { 1 } else { -1 };
+
+        let mut w = start;
+        while w != end {
+            if !state.update(sample(w as f64)) {
+                break;
+            }
+            w += step;
+        }
+        // +1 for the current layer
+        state.result((self.layers_in_front + self.layers_behind + 1) as u32)
+    }
+}
+
+/// Where and at which resolution to paint a plane of a volume, i.e. the arguments of `PaintVolume::paint` apart
+/// from the config and the target buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaintParams {
+    /// center of the painted area
+    pub xyz: [i32; 3],
+    pub u_coord: usize,
+    pub v_coord: usize,
+    pub plane_coord: usize,
+    pub width: usize,
+    pub height: usize,
+    pub sfactor: u8,
+    pub paint_zoom: u8,
+}
+impl PaintParams {
+    pub fn paint(&self, volume: &dyn PaintVolume, config: &DrawingConfig, buffer: &mut Image) {
+        volume.paint(
+            self.xyz,
+            self.u_coord,
+            self.v_coord,
+            self.plane_coord,
+            self.width,
+            self.height,
+            self.sfactor,
+            self.paint_zoom,
+            config,
+            buffer,
+        )
+    }
+}
+
+/// Paints `volume` like `PaintVolume::paint` but, if a slab projection is configured, projects all slices of the
+/// slab along `plane_coord` into a single image.
+pub fn paint_slab(volume: &dyn PaintVolume, params: &PaintParams, config: &DrawingConfig, buffer: &mut Image) {
+    match config.slab.mode {
+        SlabMode::None => params.paint(volume, config, buffer),
+        SlabMode::Max => project_slab(MaxCompositionState::new(), volume, params, config, buffer),
+        SlabMode::Min => project_slab(MinCompositionState::new(), volume, params, config, buffer),
+        SlabMode::Mean => project_slab(MeanCompositionState::new(), volume, params, config, buffer),
+    }
+}
+
+fn project_slab<S: CompositionState + Clone>(
+    state: S,
+    volume: &dyn PaintVolume,
+    params: &PaintParams,
+    config: &DrawingConfig,
+    buffer: &mut Image,
+) {
+    let PaintParams {
+        xyz,
+        plane_coord,
+        sfactor,
+        ..
+    } = *params;
+    let pixels = buffer.data.len();
+    // one state per color channel
+    let mut states = vec![state; pixels * 3];
+    let mut painted = vec![false; pixels];
+
+    // slices are painted with transparent background so that we only compose pixels with data
+    let mut slice = Image::new_from_color(buffer.width, buffer.height, Color32::TRANSPARENT);
+    let mut num_slices = 0;
+    let mut d = xyz[plane_coord] - config.slab.slices_behind as i32;
+    // no need to paint the same downsampled slice multiple times
+    let step = sfactor as i32;
+    while d <= xyz[plane_coord] + config.slab.slices_in_front as i32 {
+        let mut slice_params = *params;
+        slice_params.xyz[plane_coord] = d;
+        slice.data.fill(Color32::TRANSPARENT);
+        slice_params.paint(volume, config, &mut slice);
+
+        for (i, c) in slice.data.iter().enumerate() {
+            if c.a() == 0 {
+                continue;
+            }
+            painted[i] = true;
+            states[i * 3].update(c.r());
+            states[i * 3 + 1].update(c.g());
+            states[i * 3 + 2].update(c.b());
+        }
+        num_slices += 1;
+        d += step;
+    }
+
+    for (i, target) in buffer.data.iter_mut().enumerate() {
+        if painted[i] {
+            *target = Color32::from_rgb(
+                states[i * 3].result(num_slices),
+                states[i * 3 + 1].result(num_slices),
+                states[i * 3 + 2].result(num_slices),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::{SlabSettings, SyntheticPattern, SyntheticVolume, VoxelPaintVolume};
+
+    #[test]
+    fn slab_projection() {
+        // value is the z coordinate
+        let volume = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 2 }, [256; 3]).into_volume();
+        let params = PaintParams {
+            xyz: [50, 50, 100],
+            u_coord: 0,
+            v_coord: 1,
+            plane_coord: 2,
+            width: 4,
+            height: 3,
+            sfactor: 1,
+            paint_zoom: 1,
+        };
+        let project = |mode| {
+            let config = DrawingConfig {
+                slab: SlabSettings {
+                    mode,
+                    slices_in_front: 5,
+                    slices_behind: 3,
+                },
+                ..Default::default()
+            };
+            let mut image = Image::new(params.width, params.height);
+            paint_slab(&volume, &params, &config, &mut image);
+            assert!(image.data.iter().all(|c| *c == image.data[0]));
+            image.data[0]
+        };
+        assert_eq!(project(SlabMode::None), Color32::from_gray(100));
+        assert_eq!(project(SlabMode::Max), Color32::from_gray(105));
+        assert_eq!(project(SlabMode::Min), Color32::from_gray(97));
+        assert_eq!(project(SlabMode::Mean), Color32::from_gray(101));
+    }
+
+    #[test]
+    fn layer_composition() {
+        let value = |mode, reverse_direction| {
+            let settings = CompositingSettings {
+                mode,
+                layers_in_front: 2,
+                layers_behind: 1,
+                reverse_direction,
+                ..Default::default()
+            };
+            let mut layers = vec![];
+            let value = LayerComposition::new(&settings).value(10, |w| {
+                layers.push(w);
+                w as u8
+            });
+            (value, layers)
+        };
+        assert_eq!(value(CompositingMode::None, false), (10, vec![10.0]));
+        assert_eq!(value(CompositingMode::Max, false), (12, vec![12.0, 11.0, 10.0, 9.0]));
+        assert_eq!(value(CompositingMode::Min, true), (8, vec![8.0, 9.0, 10.0, 11.0]));
+    }
+
+    #[test]
+    fn percentile_composition() {
+        let mut median = PercentileCompositionState::new(50);
+        for v in [10, 200, 30, 40, 50] {
+            median.update(v);
+        }
+        assert_eq!(median.result(5), 40);
+
+        let mut p90 = PercentileCompositionState::new(90);
+        for v in 1..=10 {
+            p90.update(v * 10);
+        }
+        assert_eq!(p90.result(10), 90);
+
+        p90.reset();
+        assert_eq!(p90.result(0), 0);
+    }
+}
@@ -0,0 +1,135 @@
+use libm::sqrt;
+
+/// Distortion of a triangle between its UV (in segment pixels) and its 3D (in voxels) shape, based on the singular
+/// values `s1 >= s2` of the linear map from UV to 3D space, i.e. how many voxels one pixel covers along the directions
+/// of largest and smallest stretch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangleDistortion {
+    /// 3D area per UV area (`s1 * s2`), 1 if the area is preserved
+    pub area: f64,
+    /// ratio between largest and smallest stretch (`s1 / s2`), 1 if angles are preserved
+    pub angle: f64,
+    /// largest stretch or compression (`max(s1, 1 / s2)`), 1 if lengths are preserved
+    pub stretch: f64,
+    /// area of the triangle in voxels²
+    pub area_3d: f64,
+}
+impl TriangleDistortion {
+    /// Returns `None` for triangles that are degenerate in UV or 3D space
+    pub fn new(xyz: [[f64; 3]; 3], uv: [[f64; 2]; 3]) -> Option<Self> {
+        let q1 = [uv[1][0] - uv[0][0], uv[1][1] - uv[0][1]];
+        let q2 = [uv[2][0] - uv[0][0], uv[2][1] - uv[0][1]];
+        let det = q1[0] * q2[1] - q1[1] * q2[0];
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let p1 = [0, 1, 2].map(|i| xyz[1][i] - xyz[0][i]);
+        let p2 = [0, 1, 2].map(|i| xyz[2][i] - xyz[0][i]);
+        // columns of the jacobian J = P * Q^-1
+        let ju = [0, 1, 2].map(|i| (p1[i] * q2[1] - p2[i] * q1[1]) / det);
+        let jv = [0, 1, 2].map(|i| (p2[i] * q1[0] - p1[i] * q2[0]) / det);
+        let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+        // singular values from the eigenvalues of J^T J
+        let (a, b, c) = (dot(ju, ju), dot(ju, jv), dot(jv, jv));
+        let root = sqrt(((a - c) / 2.0).powi(2) + b * b);
+        let s1 = sqrt(((a + c) / 2.0 + root).max(0.0));
+        let s2 = sqrt(((a + c) / 2.0 - root).max(0.0));
+        if s2 < 1e-9 {
+            return None;
+        }
+
+        let cross = [
+            p1[1] * p2[2] - p1[2] * p2[1],
+            p1[2] * p2[0] - p1[0] * p2[2],
+            p1[0] * p2[1] - p1[1] * p2[0],
+        ];
+        Some(Self {
+            area: s1 * s2,
+            angle: s1 / s2,
+            stretch: s1.max(1.0 / s2),
+            area_3d: sqrt(dot(cross, cross)) / 2.0,
+        })
+    }
+
+    /// Color for the distortion overlay, from green (no stretch) over yellow to red (stretch by a factor of 2 or more)
+    pub fn color(&self) -> [u8; 3] {
+        let t = (self.stretch - 1.0).clamp(0.0, 1.0);
+        if t < 0.5 {
+            [(510.0 * t) as u8, 255, 0]
+        } else {
+            [255, (255.0 * (2.0 - 2.0 * t)) as u8, 0]
+        }
+    }
+}
+
+/// Summary of the distortion of all triangles of a segment
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DistortionStats {
+    /// mean stretch weighted by the 3D area of the triangles
+    pub mean_stretch: f64,
+    pub max_stretch: f64,
+    /// mean angle distortion weighted by the 3D area of the triangles
+    pub mean_angle: f64,
+    /// surface area in voxels²
+    pub area_voxels: f64,
+    /// number of triangles that are degenerate in UV or 3D space
+    pub degenerate_triangles: usize,
+}
+impl DistortionStats {
+    pub fn new(triangles: &[Option<TriangleDistortion>]) -> Self {
+        let mut stats = Self::default();
+        for t in triangles {
+            match t {
+                Some(t) => {
+                    stats.mean_stretch += t.stretch * t.area_3d;
+                    stats.mean_angle += t.angle * t.area_3d;
+                    stats.max_stretch = stats.max_stretch.max(t.stretch);
+                    stats.area_voxels += t.area_3d;
+                }
+                None => stats.degenerate_triangles += 1,
+            }
+        }
+        if stats.area_voxels > 0.0 {
+            stats.mean_stretch /= stats.area_voxels;
+            stats.mean_angle /= stats.area_voxels;
+        }
+        stats
+    }
+    /// Surface area in cm² for the given voxel size
+    pub fn area_cm2(&self, voxel_size_um: f64) -> f64 {
+        self.area_voxels * (voxel_size_um * 1e-4).powi(2)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn triangle_distortion() {
+        let xyz = [[0.0, 0.0, 10.0], [10.0, 0.0, 10.0], [0.0, 10.0, 10.0]];
+        let same = TriangleDistortion::new(xyz, [[5.0, 5.0], [15.0, 5.0], [5.0, 15.0]]).unwrap();
+        assert_eq!(
+            (same.area, same.angle, same.stretch, same.area_3d),
+            (1.0, 1.0, 1.0, 50.0)
+        );
+
+        // stretched by 2 along u in 3D space, i.e. one pixel covers two voxels
+        let stretched = TriangleDistortion::new(xyz, [[0.0, 0.0], [5.0, 0.0], [0.0, 10.0]]).unwrap();
+        assert_eq!((stretched.area, stretched.angle, stretched.stretch), (2.0, 2.0, 2.0));
+        assert_eq!(stretched.color(), [255, 0, 0]);
+        // compressed by 2 along v
+        let compressed = TriangleDistortion::new(xyz, [[0.0, 0.0], [10.0, 0.0], [0.0, 20.0]]).unwrap();
+        assert_eq!((compressed.area, compressed.angle, compressed.stretch), (0.5, 2.0, 2.0));
+
+        assert_eq!(TriangleDistortion::new(xyz, [[0.0, 0.0], [1.0, 1.0], [2.0, 2.0]]), None);
+
+        let stats = DistortionStats::new(&[Some(same), Some(stretched), None]);
+        assert_eq!(stats.mean_stretch, 1.5);
+        assert_eq!(stats.max_stretch, 2.0);
+        assert_eq!(stats.area_voxels, 100.0);
+        assert_eq!(stats.degenerate_triangles, 1);
+        assert!((stats.area_cm2(10.0) - 1e-4).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::zarr::ZarrError;
+use std::fmt;
+
+/// Errors that can occur when opening a volume.
+#[derive(Debug)]
+pub enum VolumeError {
+    NotFound(String),
+    Io(String, std::io::Error),
+    // directory that did not contain any usable data
+    NoData(String),
+    // file and reason why it could not be read
+    InvalidFormat(String, String),
+    InvalidTransform(String),
+    Zarr(ZarrError),
+}
+
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VolumeError::NotFound(path) => write!(f, "Data directory {} does not exist", path),
+            VolumeError::Io(path, e) => write!(f, "Failed to access {}: {}", path, e),
+            VolumeError::NoData(path) => write!(f, "No volume data found in {}", path),
+            VolumeError::InvalidFormat(path, reason) => write!(f, "Cannot read {}: {}", path, reason),
+            VolumeError::InvalidTransform(msg) => write!(f, "Invalid transform: {}", msg),
+            VolumeError::Zarr(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for VolumeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VolumeError::Io(_, e) => Some(e),
+            VolumeError::Zarr(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ZarrError> for VolumeError {
+    fn from(e: ZarrError) -> Self {
+        VolumeError::Zarr(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::{LayersMappedVolume, VolumeGrid500Mapped};
+    use crate::zarr::ZarrArray;
+    use std::error::Error;
+
+    #[test]
+    fn constructors_report_typed_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let empty = dir.path().to_str().unwrap();
+        let missing = dir.path().join("missing");
+        let missing = missing.to_str().unwrap();
+
+        assert!(matches!(LayersMappedVolume::from_data_dir(missing), Err(VolumeError::NotFound(p)) if p == missing));
+        assert!(matches!(
+            VolumeGrid500Mapped::from_data_dir(missing),
+            Err(VolumeError::NotFound(_))
+        ));
+        let no_data = LayersMappedVolume::from_data_dir(empty).err().unwrap();
+        assert!(matches!(no_data, VolumeError::NoData(_)));
+        assert_eq!(no_data.to_string(), format!("No volume data found in {}", empty));
+        assert!(matches!(
+            VolumeGrid500Mapped::from_data_dir(empty),
+            Err(VolumeError::NoData(_))
+        ));
+
+        // zarr errors keep their cause
+        let zarr = ZarrArray::<3, u8>::from_path(missing)
+            .map_err(VolumeError::from)
+            .err()
+            .unwrap();
+        assert!(matches!(zarr, VolumeError::Zarr(ZarrError::Io(..))));
+        assert!(zarr.source().is_some());
+
+        std::fs::write(dir.path().join(".zarray"), "{ not json").unwrap();
+        let zarr = ZarrArray::<3, u8>::from_path(empty)
+            .map_err(VolumeError::from)
+            .err()
+            .unwrap();
+        assert!(matches!(zarr, VolumeError::Zarr(ZarrError::InvalidJson(..))));
+        assert!(zarr.to_string().starts_with("Failed to parse"));
+    }
+}
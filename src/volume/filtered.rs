@@ -0,0 +1,497 @@
+use super::{
+    AutoPaintVolume, CacheConfig, CacheStats, PaintVolume, Volume, VolumeCons, VolumeMetadata, VoxelPaintVolume,
+    VoxelVolume,
+};
+use dashmap::DashMap;
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Edge length of the chunks filtered results are computed and cached in
+const CHUNK_SIZE: i64 = 32;
+
+// (chunk no, downsampling)
+type ChunkKey = ([i64; 3], i32);
+// (filtered data, provisional)
+type FilteredChunk = (Arc<Vec<u8>>, bool);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    Gaussian {
+        sigma: f64,
+    },
+    /// median over a cube of `2 * radius + 1` voxels
+    Median {
+        radius: usize,
+    },
+    GradientMagnitude,
+}
+impl FilterKind {
+    pub const DEFAULTS: [FilterKind; 3] = [
+        FilterKind::Gaussian { sigma: 1.5 },
+        FilterKind::Median { radius: 1 },
+        FilterKind::GradientMagnitude,
+    ];
+
+    /// Supported sigma of the Gaussian filter
+    pub const SIGMA_RANGE: RangeInclusive<f64> = 0.3..=8.0;
+    /// Supported radius of the median filter
+    pub const RADIUS_RANGE: RangeInclusive<usize> = 1..=3;
+
+    /// Parses specs like `gaussian:1.5`, `median:2` or `gradient`
+    pub fn from_spec(spec: &str) -> Result<Self, String> {
+        let (name, param) = match spec.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (spec, None),
+        };
+        fn parse_param<T: FromStr + PartialOrd + Display>(
+            param: Option<&str>,
+            name: &str,
+            default: T,
+            range: RangeInclusive<T>,
+        ) -> Result<T, String> {
+            let value = param.map_or(Ok(default), |p| {
+                p.parse::<T>().map_err(|_| format!("invalid {} '{}'", name, p))
+            })?;
+            if range.contains(&value) {
+                Ok(value)
+            } else {
+                Err(format!(
+                    "{} {} out of range, must be between {} and {}",
+                    name,
+                    value,
+                    range.start(),
+                    range.end()
+                ))
+            }
+        }
+        match name {
+            "gaussian" => Ok(FilterKind::Gaussian {
+                sigma: parse_param(param, "sigma", 1.5, Self::SIGMA_RANGE)?,
+            }),
+            "median" => Ok(FilterKind::Median {
+                radius: parse_param(param, "radius", 1, Self::RADIUS_RANGE)?,
+            }),
+            "gradient" => Ok(FilterKind::GradientMagnitude),
+            _ => Err(format!(
+                "unknown filter '{}', use one of gaussian:<sigma>, median:<radius>, gradient",
+                name
+            )),
+        }
+    }
+    pub fn label(&self) -> String {
+        match self {
+            FilterKind::Gaussian { sigma } => format!("Gaussian σ={}", sigma),
+            FilterKind::Median { radius } => format!("Median r={}", radius),
+            FilterKind::GradientMagnitude => "Gradient Magnitude".to_string(),
+        }
+    }
+    pub fn name(&self) -> &str {
+        match self {
+            FilterKind::Gaussian { .. } => "Gaussian",
+            FilterKind::Median { .. } => "Median",
+            FilterKind::GradientMagnitude => "Gradient Magnitude",
+        }
+    }
+    /// Number of voxels around a chunk needed to filter it
+    fn margin(&self) -> i64 {
+        match self {
+            FilterKind::Gaussian { sigma } => (3.0 * sigma).ceil().max(1.0) as i64,
+            FilterKind::Median { radius } => *radius as i64,
+            FilterKind::GradientMagnitude => 1,
+        }
+    }
+
+    /// Filters a cube of `side` voxels, returning the inner cube of `side - 2 * margin` voxels
+    fn apply(&self, input: &[u8], side: usize) -> Vec<u8> {
+        let m = self.margin() as usize;
+        let inner = side - 2 * m;
+        let idx = |x: usize, y: usize, z: usize| (z * side + y) * side + x;
+        let mut out = Vec::with_capacity(inner * inner * inner);
+        match self {
+            FilterKind::Gaussian { sigma } => {
+                let kernel = (-(m as i64)..=m as i64)
+                    .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+                    .collect::<Vec<_>>();
+                let sum = kernel.iter().sum::<f64>();
+                let kernel = kernel.iter().map(|k| k / sum).collect::<Vec<_>>();
+
+                // separable passes along x, y and z, border values are not needed for the inner cube
+                let mut buf = input.iter().map(|v| *v as f64).collect::<Vec<_>>();
+                for (dx, dy, dz) in [(1, 0, 0), (0, 1, 0), (0, 0, 1)] {
+                    let mut next = buf.clone();
+                    for z in m * dz..side - m * dz {
+                        for y in m * dy..side - m * dy {
+                            for x in m * dx..side - m * dx {
+                                next[idx(x, y, z)] = kernel
+                                    .iter()
+                                    .enumerate()
+                                    .map(|(k, w)| {
+                                        w * buf[idx(x + k * dx - m * dx, y + k * dy - m * dy, z + k * dz - m * dz)]
+                                    })
+                                    .sum();
+                            }
+                        }
+                    }
+                    buf = next;
+                }
+                for z in m..m + inner {
+                    for y in m..m + inner {
+                        for x in m..m + inner {
+                            out.push(buf[idx(x, y, z)].round().clamp(0.0, 255.0) as u8);
+                        }
+                    }
+                }
+            }
+            FilterKind::Median { .. } => {
+                let mut window = Vec::with_capacity((2 * m + 1).pow(3));
+                for z in m..m + inner {
+                    for y in m..m + inner {
+                        for x in m..m + inner {
+                            window.clear();
+                            for wz in z - m..=z + m {
+                                for wy in y - m..=y + m {
+                                    window.extend_from_slice(&input[idx(x - m, wy, wz)..=idx(x + m, wy, wz)]);
+                                }
+                            }
+                            let mid = window.len() / 2;
+                            out.push(*window.select_nth_unstable(mid).1);
+                        }
+                    }
+                }
+            }
+            FilterKind::GradientMagnitude => {
+                for z in 1..1 + inner {
+                    for y in 1..1 + inner {
+                        for x in 1..1 + inner {
+                            let d = |a: usize, b: usize| input[a] as f64 - input[b] as f64;
+                            let gx = d(idx(x + 1, y, z), idx(x - 1, y, z)) / 2.0;
+                            let gy = d(idx(x, y + 1, z), idx(x, y - 1, z)) / 2.0;
+                            let gz = d(idx(x, y, z + 1), idx(x, y, z - 1)) / 2.0;
+                            out.push((gx * gx + gy * gy + gz * gz).sqrt().min(255.0) as u8);
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+struct FilterCacheEntry {
+    data: Arc<Vec<u8>>,
+    // computed from incomplete base data (e.g. tiles still downloading), dropped before the next painting
+    provisional: bool,
+    last_access: AtomicU64,
+}
+
+/// Filtered chunks shared between all clones of a `FilteredVolume`
+struct FilterCache {
+    entries: DashMap<ChunkKey, FilterCacheEntry>,
+    config: CacheConfig,
+    access_counter: AtomicU64,
+    bytes: AtomicUsize,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+impl FilterCache {
+    fn new(config: CacheConfig) -> Self {
+        Self {
+            entries: DashMap::new(),
+            config,
+            access_counter: AtomicU64::new(0),
+            bytes: AtomicUsize::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+    fn get_or_compute(&self, key: ChunkKey, compute: impl FnOnce() -> (Vec<u8>, bool)) -> FilteredChunk {
+        let counter = self.access_counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(e) = self.entries.get(&key) {
+            e.last_access.store(counter, Ordering::Relaxed);
+            return (e.data.clone(), e.provisional);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let (data, provisional) = compute();
+        let data = Arc::new(data);
+        self.bytes.fetch_add(data.len(), Ordering::Relaxed);
+        if let Some(old) = self.entries.insert(
+            key,
+            FilterCacheEntry {
+                data: data.clone(),
+                provisional,
+                last_access: AtomicU64::new(counter),
+            },
+        ) {
+            // computed concurrently by another thread
+            self.bytes.fetch_sub(old.data.len(), Ordering::Relaxed);
+        }
+        if self.entries.len() > self.config.max_entries || self.bytes.load(Ordering::Relaxed) > self.config.max_bytes {
+            self.purge();
+        }
+        (data, provisional)
+    }
+    fn purge(&self) {
+        // purge oldest 20% of entries
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|e| (*e.key(), e.last_access.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, last_access)| *last_access);
+        let n = entries.len() / 5 + 1;
+        for (k, _) in entries.into_iter().take(n) {
+            if let Some((_, e)) = self.entries.remove(&k) {
+                self.bytes.fetch_sub(e.data.len(), Ordering::Relaxed);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    fn purge_provisional(&self) {
+        self.entries.retain(|_, e| {
+            if e.provisional {
+                self.bytes.fetch_sub(e.data.len(), Ordering::Relaxed);
+            }
+            !e.provisional
+        });
+    }
+    fn stats(&self) -> CacheStats {
+        let accesses = self.access_counter.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheStats {
+            entries: self.entries.len(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            hits: accesses.saturating_sub(misses),
+            misses,
+            evictions: self.evictions.load(Ordering::Relaxed),
+            config: Some(self.config),
+        }
+    }
+}
+
+/// Shows a filtered version of a base volume, e.g. to look at a denoised or edge-enhanced scan.
+///
+/// Filtering is done lazily in chunks of 32³ voxels per resolution level, which are cached.
+pub struct FilteredVolume {
+    base: Volume,
+    filter: FilterKind,
+    cache: Arc<FilterCache>,
+    last_chunk: RefCell<Option<(ChunkKey, FilteredChunk)>>,
+}
+impl FilteredVolume {
+    pub fn new(base: Volume, filter: FilterKind) -> Self {
+        Self {
+            base,
+            filter,
+            cache: Arc::new(FilterCache::new(CacheConfig::default())),
+            last_chunk: RefCell::new(None),
+        }
+    }
+    pub fn with_cache_config(mut self, config: CacheConfig) -> Self {
+        self.cache = Arc::new(FilterCache::new(config));
+        *self.last_chunk.borrow_mut() = None;
+        self
+    }
+    pub fn filter(&self) -> FilterKind {
+        self.filter
+    }
+
+    fn compute_chunk(&self, chunk: [i64; 3], downsampling: i32) -> (Vec<u8>, bool) {
+        let m = self.filter.margin();
+        let side = (CHUNK_SIZE + 2 * m) as usize;
+        let [x0, y0, z0] = chunk.map(|c| c * CHUNK_SIZE - m);
+        let mut input = Vec::with_capacity(side * side * side);
+        // results are provisional if some of the base data was not available yet
+        let mut provisional = false;
+        for z in 0..side as i64 {
+            for y in 0..side as i64 {
+                for x in 0..side as i64 {
+                    let xyz = [(x0 + x) as f64, (y0 + y) as f64, (z0 + z) as f64];
+                    input.push(if xyz.iter().any(|c| *c < 0.0) {
+                        0
+                    } else {
+                        self.base.try_get(xyz, downsampling).unwrap_or_else(|| {
+                            provisional = true;
+                            0
+                        })
+                    });
+                }
+            }
+        }
+        (self.filter.apply(&input, side), provisional)
+    }
+    /// The filtered value and whether it was computed from incomplete base data
+    fn filtered(&self, xyz: [f64; 3], downsampling: i32) -> (u8, bool) {
+        if xyz.iter().any(|c| *c < 0.0) {
+            return (0, false);
+        }
+        let [x, y, z] = xyz.map(|c| c as i64);
+        let key = ([x, y, z].map(|c| c / CHUNK_SIZE), downsampling);
+        let idx = (((z % CHUNK_SIZE) * CHUNK_SIZE + y % CHUNK_SIZE) * CHUNK_SIZE + x % CHUNK_SIZE) as usize;
+
+        let mut last = self.last_chunk.borrow_mut();
+        match last.as_ref() {
+            Some((last_key, (data, provisional))) if *last_key == key => (data[idx], *provisional),
+            _ => {
+                let (data, provisional) = self
+                    .cache
+                    .get_or_compute(key, || self.compute_chunk(key.0, downsampling));
+                let v = data[idx];
+                *last = Some((key, (data, provisional)));
+                (v, provisional)
+            }
+        }
+    }
+}
+impl VoxelVolume for FilteredVolume {
+    fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        self.filtered(xyz, downsampling).0
+    }
+    fn try_get(&self, xyz: [f64; 3], downsampling: i32) -> Option<u8> {
+        let (v, provisional) = self.filtered(xyz, downsampling);
+        (!provisional).then_some(v)
+    }
+    fn reset_for_painting(&self) {
+        self.base.reset_for_painting();
+        self.cache.purge_provisional();
+        *self.last_chunk.borrow_mut() = None;
+    }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        self.base.metadata()
+    }
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.cache.stats())
+    }
+}
+
+impl AutoPaintVolume for FilteredVolume {
+    fn shared_volume(&self) -> VolumeCons {
+        let base = self.base.shared();
+        let filter = self.filter;
+        let cache = self.cache.clone();
+        Box::new(move || {
+            FilteredVolume {
+                base: base(),
+                filter,
+                cache,
+                last_chunk: RefCell::new(None),
+            }
+            .into_volume()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::{DrawingConfig, Image, SyntheticPattern, SyntheticVolume};
+    use egui::Color32;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn filters_on_chunk_borders() {
+        let base = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 0 }, [256; 3]).into_volume();
+        let gaussian = FilteredVolume::new(base.clone(), FilterKind::Gaussian { sigma: 1.0 });
+        let median = FilteredVolume::new(base.clone(), FilterKind::Median { radius: 1 });
+        let gradient = FilteredVolume::new(base.clone(), FilterKind::GradientMagnitude);
+
+        // a linear ramp is preserved by smoothing and the median, also across chunk borders
+        for x in [30.0, 31.0, 32.0, 33.0, 100.0] {
+            let xyz = [x, 40.0, 70.0];
+            assert_eq!(gaussian.get(xyz, 1), base.get(xyz, 1));
+            assert_eq!(median.get(xyz, 1), base.get(xyz, 1));
+            assert_eq!(gradient.get(xyz, 1), 1);
+        }
+        assert_eq!(gaussian.cache_stats().unwrap().misses, 3);
+
+        // step between dark and bright cells
+        let checkerboard = SyntheticVolume::new(SyntheticPattern::Checkerboard { size: 8 }, [64; 3]).into_volume();
+        let gradient = FilteredVolume::new(checkerboard, FilterKind::GradientMagnitude);
+        assert_eq!(gradient.get([4.0, 4.0, 4.0], 1), 0);
+        assert!(gradient.get([8.0, 4.0, 4.0], 1) > 50);
+
+        assert_eq!(FilterKind::from_spec("median:2"), Ok(FilterKind::Median { radius: 2 }));
+        assert_eq!(
+            FilterKind::from_spec("gaussian"),
+            Ok(FilterKind::Gaussian { sigma: 1.5 })
+        );
+        assert!(FilterKind::from_spec("sobel").is_err());
+        assert_eq!(
+            FilterKind::from_spec("gaussian:20"),
+            Err("sigma 20 out of range, must be between 0.3 and 8".to_string())
+        );
+        assert_eq!(
+            FilterKind::from_spec("median:5"),
+            Err("radius 5 out of range, must be between 1 and 3".to_string())
+        );
+        assert!(FilterKind::from_spec("median:1.5").is_err());
+    }
+
+    /// Zero everywhere, with data below x = 32 only available after `loaded` is set
+    #[derive(Clone)]
+    struct Downloading {
+        loaded: Arc<AtomicBool>,
+    }
+    impl VoxelVolume for Downloading {
+        fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+            self.try_get(xyz, downsampling).unwrap_or(0)
+        }
+        fn try_get(&self, xyz: [f64; 3], _downsampling: i32) -> Option<u8> {
+            (xyz[0] >= 32.0 || self.loaded.load(Ordering::Relaxed)).then_some(0)
+        }
+    }
+    impl AutoPaintVolume for Downloading {
+        fn shared_volume(&self) -> VolumeCons {
+            let volume = self.clone();
+            Box::new(move || volume.into_volume())
+        }
+    }
+
+    #[test]
+    fn recomputes_chunks_with_missing_data() {
+        let loaded = Arc::new(AtomicBool::new(false));
+        let base = Downloading { loaded: loaded.clone() }.into_volume();
+        let filtered = FilteredVolume::new(base, FilterKind::Median { radius: 1 });
+
+        // black voxels are data as well, only chunks with missing data are recomputed
+        filtered.get([40.0, 40.0, 40.0], 1);
+        filtered.get([100.0, 40.0, 40.0], 1);
+        filtered.get([10.0, 40.0, 40.0], 1);
+        assert_eq!(filtered.cache_stats().unwrap().entries, 3);
+        filtered.reset_for_painting();
+        assert_eq!(filtered.cache_stats().unwrap().entries, 1);
+
+        loaded.store(true, Ordering::Relaxed);
+        filtered.get([40.0, 40.0, 40.0], 1);
+        filtered.get([10.0, 40.0, 40.0], 1);
+        filtered.reset_for_painting();
+        assert_eq!(filtered.cache_stats().unwrap().entries, 3);
+    }
+
+    #[test]
+    fn keeps_coarser_data_while_loading() {
+        let loaded = Arc::new(AtomicBool::new(false));
+        let base = Downloading { loaded: loaded.clone() }.into_volume();
+        let config = CacheConfig {
+            max_entries: 10,
+            max_bytes: 1 << 20,
+        };
+        let filtered = FilteredVolume::new(base, FilterKind::Median { radius: 1 }).with_cache_config(config);
+        assert_eq!(filtered.cache_stats().unwrap().config, Some(config));
+
+        assert_eq!(filtered.try_get([10.0, 40.0, 40.0], 1), None);
+        assert_eq!(filtered.try_get([100.0, 40.0, 40.0], 1), Some(0));
+        let mut image = Image::new_from_color(4, 4, Color32::from_gray(7));
+        filtered.paint([10, 40, 40], 0, 1, 2, 4, 4, 1, 1, &DrawingConfig::default(), &mut image);
+        assert!(image.data.iter().all(|c| *c == Color32::from_gray(7)));
+
+        loaded.store(true, Ordering::Relaxed);
+        filtered.reset_for_painting();
+        filtered.paint([10, 40, 40], 0, 1, 2, 4, 4, 1, 1, &DrawingConfig::default(), &mut image);
+        assert!(image.data.iter().all(|c| *c == Color32::from_gray(0)));
+    }
+}
@@ -0,0 +1,333 @@
+use nalgebra::{Matrix3, SymmetricEigen, Vector3};
+use std::collections::HashMap;
+
+/// Computes a low-distortion UV map for a triangle mesh using least squares conformal maps (LSCM, Lévy et al. 2002).
+///
+/// UVs are given in voxels, i.e. the area of the flattened surface matches the area of the mesh. Each connected part
+/// of the mesh is flattened separately and the parts are placed next to each other along u. Vertices not used by any
+/// triangle end up at 0/0.
+pub fn lscm(vertices: &[[f64; 3]], triangles: &[[usize; 3]]) -> Vec<[f64; 2]> {
+    let mut uvs = vec![[0.0; 2]; vertices.len()];
+    let mut offset_u = 0.0;
+    for (component_vertices, component_triangles) in components(vertices.len(), triangles) {
+        let flattened = flatten_component(vertices, &component_vertices, &component_triangles);
+
+        let min_u = flattened.iter().map(|uv| uv[0]).fold(f64::INFINITY, f64::min);
+        let max_u = flattened.iter().map(|uv| uv[0]).fold(f64::NEG_INFINITY, f64::max);
+        let min_v = flattened.iter().map(|uv| uv[1]).fold(f64::INFINITY, f64::min);
+        for (i, uv) in component_vertices.iter().zip(flattened) {
+            uvs[*i] = [uv[0] - min_u + offset_u, uv[1] - min_v];
+        }
+        offset_u += max_u - min_u + 10.0;
+    }
+    uvs
+}
+
+/// Splits the mesh into connected parts, returns the vertices and triangles of each part
+fn components(num_vertices: usize, triangles: &[[usize; 3]]) -> Vec<(Vec<usize>, Vec<[usize; 3]>)> {
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    let mut parent = (0..num_vertices).collect::<Vec<_>>();
+    for t in triangles {
+        for k in 1..3 {
+            let (a, b) = (find(&mut parent, t[0]), find(&mut parent, t[k]));
+            parent[a] = b;
+        }
+    }
+
+    let mut component_of_root = vec![usize::MAX; num_vertices];
+    let mut components: Vec<(Vec<usize>, Vec<[usize; 3]>)> = vec![];
+    let mut seen = vec![false; num_vertices];
+    for t in triangles {
+        let root = find(&mut parent, t[0]);
+        if component_of_root[root] == usize::MAX {
+            component_of_root[root] = components.len();
+            components.push((vec![], vec![]));
+        }
+        let (vertices, triangles) = &mut components[component_of_root[root]];
+        triangles.push(*t);
+        for i in t {
+            if !seen[*i] {
+                seen[*i] = true;
+                vertices.push(*i);
+            }
+        }
+    }
+    components
+}
+
+/// Flattens a connected part of the mesh, returns the UVs of `vertices`
+fn flatten_component(all_vertices: &[[f64; 3]], vertices: &[usize], triangles: &[[usize; 3]]) -> Vec<[f64; 2]> {
+    let local = vertices
+        .iter()
+        .enumerate()
+        .map(|(k, i)| (*i, k))
+        .collect::<HashMap<_, _>>();
+    let points = vertices
+        .iter()
+        .map(|i| Vector3::from(all_vertices[*i]))
+        .collect::<Vec<_>>();
+    let triangles = triangles.iter().map(|t| t.map(|i| local[&i])).collect::<Vec<_>>();
+
+    // start from a projection onto the best fitting plane, which is also used to choose the pinned vertices
+    let mut uvs = plane_projection(&points, &triangles);
+    let pin_min = (0..uvs.len()).min_by(|a, b| uvs[*a][0].total_cmp(&uvs[*b][0])).unwrap();
+    let pin_max = (0..uvs.len()).max_by(|a, b| uvs[*a][0].total_cmp(&uvs[*b][0])).unwrap();
+    if pin_min == pin_max {
+        return uvs;
+    }
+
+    // two unknowns (u and v) per vertex that is not pinned
+    let mut free_index = vec![usize::MAX; uvs.len()];
+    let mut num_free = 0;
+    for (k, index) in free_index.iter_mut().enumerate() {
+        if k != pin_min && k != pin_max {
+            *index = num_free;
+            num_free += 1;
+        }
+    }
+
+    // Two equations per triangle for the Cauchy-Riemann conditions u_x = v_y and u_y = -v_x in a local frame of the
+    // triangle, weighted by the square root of the triangle area. Pinned vertices are moved to the right hand side.
+    let mut matrix = SparseRows::default();
+    let mut rhs = vec![];
+    let mut area_3d = 0.0;
+    for t in triangles.iter() {
+        let [p0, p1, p2] = t.map(|k| points[k]);
+        let e01 = p1 - p0;
+        let e02 = p2 - p0;
+        let normal = e01.cross(&e02);
+        let area = normal.norm() / 2.0;
+        if area < 1e-12 || e01.norm() < 1e-12 {
+            continue;
+        }
+        area_3d += area;
+        let x_axis = e01.normalize();
+        let y_axis = normal.normalize().cross(&x_axis);
+        let q = [[0.0, 0.0], [e01.norm(), 0.0], [e02.dot(&x_axis), e02.dot(&y_axis)]];
+
+        let weight = 1.0 / (2.0 * area.sqrt());
+        let mut rows = [vec![], vec![]];
+        let mut b = [0.0, 0.0];
+        for j in 0..3 {
+            // gradient of the linear basis function of vertex j, scaled by the weight
+            let e = [
+                q[(j + 2) % 3][0] - q[(j + 1) % 3][0],
+                q[(j + 2) % 3][1] - q[(j + 1) % 3][1],
+            ];
+            let g = [-e[1] * weight, e[0] * weight];
+            let k = t[j];
+            let coefficients = [[g[0], -g[1]], [g[1], g[0]]];
+            for (row, c) in coefficients.iter().enumerate() {
+                if free_index[k] == usize::MAX {
+                    b[row] -= c[0] * uvs[k][0] + c[1] * uvs[k][1];
+                } else {
+                    rows[row].push((2 * free_index[k], c[0]));
+                    rows[row].push((2 * free_index[k] + 1, c[1]));
+                }
+            }
+        }
+        for (row, b) in rows.into_iter().zip(b) {
+            matrix.push(row);
+            rhs.push(b);
+        }
+    }
+
+    let mut x = vec![0.0; 2 * num_free];
+    for (k, index) in free_index.iter().enumerate() {
+        if *index != usize::MAX {
+            x[2 * index] = uvs[k][0];
+            x[2 * index + 1] = uvs[k][1];
+        }
+    }
+    least_squares(&matrix, &rhs, &mut x);
+    for (k, index) in free_index.iter().enumerate() {
+        if *index != usize::MAX {
+            uvs[k] = [x[2 * index], x[2 * index + 1]];
+        }
+    }
+
+    // the pinned vertices fix rotation and scale, scale to match the area of the mesh
+    let area_uv: f64 = triangles.iter().map(|t| signed_area(t.map(|k| uvs[k]))).sum();
+    if area_uv > 0.0 {
+        let scale = (area_3d / area_uv).sqrt();
+        uvs.iter_mut().for_each(|uv| *uv = uv.map(|c| c * scale));
+    }
+    uvs
+}
+
+/// Orthogonal projection onto the plane spanned by the two main axes of the points, oriented so that triangles keep
+/// their winding
+fn plane_projection(points: &[Vector3<f64>], triangles: &[[usize; 3]]) -> Vec<[f64; 2]> {
+    let center = points.iter().sum::<Vector3<f64>>() / points.len() as f64;
+    let covariance = points
+        .iter()
+        .map(|p| (p - center) * (p - center).transpose())
+        .sum::<Matrix3<f64>>();
+    let eigen = SymmetricEigen::new(covariance);
+    let mut axes = [0, 1, 2];
+    axes.sort_by(|a, b| eigen.eigenvalues[*b].total_cmp(&eigen.eigenvalues[*a]));
+    let u_axis = eigen.eigenvectors.column(axes[0]).into_owned();
+    let mut v_axis = eigen.eigenvectors.column(axes[1]).into_owned();
+
+    let project = |v_axis: &Vector3<f64>| {
+        points
+            .iter()
+            .map(|p| [(p - center).dot(&u_axis), (p - center).dot(v_axis)])
+            .collect::<Vec<_>>()
+    };
+    let uvs = project(&v_axis);
+    let area: f64 = triangles.iter().map(|t| signed_area(t.map(|k| uvs[k]))).sum();
+    if area < 0.0 {
+        v_axis = -v_axis;
+        project(&v_axis)
+    } else {
+        uvs
+    }
+}
+
+fn signed_area([a, b, c]: [[f64; 2]; 3]) -> f64 {
+    ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])) / 2.0
+}
+
+#[derive(Default)]
+struct SparseRows {
+    start: Vec<usize>,
+    entries: Vec<(usize, f64)>,
+}
+impl SparseRows {
+    fn push(&mut self, row: Vec<(usize, f64)>) {
+        self.start.push(self.entries.len());
+        self.entries.extend(row);
+    }
+    fn row(&self, i: usize) -> &[(usize, f64)] {
+        let end = self.start.get(i + 1).copied().unwrap_or(self.entries.len());
+        &self.entries[self.start[i]..end]
+    }
+    fn mul(&self, x: &[f64]) -> Vec<f64> {
+        (0..self.start.len())
+            .map(|i| self.row(i).iter().map(|(c, v)| v * x[*c]).sum())
+            .collect()
+    }
+    fn mul_transposed(&self, y: &[f64], columns: usize) -> Vec<f64> {
+        let mut res = vec![0.0; columns];
+        for (i, y) in y.iter().enumerate() {
+            for (c, v) in self.row(i) {
+                res[*c] += v * y;
+            }
+        }
+        res
+    }
+}
+
+/// Minimizes |Ax - b| with conjugate gradients on the normal equations (CGLS), starting from the given `x`
+fn least_squares(a: &SparseRows, b: &[f64], x: &mut [f64]) {
+    const MAX_ITERATIONS: usize = 5000;
+    const TOLERANCE: f64 = 1e-7;
+    fn dot(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(a, b)| a * b).sum()
+    }
+
+    let ax = a.mul(x);
+    let mut r = b.iter().zip(ax).map(|(b, ax)| b - ax).collect::<Vec<_>>();
+    let mut s = a.mul_transposed(&r, x.len());
+    let mut p = s.clone();
+    let mut gamma = dot(&s, &s);
+    let gamma0 = gamma;
+    for _ in 0..MAX_ITERATIONS {
+        if gamma <= TOLERANCE * TOLERANCE * gamma0 || gamma == 0.0 {
+            break;
+        }
+        let q = a.mul(&p);
+        let qq = dot(&q, &q);
+        if qq == 0.0 {
+            break;
+        }
+        let alpha = gamma / qq;
+        x.iter_mut().zip(&p).for_each(|(x, p)| *x += alpha * p);
+        r.iter_mut().zip(&q).for_each(|(r, q)| *r -= alpha * q);
+        s = a.mul_transposed(&r, x.len());
+        let gamma_new = dot(&s, &s);
+        let beta = gamma_new / gamma;
+        gamma = gamma_new;
+        p.iter_mut().zip(&s).for_each(|(p, s)| *p = s + beta * *p);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::test_segment::{self, HEIGHT, WIDTH};
+    use crate::volume::{EmptyVolume, ObjVolume, ProjectionKind, VoxelPaintVolume};
+    use std::sync::Arc;
+
+    #[test]
+    fn flatten_half_cylinder() {
+        // half a cylinder with radius 50 and length 100, unrolls into a 157 x 100 rectangle
+        let (cols, rows) = (33, 11);
+        let vertices = (0..rows)
+            .flat_map(|r| {
+                (0..cols).map(move |c| {
+                    let angle = std::f64::consts::PI * c as f64 / (cols - 1) as f64;
+                    [100.0 + 50.0 * angle.cos(), 100.0 + 50.0 * angle.sin(), r as f64 * 10.0]
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut triangles = vec![];
+        for r in 0..rows - 1 {
+            for c in 0..cols - 1 {
+                let i = r * cols + c;
+                triangles.push([i, i + 1, i + cols]);
+                triangles.push([i + 1, i + cols + 1, i + cols]);
+            }
+        }
+
+        let uvs = lscm(&vertices, &triangles);
+        let distance = |a: usize, b: usize| ((uvs[a][0] - uvs[b][0]).powi(2) + (uvs[a][1] - uvs[b][1]).powi(2)).sqrt();
+        let arc = std::f64::consts::PI * 50.0;
+        let corner = |c: usize, r: usize| r * cols + c;
+        assert!((distance(corner(0, 0), corner(cols - 1, 0)) - arc).abs() < 2.0);
+        assert!((distance(corner(0, 0), corner(0, rows - 1)) - 100.0).abs() < 2.0);
+        assert!((distance(corner(0, 0), corner(cols - 1, rows - 1)) - (arc * arc + 100.0 * 100.0).sqrt()).abs() < 2.0);
+        assert!(triangles.iter().all(|t| signed_area(t.map(|i| uvs[i])) > 0.0));
+
+        // a second, disconnected copy is placed next to the first one
+        let shifted = vertices.iter().map(|v| [v[0] + 500.0, v[1], v[2]]);
+        let vertices = vertices.iter().copied().chain(shifted).collect::<Vec<_>>();
+        let n = cols * rows;
+        let triangles = triangles
+            .iter()
+            .copied()
+            .chain(triangles.iter().map(|t| t.map(|i| i + n)))
+            .collect::<Vec<_>>();
+        let uvs = lscm(&vertices, &triangles);
+        let max_u = |range: std::ops::Range<usize>| range.map(|i| uvs[i][0]).fold(f64::NEG_INFINITY, f64::max);
+        let min_u = (n..2 * n).map(|i| uvs[i][0]).fold(f64::INFINITY, f64::min);
+        assert!(min_u > max_u(0..n));
+    }
+
+    #[test]
+    fn flatten_obj_without_uvs() {
+        // the test segment without texture coordinates and normals
+        let mut obj = String::new();
+        for [x, y, z, _, _] in test_segment::vertices() {
+            obj += &format!("v {} {} {}\n", x, y, z);
+        }
+        for [a, b, c, d] in test_segment::quads().into_iter().map(|q| q.map(|i| i + 1)) {
+            obj += &format!("f {} {} {}\nf {} {} {}\n", a, b, d, b, c, d);
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("segment.obj");
+        std::fs::write(&path, obj).unwrap();
+
+        let obj = ObjVolume::load_obj(path.to_str().unwrap(), &None, ProjectionKind::None).unwrap();
+        assert_eq!(obj.flattened_size(), Some([WIDTH, HEIGHT]));
+        let volume = ObjVolume::new(Arc::new(obj), EmptyVolume {}.into_volume(), WIDTH, HEIGHT);
+        test_segment::assert_volume_coords(|uvw| volume.convert_to_volume_coords(uvw));
+    }
+}
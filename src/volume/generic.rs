@@ -1,7 +1,10 @@
-use super::{DrawingConfig, Image, PaintVolume, VoxelVolume};
+use super::{DrawingConfig, Image, PaintVolume, VolumeCons, VoxelVolume};
 
 // marker trait for volumes that do not want to provide a specific PaintVolume implementation
-pub trait AutoPaintVolume {}
+pub trait AutoPaintVolume {
+    /// See [`PaintVolume::shared`]
+    fn shared_volume(&self) -> VolumeCons;
+}
 
 impl<T: VoxelVolume + AutoPaintVolume> PaintVolume for T {
     fn paint(
@@ -29,12 +32,14 @@ impl<T: VoxelVolume + AutoPaintVolume> PaintVolume for T {
                 uvw[v_coord] = (xyz[v_coord] + im_rel_v) as f64 / fi32;
                 uvw[plane_coord] = (xyz[plane_coord]) as f64 / fi32;
 
-                let v = self.get(uvw, _sfactor as i32);
-                buffer.set_gray(im_u, im_v, v);
+                // keep what lower resolutions have painted if data is not available (yet)
+                if let Some(v) = self.try_get(uvw, _sfactor as i32) {
+                    buffer.set_gray(im_u, im_v, v);
+                }
             }
         }
     }
-    fn shared(&self) -> super::VolumeCons {
-        todo!()
+    fn shared(&self) -> VolumeCons {
+        self.shared_volume()
     }
 }
@@ -1,44 +1,34 @@
-use std::io::{Read, Seek};
-
-use super::{AutoPaintVolume, VoxelVolume};
-
-struct Cell {
-    data: memmap::Mmap,
-    strip_spacing: usize,
-}
-impl Cell {
-    fn get(&self, x: usize, y: usize, z: usize) -> u8 {
-        let off = self.strip_spacing * z + (y * 500 + x) * 2;
-
-        // off + 1 because we select the higher order bits of little endian 16 bit values
-        if off + 1 >= self.data.len() {
-            0
-        } else {
-            self.data[off + 1]
-        }
-    }
-}
+use super::tiffpages::{TiffChunkCache, TiffPages};
+use super::{
+    AutoPaintVolume, CacheConfig, DataType, VolumeCons, VolumeError, VolumeMetadata, VoxelPaintVolume, VoxelVolume,
+};
+use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct VolumeGrid500Mapped {
     max_x: usize,
     max_y: usize,
     max_z: usize,
-    data: Vec<Vec<Vec<Option<Cell>>>>,
+    data: Arc<Vec<Vec<Vec<Option<TiffPages>>>>>,
+    // decoded chunks of compressed pages, shared by all files of the volume
+    cache: Arc<TiffChunkCache>,
 }
 impl VolumeGrid500Mapped {
-    pub fn from_data_dir(data_dir: &str) -> Self {
-        use memmap::MmapOptions;
-        use std::fs::File;
-
+    pub fn from_data_dir(data_dir: &str) -> Result<Self, VolumeError> {
         // find highest xyz values for files in data_dir named like this format: format!("{}/cell_yxz_{:03}_{:03}_{:03}.tif", data_dir, y, x, z);
         // use regex to match file names
         let mut max_x = 0;
         let mut max_y = 0;
         let mut max_z = 0;
-        for entry in std::fs::read_dir(data_dir).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            let file_name = path.file_name().unwrap().to_str().unwrap();
+        if !std::path::Path::new(data_dir).exists() {
+            return Err(VolumeError::NotFound(data_dir.to_string()));
+        }
+        let io_error = |e| VolumeError::Io(data_dir.to_string(), e);
+        for entry in std::fs::read_dir(data_dir).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            let Some(file_name) = path.file_name().and_then(|f| f.to_str()) else {
+                continue;
+            };
             if let Some(captures) = regex::Regex::new(r"cell_yxz_(\d+)_(\d+)_(\d+)\.tif")
                 .unwrap()
                 .captures(file_name)
@@ -58,69 +48,23 @@ impl VolumeGrid500Mapped {
                 }
             }
         }
-        fn cell_for(data_dir: &str, x: usize, y: usize, z: usize) -> Option<Cell> {
+        let cache = Arc::new(TiffChunkCache::new(CacheConfig::default()));
+        let cell_for = |x: usize, y: usize, z: usize| {
             let file_name = format!("{}/cell_yxz_{:03}_{:03}_{:03}.tif", data_dir, y, x, z);
-
-            let file = File::open(file_name.clone()).ok()?;
-            let mut decoder = tiff::decoder::Decoder::new(file).ok()?;
-
-            use tiff::tags::Tag;
-            fn assume<T: Seek + Read>(decoder: &mut tiff::decoder::Decoder<T>, tag: Tag, value: u32) -> Option<()> {
-                if let Ok(v) = decoder.get_tag_u32(tag) {
-                    if v != value {
-                        println!("Expected {:?} to be {} but was {}", tag, value, v);
-                        None
-                    } else {
-                        Some(())
-                    }
-                } else {
-                    // ok if not found
-                    Some(())
-                }
-            }
-
-            assume(&mut decoder, Tag::ImageWidth, 500)?;
-            assume(&mut decoder, Tag::ImageLength, 500)?;
-            assume(&mut decoder, Tag::BitsPerSample, 16)?;
-            assume(&mut decoder, Tag::Compression, 1 /* None */)?;
-            assume(&mut decoder, Tag::PlanarConfiguration, 2 /* Planar */)?;
-
-            // get offsets
-            let offsets = decoder.get_tag_u32_vec(Tag::StripOffsets).ok()?;
-            if offsets.len() != 1 {
-                println!("Expected 1 strip offset: {}", offsets.len());
+            let cell = TiffPages::open(&file_name, &cache)?;
+            if cell.width != 500 || cell.height != 500 {
+                println!(
+                    "Expected 500x500 cell but got {}x{} in {}",
+                    cell.width, cell.height, file_name
+                );
                 return None;
             }
-            let first_offset = offsets[0] as usize;
-
-            decoder.next_image().ok()?;
-            let next_offset = decoder.get_tag_u32_vec(Tag::StripOffsets).ok()?[0] as usize;
-            let spacing = next_offset - first_offset;
-            // FIXME: we still assume that the spacing is the same between all layers (i.e. images inside of the same file)
-
-            let file = File::open(file_name).ok()?;
-            if let Ok(mmap) = unsafe { MmapOptions::new().offset(first_offset as u64).map(&file) } {
-                Some(Cell {
-                    data: mmap,
-                    strip_spacing: spacing,
-                })
-            } else {
-                None
-            }
-        }
-        if !std::path::Path::new(data_dir).exists() {
-            println!("Data directory {} does not exist", data_dir);
-            return Self {
-                max_x: 0,
-                max_y: 0,
-                max_z: 0,
-                data: vec![],
-            };
-        }
-        let data: Vec<Vec<Vec<Option<Cell>>>> = (1..=max_z)
+            Some(cell)
+        };
+        let data: Vec<Vec<Vec<Option<TiffPages>>>> = (1..=max_z)
             .map(|z| {
                 (1..=max_y)
-                    .map(|y| (1..=max_x).map(|x| cell_for(data_dir, x, y, z)).collect())
+                    .map(|y| (1..=max_x).map(|x| cell_for(x, y, z)).collect())
                     .collect()
             })
             .collect();
@@ -128,17 +72,44 @@ impl VolumeGrid500Mapped {
         // count number of slices found
         let slices_found = data.iter().flatten().flatten().flatten().count();
         println!("Found {} cells in {}", slices_found, data_dir);
+        if slices_found == 0 {
+            return Err(VolumeError::NoData(data_dir.to_string()));
+        }
         println!("max_x: {}, max_y: {}, max_z: {}", max_x, max_y, max_z);
 
-        Self {
+        Ok(Self {
             max_x: max_x - 1,
             max_y: max_y - 1,
             max_z: max_z - 1,
-            data,
-        }
+            data: Arc::new(data),
+            cache,
+        })
+    }
+
+    /// Limits the decoded data of compressed TIFF pages kept in memory
+    pub fn with_cache_config(self, config: CacheConfig) -> Self {
+        self.cache.set_config(config);
+        self
     }
 }
 impl VoxelVolume for VolumeGrid500Mapped {
+    fn reset_for_painting(&self) {
+        self.cache.forget_failures();
+    }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        if self.data.is_empty() {
+            None
+        } else {
+            let shape = [(self.max_x + 1) * 500, (self.max_y + 1) * 500, (self.max_z + 1) * 500];
+            let cells = || self.data.iter().flatten().flatten().flatten();
+            let mut metadata = match cells().next() {
+                Some(cell) => cell.metadata(shape),
+                None => VolumeMetadata::new(shape, DataType::Unknown),
+            };
+            metadata.data_range = TiffPages::combined_data_range(cells());
+            Some(metadata)
+        }
+    }
     fn get(&self, _xyz: [f64; 3], downsampling: i32) -> u8 {
         let xyz = [
             _xyz[0] as i32 * downsampling,
@@ -164,4 +135,9 @@ impl VoxelVolume for VolumeGrid500Mapped {
     }
 }
 
-impl AutoPaintVolume for VolumeGrid500Mapped {}
+impl AutoPaintVolume for VolumeGrid500Mapped {
+    fn shared_volume(&self) -> VolumeCons {
+        let volume = self.clone();
+        Box::new(move || volume.into_volume())
+    }
+}
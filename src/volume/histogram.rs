@@ -0,0 +1,120 @@
+use super::VoxelVolume;
+
+/// Distribution of the values of a volume. Value 0 is treated as missing data and not counted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub bins: [u64; 256],
+    pub total: u64,
+    /// samples that were skipped because their data was not available yet
+    pub missing: u64,
+}
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            bins: [0; 256],
+            total: 0,
+            missing: 0,
+        }
+    }
+}
+impl Histogram {
+    pub const SAMPLES_PER_AXIS: i32 = 32;
+
+    /// Samples a cube of `2 * half_extent` full resolution voxels around `center` on a regular grid. Samples whose data
+    /// is not available yet are only counted as missing.
+    pub fn sample(volume: &dyn VoxelVolume, center: [i32; 3], half_extent: i32, downsampling: i32) -> Self {
+        let mut histogram = Self::default();
+        let n = Self::SAMPLES_PER_AXIS;
+        let step = (2 * half_extent / n).max(1);
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    let xyz = [
+                        center[0] - half_extent + x * step,
+                        center[1] - half_extent + y * step,
+                        center[2] - half_extent + z * step,
+                    ];
+                    if xyz.iter().any(|c| *c < 0) {
+                        continue;
+                    }
+                    match volume.try_get(xyz.map(|c| (c / downsampling) as f64), downsampling) {
+                        Some(v) => histogram.add(v),
+                        None => histogram.missing += 1,
+                    }
+                }
+            }
+        }
+        histogram
+    }
+    pub fn add(&mut self, value: u8) {
+        if value > 0 {
+            self.bins[value as usize] += 1;
+            self.total += 1;
+        }
+    }
+    /// Smallest value so that at least `p` percent of the samples are less or equal
+    pub fn percentile(&self, p: f64) -> u8 {
+        let target = (self.total as f64 * p / 100.0).ceil().max(1.0) as u64;
+        let mut sum = 0;
+        for (v, count) in self.bins.iter().enumerate() {
+            sum += count;
+            if sum >= target {
+                return v as u8;
+            }
+        }
+        255
+    }
+    /// Window covering the values between the `low` and `high` percentiles
+    pub fn auto_window(&self, low: f64, high: f64) -> Option<[u8; 2]> {
+        if self.total == 0 {
+            return None;
+        }
+        let min = self.percentile(low);
+        let max = self.percentile(high).max(min.saturating_add(1));
+        Some([min, max])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::{SyntheticPattern, SyntheticVolume};
+
+    #[test]
+    fn percentiles_of_gradient() {
+        let mut histogram = Histogram::default();
+        for v in 0..=255u8 {
+            for _ in 0..10 {
+                histogram.add(v);
+            }
+        }
+        assert_eq!(histogram.total, 2550);
+        assert_eq!(histogram.percentile(0.0), 1);
+        assert_eq!(histogram.percentile(50.0), 128);
+        assert_eq!(histogram.percentile(100.0), 255);
+        assert_eq!(histogram.auto_window(0.5, 99.5), Some([2, 254]));
+        assert_eq!(Histogram::default().auto_window(0.5, 99.5), None);
+
+        let volume = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 0 }, [256; 3]);
+        let sampled = Histogram::sample(&volume, [128, 128, 128], 64, 1);
+        assert_eq!(sampled.total, 32 * 32 * 32);
+        assert_eq!(sampled.percentile(0.0), 64);
+        assert_eq!(sampled.percentile(100.0), 188);
+        assert_eq!(sampled.missing, 0);
+
+        // only the half below x = 128 is available
+        struct Loading;
+        impl VoxelVolume for Loading {
+            fn get(&self, _xyz: [f64; 3], _downsampling: i32) -> u8 {
+                0
+            }
+            fn try_get(&self, xyz: [f64; 3], _downsampling: i32) -> Option<u8> {
+                (xyz[0] < 128.0).then_some(10)
+            }
+        }
+        let sampled = Histogram::sample(&Loading, [128, 128, 128], 64, 1);
+        assert_eq!(sampled.total, 16 * 32 * 32);
+        assert_eq!(sampled.missing, 16 * 32 * 32);
+        assert_eq!(sampled.percentile(100.0), 10);
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A point where a mesh edge crosses a plane, keyed by the vertex indices of the edge
+pub(super) type EdgePoint = ((usize, usize), [f64; 3]);
+
+/// The line segment where a triangle crosses the plane at `position` along `axis`, if it does. `ids` identify the
+/// vertices of the triangle, each end point is keyed by the edge it lies on, or by its vertex if that lies on the plane.
+pub(super) fn triangle_intersection(
+    tri: [[f64; 3]; 3],
+    ids: [usize; 3],
+    axis: usize,
+    position: f64,
+) -> Option<[EdgePoint; 2]> {
+    let mut points = vec![];
+    for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+        let (pa, pb) = (tri[a], tri[b]);
+        let d1 = position - pa[axis];
+        let d2 = position - pb[axis];
+        // vertices on the plane count as above it, so that a surface touching the plane is only crossed once
+        if (d1 <= 0.0) != (d2 <= 0.0) {
+            let (a, b) = (ids[a], ids[b]);
+            let key = if d1 == 0.0 {
+                (a, a)
+            } else if d2 == 0.0 {
+                (b, b)
+            } else {
+                (a.min(b), a.max(b))
+            };
+            let t = d1 / (d1 - d2);
+            points.push((key, [0, 1, 2].map(|k| pa[k] + t * (pb[k] - pa[k]))));
+        }
+    }
+    match points[..] {
+        [p1, p2] if p1.0 != p2.0 => Some([p1, p2]),
+        _ => None,
+    }
+}
+
+/// Where a surface crosses an axis-aligned plane, as polylines in volume coordinates, e.g. to export sheet tracings
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PlaneIntersection {
+    /// normal axis of the plane (0 = x, 1 = y, 2 = z)
+    pub axis: usize,
+    /// position of the plane along the axis
+    pub position: f64,
+    /// closed polylines end with their first point
+    pub polylines: Vec<Vec<[f64; 3]>>,
+}
+impl PlaneIntersection {
+    /// Chains line segments into polylines. Segment end points are identified by a key, e.g. the mesh edge they lie
+    /// on, so that end points shared by neighbouring segments are joined exactly.
+    pub(super) fn from_segments<K: Eq + Hash + Copy>(
+        axis: usize,
+        position: f64,
+        segments: &[[(K, [f64; 3]); 2]],
+    ) -> Self {
+        let mut segments_at: HashMap<K, Vec<usize>> = HashMap::new();
+        for (i, segment) in segments.iter().enumerate() {
+            for (key, _) in segment {
+                segments_at.entry(*key).or_default().push(i);
+            }
+        }
+
+        let mut used = vec![false; segments.len()];
+        let follow = |start: usize, from: usize, used: &mut Vec<bool>| {
+            let mut polyline = vec![segments[start][from].1];
+            let (mut current, mut end) = (start, 1 - from);
+            loop {
+                used[current] = true;
+                let (key, point) = segments[current][end];
+                polyline.push(point);
+                let next = segments_at[&key].iter().copied().find(|&s| !used[s]);
+                let Some(next) = next else {
+                    break;
+                };
+                end = if segments[next][0].0 == key { 1 } else { 0 };
+                current = next;
+            }
+            polyline
+        };
+
+        // open polylines start at end points that belong to a single segment, the remaining ones are closed
+        let mut polylines = vec![];
+        for i in 0..segments.len() {
+            if used[i] {
+                continue;
+            }
+            if let Some(from) = (0..2).find(|&end| segments_at[&segments[i][end].0].len() == 1) {
+                polylines.push(follow(i, from, &mut used));
+            }
+        }
+        for i in 0..segments.len() {
+            if !used[i] {
+                polylines.push(follow(i, 0, &mut used));
+            }
+        }
+
+        Self {
+            axis,
+            position,
+            polylines,
+        }
+    }
+
+    /// The in-plane coordinates, in the same order as in the volume panes
+    pub fn plane_axes(&self) -> (usize, usize) {
+        match self.axis {
+            0 => (2, 1),
+            1 => (0, 2),
+            _ => (0, 1),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// SVG with one polyline per intersection, in volume coordinates of the plane axes
+    pub fn to_svg(&self) -> String {
+        let (u, v) = self.plane_axes();
+        let points = self.polylines.iter().flatten();
+        let (mut min, mut max) = ([f64::INFINITY; 2], [f64::NEG_INFINITY; 2]);
+        for p in points {
+            min = [min[0].min(p[u]), min[1].min(p[v])];
+            max = [max[0].max(p[u]), max[1].max(p[v])];
+        }
+        if self.polylines.is_empty() {
+            (min, max) = ([0.0; 2], [0.0; 2]);
+        }
+        let margin = 10.0;
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            min[0] - margin,
+            min[1] - margin,
+            max[0] - min[0] + 2.0 * margin,
+            max[1] - min[1] + 2.0 * margin
+        );
+        let axis_names = ["x", "y", "z"];
+        svg += &format!(
+            "<!-- intersection with {} = {}, horizontal axis {}, vertical axis {} -->\n",
+            axis_names[self.axis], self.position, axis_names[u], axis_names[v]
+        );
+        for polyline in &self.polylines {
+            let points = polyline
+                .iter()
+                .map(|p| format!("{:.2},{:.2}", p[u], p[v]))
+                .collect::<Vec<_>>()
+                .join(" ");
+            svg += &format!(
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"#ff00ff\" stroke-width=\"1\"/>\n",
+                points
+            );
+        }
+        svg += "</svg>\n";
+        svg
+    }
+
+    /// Writes the intersection as SVG or, for all other file extensions, as JSON
+    pub fn write(&self, file_path: &str) -> std::io::Result<()> {
+        let content = if file_path.ends_with(".svg") {
+            self.to_svg()
+        } else {
+            self.to_json()
+        };
+        std::fs::write(file_path, content)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chain_segments() {
+        // an open chain given out of order and in mixed directions, and a closed triangle
+        let p = |x: f64, y: f64| [x, y, 5.0];
+        let segments = [
+            [(2, p(2.0, 0.0)), (1, p(1.0, 0.0))],
+            [(0, p(0.0, 0.0)), (1, p(1.0, 0.0))],
+            [(2, p(2.0, 0.0)), (3, p(3.0, 1.0))],
+            [(10, p(10.0, 0.0)), (11, p(11.0, 0.0))],
+            [(11, p(11.0, 0.0)), (12, p(10.0, 1.0))],
+            [(12, p(10.0, 1.0)), (10, p(10.0, 0.0))],
+        ];
+        let intersection = PlaneIntersection::from_segments(2, 5.0, &segments);
+        assert_eq!(intersection.polylines.len(), 2);
+        let mut open = intersection.polylines[0].clone();
+        if open[0] != p(0.0, 0.0) {
+            open.reverse();
+        }
+        assert_eq!(open, vec![p(0.0, 0.0), p(1.0, 0.0), p(2.0, 0.0), p(3.0, 1.0)]);
+        let closed = &intersection.polylines[1];
+        assert_eq!(closed.len(), 4);
+        assert_eq!(closed.first(), closed.last());
+
+        let svg = intersection.to_svg();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"-10 -10 31 21\">"));
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        let json: serde_json::Value = serde_json::from_str(&intersection.to_json()).unwrap();
+        assert_eq!(json["axis"], 2);
+        assert_eq!(json["polylines"][0].as_array().unwrap().len(), 4);
+    }
+}
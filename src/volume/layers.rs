@@ -1,43 +1,32 @@
-use std::io::{Read, Seek};
-
-use super::{AutoPaintVolume, VoxelVolume};
-
-struct Layer {
-    data: memmap::Mmap,
-    width: usize,
-    height: usize,
-}
-impl Layer {
-    fn get(&self, x: usize, y: usize) -> u8 {
-        let off = (y * self.width + x) * 2;
-
-        // off + 1 because we select the higher order bits of little endian 16 bit values
-        if off + 1 >= self.data.len() {
-            0
-        } else {
-            self.data[off + 1]
-        }
-    }
-}
+use super::tiffpages::{TiffChunkCache, TiffPages};
+use super::{
+    AutoPaintVolume, CacheConfig, DataType, VolumeCons, VolumeError, VolumeMetadata, VoxelPaintVolume, VoxelVolume,
+};
+use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct LayersMappedVolume {
     max_x: usize,
     max_y: usize,
     max_z: usize,
-    data: Vec<Option<Layer>>,
+    data: Arc<Vec<Option<TiffPages>>>,
+    // decoded chunks of compressed pages, shared by all files of the volume
+    cache: Arc<TiffChunkCache>,
 }
 impl LayersMappedVolume {
-    pub fn from_data_dir(data_dir: &str) -> Self {
-        use memmap::MmapOptions;
-        use std::fs::File;
-
+    pub fn from_data_dir(data_dir: &str) -> Result<Self, VolumeError> {
         // find highest xyz values for files in data_dir named like this format: format!("{}/cell_yxz_{:03}_{:03}_{:03}.tif", data_dir, y, x, z);
         // use regex to match file names
         let mut max_z = 0;
-        for entry in std::fs::read_dir(data_dir).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            let file_name = path.file_name().unwrap().to_str().unwrap();
+        if !std::path::Path::new(data_dir).exists() {
+            return Err(VolumeError::NotFound(data_dir.to_string()));
+        }
+        let io_error = |e| VolumeError::Io(data_dir.to_string(), e);
+        for entry in std::fs::read_dir(data_dir).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            let Some(file_name) = path.file_name().and_then(|f| f.to_str()) else {
+                continue;
+            };
             if let Some(captures) = regex::Regex::new(r"(\d{5})\.tif").unwrap().captures(file_name) {
                 //println!("Found file: {}", file_name);
                 let z = captures.get(1).unwrap().as_str().parse::<usize>().unwrap();
@@ -46,82 +35,48 @@ impl LayersMappedVolume {
                 }
             }
         }
-        fn layer_for(data_dir: &str, z: usize) -> Option<Layer> {
-            let file_name = format!("{}/{:05}.tif", data_dir, z);
-
-            let file = File::open(file_name.clone()).ok()?;
-            let mut decoder = tiff::decoder::Decoder::new(file).ok()?;
-
-            use tiff::tags::Tag;
-            fn assume<T: Seek + Read>(decoder: &mut tiff::decoder::Decoder<T>, tag: Tag, value: u32) -> Option<()> {
-                if let Ok(v) = decoder.get_tag_u32(tag) {
-                    if v != value {
-                        println!("Expected {:?} to be {} but was {}", tag, value, v);
-                        None
-                    } else {
-                        Some(())
-                    }
-                } else {
-                    // ok if not found
-                    Some(())
-                }
-            }
-
-            let width = decoder.get_tag_u32(Tag::ImageWidth).ok()? as usize;
-            let height = decoder.get_tag_u32(Tag::ImageLength).ok()? as usize;
-
-            assume(&mut decoder, Tag::BitsPerSample, 16)?;
-            assume(&mut decoder, Tag::Compression, 1 /* None */)?;
-            assume(&mut decoder, Tag::SamplesPerPixel, 1)?;
-            // PlanarConfiguration does not matter for grayscale
-            // assume(&mut decoder, Tag::PlanarConfiguration, 2 /* Planar */)?;
-
-            // get offsets
-            let offsets = decoder.get_tag_u32_vec(Tag::StripOffsets).ok()?;
-            if offsets.len() != 1 {
-                println!("Expected 1 strip offset: {}", offsets.len());
-                return None;
-            }
-            let first_offset = offsets[0] as usize;
-
-            let file = File::open(file_name).ok()?;
-            if let Ok(mmap) = unsafe { MmapOptions::new().offset(first_offset as u64).map(&file) } {
-                Some(Layer {
-                    data: mmap,
-                    width,
-                    height,
-                })
-            } else {
-                None
-            }
-        }
-        if !std::path::Path::new(data_dir).exists() {
-            println!("Data directory {} does not exist", data_dir);
-            return Self {
-                max_x: 0,
-                max_y: 0,
-                max_z: 0,
-                data: vec![],
-            };
-        }
-        let data: Vec<Option<Layer>> = (0..=max_z).map(|z| layer_for(data_dir, z)).collect();
+        let cache = Arc::new(TiffChunkCache::new(CacheConfig::default()));
+        let layer_for = |z: usize| TiffPages::open(&format!("{}/{:05}.tif", data_dir, z), &cache);
+        let data: Vec<Option<TiffPages>> = (0..=max_z).map(layer_for).collect();
 
         // count number of slices found
         let layers_found = data.iter().flatten().count();
         let max_x = data.iter().flatten().map(|l| l.width).min().unwrap_or(0);
         let max_y = data.iter().flatten().map(|l| l.height).min().unwrap_or(0);
         println!("Found {} layers in {}", layers_found, data_dir);
+        if layers_found == 0 {
+            return Err(VolumeError::NoData(data_dir.to_string()));
+        }
         println!("max_x: {}, max_y: {}, max_z: {}", max_x, max_y, max_z);
 
-        Self {
+        Ok(Self {
             max_x: max_x - 1,
             max_y: max_y - 1,
             max_z: max_z - 1,
-            data,
-        }
+            data: Arc::new(data),
+            cache,
+        })
+    }
+
+    /// Limits the decoded data of compressed TIFF pages kept in memory
+    pub fn with_cache_config(self, config: CacheConfig) -> Self {
+        self.cache.set_config(config);
+        self
     }
 }
 impl VoxelVolume for LayersMappedVolume {
+    fn reset_for_painting(&self) {
+        self.cache.forget_failures();
+    }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        let shape = [self.max_x + 1, self.max_y + 1, self.data.len()];
+        let mut metadata = match self.data.iter().flatten().next() {
+            Some(layer) => layer.metadata(shape),
+            None => VolumeMetadata::new(shape, DataType::Unknown),
+        };
+        metadata.data_range = TiffPages::combined_data_range(self.data.iter().flatten());
+        Some(metadata)
+    }
     fn get(&self, _xyz: [f64; 3], downsampling: i32) -> u8 {
         let xyz = [
             _xyz[0] as i32 * downsampling,
@@ -139,11 +94,16 @@ impl VoxelVolume for LayersMappedVolume {
             //println!("out of bounds: {:?}", xyz);
             0
         } else if let Some(layer) = &self.data[xyz[2] as usize] {
-            layer.get(xyz[0] as usize, xyz[1] as usize)
+            layer.get(xyz[0] as usize, xyz[1] as usize, 0)
         } else {
             0
         }
     }
 }
 
-impl AutoPaintVolume for LayersMappedVolume {}
+impl AutoPaintVolume for LayersMappedVolume {
+    fn shared_volume(&self) -> VolumeCons {
+        let volume = self.clone();
+        Box::new(move || volume.into_volume())
+    }
+}
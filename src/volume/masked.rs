@@ -0,0 +1,163 @@
+use super::{
+    AutoPaintVolume, CacheStats, PaintVolume, Volume, VolumeCons, VolumeMetadata, VoxelPaintVolume, VoxelVolume,
+};
+
+/// Hides all voxels of an underlying volume where a binary mask volume is 0, e.g. the air and the case around a
+/// scroll. Masked voxels are never requested from the underlying volume, so no data is downloaded for them.
+///
+/// Masks are often stored at a lower resolution than the scan. `mask_scale` is the number of full resolution voxels
+/// per mask voxel along each axis.
+pub struct MaskedVolume {
+    volume: Volume,
+    mask: Volume,
+    mask_scale: f64,
+}
+impl MaskedVolume {
+    /// Creates a masked volume, deriving the mask scale from the shapes of both volumes if they are known.
+    pub fn new(volume: Volume, mask: Volume) -> Self {
+        let mask_scale = match (volume.metadata(), mask.metadata()) {
+            (Some(v), Some(m)) => Self::scale_between(&v, &m).unwrap_or(1.0),
+            _ => 1.0,
+        };
+        Self {
+            volume,
+            mask,
+            mask_scale,
+        }
+    }
+    /// Mask scale for a mask with the given metadata covering a volume with the given metadata
+    pub fn scale_between(volume: &VolumeMetadata, mask: &VolumeMetadata) -> Option<f64> {
+        if mask.full_shape()[0] == 0 {
+            return None;
+        }
+        // masks are downsampled by powers of two, round to the closest one
+        let ratio = volume.full_shape()[0] as f64 / mask.full_shape()[0] as f64;
+        Some(2f64.powi(ratio.max(1.0).log2().round() as i32))
+    }
+    pub fn with_mask_scale(mut self, mask_scale: f64) -> Self {
+        self.mask_scale = mask_scale;
+        self
+    }
+    pub fn mask_scale(&self) -> f64 {
+        self.mask_scale
+    }
+
+    /// Whether a voxel given in downsampled coordinates is inside of the mask, `None` if that part of the mask is not
+    /// available yet
+    pub fn is_inside(&self, xyz: [f64; 3], downsampling: i32) -> Option<bool> {
+        // read the mask at the coarsest level that still resolves the requested one
+        let factor = downsampling as f64 / self.mask_scale;
+        let mask_downsampling = if factor >= 2.0 {
+            1 << factor.log2().floor() as u32
+        } else {
+            1
+        };
+        let mask_xyz = xyz.map(|c| (c * factor / mask_downsampling as f64).floor());
+        if mask_xyz.iter().any(|c| *c < 0.0) {
+            return Some(false);
+        }
+        self.mask.try_get(mask_xyz, mask_downsampling).map(|v| v != 0)
+    }
+}
+
+impl VoxelVolume for MaskedVolume {
+    // voxels are only hidden where the mask is known to be 0
+    fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        if self.is_inside(xyz, downsampling) != Some(false) {
+            self.volume.get(xyz, downsampling)
+        } else {
+            0
+        }
+    }
+    fn try_get(&self, xyz: [f64; 3], downsampling: i32) -> Option<u8> {
+        match self.is_inside(xyz, downsampling)? {
+            true => self.volume.try_get(xyz, downsampling),
+            false => Some(0),
+        }
+    }
+    fn get_interpolated(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        if self.is_inside(xyz, downsampling) != Some(false) {
+            self.volume.get_interpolated(xyz, downsampling)
+        } else {
+            0
+        }
+    }
+    fn reset_for_painting(&self) {
+        self.volume.reset_for_painting();
+        self.mask.reset_for_painting();
+    }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        self.volume.metadata()
+    }
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.volume.cache_stats()
+    }
+}
+
+impl AutoPaintVolume for MaskedVolume {
+    fn shared_volume(&self) -> VolumeCons {
+        let volume = self.volume.shared();
+        let mask = self.mask.shared();
+        let mask_scale = self.mask_scale;
+        Box::new(move || {
+            MaskedVolume {
+                volume: volume(),
+                mask: mask(),
+                mask_scale,
+            }
+            .into_volume()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::{DrawingConfig, Image, SyntheticPattern, SyntheticVolume};
+    use egui::Color32;
+
+    #[test]
+    fn masked_voxels_are_hidden() {
+        let volume = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 0 }, [256; 3]).into_volume();
+        // a half resolution mask that is 0 only in the first mask layer (full resolution z = 0 and 1)
+        let mask = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 2 }, [128; 3]).into_volume();
+        let masked = MaskedVolume::new(volume, mask);
+        assert_eq!(masked.mask_scale(), 2.0);
+        assert_eq!(masked.get([100.0, 5.0, 1.0], 1), 0);
+        assert_eq!(masked.get([100.0, 5.0, 2.0], 1), 100);
+        assert_eq!(masked.get_interpolated([100.0, 5.0, 0.5], 1), 0);
+        assert_eq!(masked.get([25.0, 2.0, 0.0], 4), 0);
+        assert_eq!(masked.get([25.0, 2.0, 1.0], 4), 100);
+        assert_eq!(masked.is_inside([25.0, 2.0, 1.0], 4), Some(true));
+    }
+
+    /// A mask that is still downloading
+    struct Downloading;
+    impl VoxelVolume for Downloading {
+        fn get(&self, _xyz: [f64; 3], _downsampling: i32) -> u8 {
+            0
+        }
+        fn try_get(&self, _xyz: [f64; 3], _downsampling: i32) -> Option<u8> {
+            None
+        }
+    }
+    impl AutoPaintVolume for Downloading {
+        fn shared_volume(&self) -> VolumeCons {
+            Box::new(|| Downloading.into_volume())
+        }
+    }
+
+    #[test]
+    fn unavailable_mask_hides_nothing() {
+        let volume = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 0 }, [256; 3]).into_volume();
+        let masked = MaskedVolume::new(volume, Downloading.into_volume());
+        assert_eq!(masked.is_inside([100.0, 5.0, 5.0], 1), None);
+        assert_eq!(masked.try_get([100.0, 5.0, 5.0], 1), None);
+        assert_eq!(masked.get([100.0, 5.0, 5.0], 1), 100);
+
+        // what coarser levels have painted stays visible
+        let mut image = Image::new_from_color(4, 4, Color32::from_gray(7));
+        masked.paint([100, 5, 5], 0, 1, 2, 4, 4, 1, 1, &DrawingConfig::default(), &mut image);
+        assert!(image.data.iter().all(|c| *c == Color32::from_gray(7)));
+    }
+}
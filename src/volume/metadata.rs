@@ -0,0 +1,90 @@
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum DataType {
+    UInt8,
+    UInt16,
+    UInt32,
+    Float32,
+    Float64,
+    Unknown,
+}
+impl DataType {
+    /// Parses numpy style dtype strings as used in zarr arrays, e.g. `|u1` or `<f4`
+    pub fn from_numpy_dtype(dtype: &str) -> Self {
+        match dtype.trim_start_matches(['<', '>', '|', '=']) {
+            "u1" => DataType::UInt8,
+            "u2" => DataType::UInt16,
+            "u4" => DataType::UInt32,
+            "f4" => DataType::Float32,
+            "f8" => DataType::Float64,
+            _ => DataType::Unknown,
+        }
+    }
+    pub fn from_bits_per_sample(bits: u32) -> Self {
+        match bits {
+            8 => DataType::UInt8,
+            16 => DataType::UInt16,
+            32 => DataType::UInt32,
+            _ => DataType::Unknown,
+        }
+    }
+    pub fn label(&self) -> &str {
+        match self {
+            DataType::UInt8 => "uint8",
+            DataType::UInt16 => "uint16",
+            DataType::UInt32 => "uint32",
+            DataType::Float32 => "float32",
+            DataType::Float64 => "float64",
+            DataType::Unknown => "unknown",
+        }
+    }
+}
+
+/// Static information about a volume as far as the backend knows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeMetadata {
+    /// shape as [x, y, z] for each resolution level, starting with full resolution
+    pub shape: Vec<[usize; 3]>,
+    /// edge length of a full resolution voxel in µm, if known
+    pub voxel_size_um: Option<f64>,
+    /// data type of the source data (values are always delivered as u8)
+    pub dtype: DataType,
+    /// position of voxel [0, 0, 0] in full resolution voxel coordinates
+    pub origin: [f64; 3],
+    /// smallest and largest value of the source data (before the conversion to u8), if recorded in the file
+    pub data_range: Option<[f64; 2]>,
+}
+impl VolumeMetadata {
+    pub fn new(shape: [usize; 3], dtype: DataType) -> Self {
+        Self {
+            shape: vec![shape],
+            voxel_size_um: None,
+            dtype,
+            origin: [0.0; 3],
+            data_range: None,
+        }
+    }
+    /// Adds downsampled levels (each halving the previous one) until `levels` levels are available
+    pub fn with_pyramid(mut self, levels: usize) -> Self {
+        while self.shape.len() < levels {
+            let [x, y, z] = *self.shape.last().unwrap();
+            self.shape.push([(x + 1) / 2, (y + 1) / 2, (z + 1) / 2]);
+        }
+        self
+    }
+    pub fn with_voxel_size_um(mut self, voxel_size_um: f64) -> Self {
+        self.voxel_size_um = Some(voxel_size_um);
+        self
+    }
+    pub fn with_origin(mut self, origin: [f64; 3]) -> Self {
+        self.origin = origin;
+        self
+    }
+    pub fn with_data_range(mut self, min: f64, max: f64) -> Self {
+        self.data_range = Some([min, max]);
+        self
+    }
+    /// full resolution shape as [x, y, z]
+    pub fn full_shape(&self) -> [usize; 3] {
+        self.shape[0]
+    }
+}
@@ -1,22 +1,58 @@
+mod blend;
+mod clahe;
+mod composition;
+mod distortion;
 mod empty;
+mod error;
+mod filtered;
+mod flatten;
 mod generic;
 mod grid500;
+mod histogram;
+mod intersection;
 mod layers;
+mod masked;
+mod metadata;
+mod multipage;
 mod objvolume;
+mod ply;
 mod ppmvolume;
+mod raw;
+mod synthetic;
+#[cfg(test)]
+mod test_segment;
+mod tiffpages;
+mod tifxyz;
 mod transform;
+mod transformed;
 mod volume64x4;
 
+pub use blend::{BlendMode, Colormap, LayerSettings, VolumeLayer};
+pub use clahe::ClaheSettings;
+pub use composition::{paint_slab, PaintParams};
+pub use distortion::{DistortionStats, TriangleDistortion};
 use egui::{Color32, ColorImage};
 pub use empty::EmptyVolume;
+pub use error::VolumeError;
+pub use filtered::{FilterKind, FilteredVolume};
 pub use generic::AutoPaintVolume;
 pub use grid500::VolumeGrid500Mapped;
+pub use histogram::Histogram;
+pub use intersection::PlaneIntersection;
 pub use layers::LayersMappedVolume;
 use libm::modf;
+pub use masked::MaskedVolume;
+pub use metadata::{DataType, VolumeMetadata};
+pub use multipage::MultiPageTiffVolume;
 pub use objvolume::{ObjFile, ObjVolume, ProjectionKind};
+pub use ply::load_ply;
 pub use ppmvolume::PPMVolume;
+pub use raw::RawVolume;
 use std::sync::Arc;
+pub use synthetic::{SyntheticPattern, SyntheticVolume};
+pub use tifxyz::TifXyzSurface;
 pub use transform::AffineTransform;
+pub use transformed::TransformedVolume;
 pub use volume64x4::VolumeGrid64x4Mapped;
 
 #[derive(Copy, Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
@@ -25,6 +61,10 @@ pub enum CompositingMode {
     Max,
     Alpha,
     AlphaHeightMap,
+    Mean,
+    Min,
+    Median,
+    Percentile,
 }
 impl CompositingMode {
     pub fn label(&self) -> &str {
@@ -33,16 +73,25 @@ impl CompositingMode {
             CompositingMode::Max => "Max",
             CompositingMode::Alpha => "Alpha",
             CompositingMode::AlphaHeightMap => "Alpha Height Map",
+            CompositingMode::Mean => "Mean",
+            CompositingMode::Min => "Min",
+            CompositingMode::Median => "Median",
+            CompositingMode::Percentile => "Percentile",
         }
     }
-    pub const VALUES: [CompositingMode; 4] = [
+    pub const VALUES: [CompositingMode; 8] = [
         CompositingMode::None,
         CompositingMode::Max,
         CompositingMode::Alpha,
         CompositingMode::AlphaHeightMap,
+        CompositingMode::Mean,
+        CompositingMode::Min,
+        CompositingMode::Median,
+        CompositingMode::Percentile,
     ];
 }
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub struct CompositingSettings {
     pub mode: CompositingMode,
     pub layers_in_front: u8,
@@ -52,6 +101,51 @@ pub struct CompositingSettings {
     pub alpha_threshold: u16,
     pub opacity: u16,
     pub reverse_direction: bool,
+    /// percentile (0-100) to use for `CompositingMode::Percentile`
+    pub percentile: u8,
+}
+impl Default for CompositingSettings {
+    fn default() -> Self {
+        Self {
+            mode: CompositingMode::None,
+            layers_in_front: 6,
+            layers_behind: 6,
+            alpha_min: (0.3 * 255.0) as u8,
+            alpha_max: (0.7 * 255.0) as u8,
+            alpha_threshold: 9500,
+            opacity: 1,
+            reverse_direction: false,
+            percentile: 90,
+        }
+    }
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub enum SlabMode {
+    None,
+    Max,
+    Min,
+    Mean,
+}
+impl SlabMode {
+    pub fn label(&self) -> &str {
+        match self {
+            SlabMode::None => "None",
+            SlabMode::Max => "Max",
+            SlabMode::Min => "Min",
+            SlabMode::Mean => "Mean",
+        }
+    }
+    pub const VALUES: [SlabMode; 4] = [SlabMode::None, SlabMode::Max, SlabMode::Min, SlabMode::Mean];
+}
+/// Thick-slab projection along the plane axis of the XY/XZ/YZ panes
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub struct SlabSettings {
+    pub mode: SlabMode,
+    /// number of slices with larger plane coordinate
+    pub slices_in_front: u8,
+    /// number of slices with smaller plane coordinate
+    pub slices_behind: u8,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
@@ -65,8 +159,15 @@ pub struct DrawingConfig {
     pub trilinear_interpolation: bool,
     pub draw_xyz_outlines: bool,
     pub show_segment_outlines: bool,
+    /// color of segment outlines outside of the highlighted section
+    pub segment_outline_color: [u8; 3],
     pub draw_outline_vertices: bool,
+    /// color the segment pane by the UV distortion of the segment triangles
+    pub show_distortion: bool,
     pub compositing: CompositingSettings,
+    pub slab: SlabSettings,
+    /// local contrast enhancement of the segment pane
+    pub clahe: ClaheSettings,
 }
 impl DrawingConfig {
     pub fn filters_active(&self) -> bool {
@@ -86,6 +187,25 @@ impl DrawingConfig {
             _ => 0xff,
         }) >> self.mask_shift
     }
+    /// Applies the contrast window and bit mask to a painted value
+    pub fn apply_filters(&self, value: u8) -> u8 {
+        let mask = self.bit_mask();
+        let window = 255 - (self.threshold_min as i32 + self.threshold_max as i32);
+        let pluscon = ((value as i32 - self.threshold_min as i32).max(0) * 255 / window.max(1)).min(255) as u8;
+        (((pluscon & mask) as f32) / (mask as f32) * 255.0) as u8
+    }
+    /// Applies the filters to all gray pixels of a painted image, if they are active
+    pub fn apply_filters_to(&self, image: &mut Image) {
+        if !self.filters_active() {
+            return;
+        }
+        for c in image.data.iter_mut() {
+            // leave missing data and colored overlays alone
+            if c.r() > 0 && c.r() == c.g() && c.g() == c.b() {
+                *c = Color32::from_gray(self.apply_filters(c.r()));
+            }
+        }
+    }
 }
 impl Default for DrawingConfig {
     fn default() -> Self {
@@ -98,17 +218,52 @@ impl Default for DrawingConfig {
             trilinear_interpolation: false,
             draw_xyz_outlines: false,
             show_segment_outlines: true,
+            segment_outline_color: [0xff, 0, 0xff],
             draw_outline_vertices: false,
-            compositing: CompositingSettings {
-                mode: CompositingMode::None,
-                layers_in_front: 6,
-                layers_behind: 6,
-                alpha_min: (0.3 * 255.0) as u8,
-                alpha_max: (0.7 * 255.0) as u8,
-                alpha_threshold: 9500,
-                opacity: 1,
-                reverse_direction: false,
+            show_distortion: false,
+            compositing: CompositingSettings::default(),
+            slab: SlabSettings {
+                mode: SlabMode::None,
+                slices_in_front: 5,
+                slices_behind: 5,
             },
+            clahe: ClaheSettings::default(),
+        }
+    }
+}
+
+/// Upper bounds for the data a volume keeps cached in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub max_entries: usize,
+    pub max_bytes: usize,
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 16384,
+            max_bytes: 2 << 30,
+        }
+    }
+}
+
+/// Occupancy and effectiveness of a volume's cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub config: Option<CacheConfig>,
+}
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
         }
     }
 }
@@ -118,6 +273,19 @@ pub trait VoxelVolume {
 
     fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8;
 
+    /// Like `get`, but `None` if the data is not available yet, e.g. while it is still being downloaded
+    fn try_get(&self, xyz: [f64; 3], downsampling: i32) -> Option<u8> {
+        Some(self.get(xyz, downsampling))
+    }
+
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        None
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
     fn get_interpolated(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
         self.get_interpolated_slow(xyz, downsampling)
     }
@@ -174,6 +342,20 @@ impl Image {
     pub fn set_gray(&mut self, x: usize, y: usize, value: u8) {
         self.set(x, y, Color32::from_gray(value));
     }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    /// Copies the given area into a new image
+    pub fn crop(&self, x0: usize, y0: usize, width: usize, height: usize) -> Image {
+        let mut data = Vec::with_capacity(width * height);
+        for y in y0..y0 + height {
+            data.extend_from_slice(&self.data[y * self.width + x0..y * self.width + x0 + width]);
+        }
+        Image { width, height, data }
+    }
 }
 impl From<Image> for ColorImage {
     fn from(value: Image) -> Self {
@@ -245,6 +427,10 @@ impl Volume {
     pub fn from_ref(volume: Arc<dyn VoxelPaintVolume>) -> Self {
         Self { volume }
     }
+    /// Identifies the underlying volume instance, e.g. for caching painted tiles
+    pub fn id(&self) -> usize {
+        Arc::as_ptr(&self.volume) as *const () as usize
+    }
 }
 impl PaintVolume for Volume {
     fn paint(
@@ -282,10 +468,19 @@ impl VoxelVolume for Volume {
     fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
         self.volume.get(xyz, downsampling)
     }
+    fn try_get(&self, xyz: [f64; 3], downsampling: i32) -> Option<u8> {
+        self.volume.try_get(xyz, downsampling)
+    }
     fn get_interpolated(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
         self.volume.get_interpolated(xyz, downsampling)
     }
     fn reset_for_painting(&self) {
         self.volume.reset_for_painting();
     }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        self.volume.metadata()
+    }
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.volume.cache_stats()
+    }
 }
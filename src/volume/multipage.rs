@@ -0,0 +1,113 @@
+use super::tiffpages::{TiffChunkCache, TiffPages};
+use super::{AutoPaintVolume, CacheConfig, VolumeCons, VolumeError, VolumeMetadata, VoxelPaintVolume, VoxelVolume};
+use std::sync::Arc;
+
+/// A volume stored as a single TIFF file with one page per z slice.
+#[derive(Clone)]
+pub struct MultiPageTiffVolume {
+    pages: Arc<TiffPages>,
+    depth: usize,
+    // decoded chunks of compressed pages, shared by all clones of the volume
+    cache: Arc<TiffChunkCache>,
+}
+impl MultiPageTiffVolume {
+    pub fn from_file(file_name: &str) -> Result<Self, VolumeError> {
+        if !std::path::Path::new(file_name).exists() {
+            return Err(VolumeError::NotFound(file_name.to_string()));
+        }
+        let invalid = |reason: &str| VolumeError::InvalidFormat(file_name.to_string(), reason.to_string());
+        let cache = Arc::new(TiffChunkCache::new(CacheConfig::default()));
+        let pages = TiffPages::open(file_name, &cache).ok_or_else(|| invalid("not a supported gray-scale TIFF"))?;
+        let depth = TiffPages::count_pages(file_name).ok_or_else(|| invalid("could not read all pages"))?;
+        println!(
+            "Found {} pages of size {}x{} in {}",
+            depth, pages.width, pages.height, file_name
+        );
+
+        Ok(Self {
+            pages: Arc::new(pages),
+            depth,
+            cache,
+        })
+    }
+
+    /// Limits the decoded data of compressed TIFF pages kept in memory
+    pub fn with_cache_config(self, config: CacheConfig) -> Self {
+        self.cache.set_config(config);
+        self
+    }
+}
+impl VoxelVolume for MultiPageTiffVolume {
+    fn reset_for_painting(&self) {
+        self.cache.forget_failures();
+    }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        Some(self.pages.metadata([self.pages.width, self.pages.height, self.depth]))
+    }
+    fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        let [x, y, z] = xyz.map(|v| v as i32 * downsampling);
+        if x < 0 || y < 0 || z < 0 || z as usize >= self.depth {
+            0
+        } else {
+            self.pages.get(x as usize, y as usize, z as usize)
+        }
+    }
+}
+
+impl AutoPaintVolume for MultiPageTiffVolume {
+    fn shared_volume(&self) -> VolumeCons {
+        let volume = self.clone();
+        Box::new(move || volume.into_volume())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    #[test]
+    fn two_page_tiff() {
+        let (width, height) = (6u32, 4u32);
+        let value = |x: u32, y: u32, z: u32| (1 + x + 10 * y + 100 * z) as u8;
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("stack.tif");
+        let mut encoder = TiffEncoder::new(File::create(&file).unwrap()).unwrap();
+        for z in 0..2 {
+            let data = (0..height)
+                .flat_map(|y| (0..width).map(move |x| value(x, y, z)))
+                .collect::<Vec<_>>();
+            encoder.write_image::<colortype::Gray8>(width, height, &data).unwrap();
+        }
+        drop(encoder);
+
+        let volume = MultiPageTiffVolume::from_file(file.to_str().unwrap()).unwrap();
+        assert_eq!(volume.metadata().unwrap().full_shape(), [6, 4, 2]);
+        assert_eq!(volume.get([0.0, 0.0, 0.0], 1), value(0, 0, 0));
+        assert_eq!(volume.get([3.0, 2.0, 0.0], 1), value(3, 2, 0));
+        assert_eq!(volume.get([3.0, 2.0, 1.0], 1), value(3, 2, 1));
+        assert_eq!(volume.get([5.0, 3.0, 1.0], 1), value(5, 3, 1));
+        assert_eq!(volume.get([6.0, 0.0, 0.0], 1), 0);
+        assert_eq!(volume.get([0.0, 4.0, 0.0], 1), 0);
+        assert_eq!(volume.get([0.0, 0.0, 2.0], 1), 0);
+        assert_eq!(volume.get([0.0, -1.0, 0.0], 1), 0);
+        assert_eq!(volume.get([1.0, 1.0, 0.0], 2), value(2, 2, 0));
+    }
+
+    #[test]
+    fn missing_and_invalid_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.tif");
+        assert!(matches!(
+            MultiPageTiffVolume::from_file(missing.to_str().unwrap()),
+            Err(VolumeError::NotFound(_))
+        ));
+        let invalid = dir.path().join("invalid.tif");
+        std::fs::write(&invalid, b"not a tiff").unwrap();
+        assert!(matches!(
+            MultiPageTiffVolume::from_file(invalid.to_str().unwrap()),
+            Err(VolumeError::InvalidFormat(..))
+        ));
+    }
+}
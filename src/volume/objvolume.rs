@@ -1,8 +1,12 @@
-use super::{Image, PaintVolume, SurfaceVolume, Volume, VoxelVolume};
-use crate::volume::{AffineTransform, CompositingMode, VoxelPaintVolume};
-use libm::{pow, sqrt};
-use std::sync::Arc;
-use wavefront_obj::obj::{self, Object, Primitive, Vertex};
+use super::composition::LayerComposition;
+use super::intersection::triangle_intersection;
+use super::{Image, PaintVolume, SurfaceVolume, Volume, VolumeError, VolumeMetadata, VoxelVolume};
+use crate::volume::{AffineTransform, DistortionStats, PlaneIntersection, TriangleDistortion, VoxelPaintVolume};
+use libm::{atan2, pow, sqrt};
+use nalgebra::Vector3;
+use std::f64::consts::PI;
+use std::sync::{Arc, OnceLock};
+use wavefront_obj::obj::{self, Normal, Object, Primitive, TVertex, Vertex};
 
 // TODO: create a single AABB index for both XYZ and UV index
 struct XYZIndex {
@@ -224,15 +228,54 @@ impl UVIndex {
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
 pub enum ProjectionKind {
+    #[default]
     None,
     OrthographicXZ,
+    /// Replace the texture coordinates by a computed low-distortion UV map
+    Flatten,
+    /// Map vertices to angle and height around an axis through `center`, e.g. the umbilicus of a scroll
+    Cylindrical {
+        center: [f64; 3],
+        axis: [f64; 3],
+    },
+}
+impl ProjectionKind {
+    pub fn label(&self) -> &str {
+        match self {
+            ProjectionKind::None => "Texture coordinates",
+            ProjectionKind::OrthographicXZ => "Orthographic XZ",
+            ProjectionKind::Flatten => "Flatten (LSCM)",
+            ProjectionKind::Cylindrical { .. } => "Cylindrical",
+        }
+    }
+    /// Parses a cylindrical projection from `x,y,z` (axis along z) or `x,y,z,dx,dy,dz`
+    pub fn cylindrical_from_spec(spec: &str) -> Result<Self, String> {
+        let values = spec
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid cylinder '{}', use x,y,z or x,y,z,dx,dy,dz", spec))?;
+        match values[..] {
+            [x, y, z] => Ok(ProjectionKind::Cylindrical {
+                center: [x, y, z],
+                axis: [0.0, 0.0, 1.0],
+            }),
+            [x, y, z, dx, dy, dz] if dx != 0.0 || dy != 0.0 || dz != 0.0 => Ok(ProjectionKind::Cylindrical {
+                center: [x, y, z],
+                axis: [dx, dy, dz],
+            }),
+            _ => Err(format!("invalid cylinder '{}', use x,y,z or x,y,z,dx,dy,dz", spec)),
+        }
+    }
 }
 
 pub struct ObjFile {
     object: Object,
     has_inverted_uv_tris: bool,
+    /// size of the UV map in voxels, if it was computed by flattening or projecting the mesh
+    flattened_size: Option<[usize; 2]>,
     uv_index: UVIndex,
     xyz_index: XYZIndex,
 }
@@ -259,6 +302,21 @@ impl ObjFile {
             });
         }
 
+        Self::complete_normals(&mut object);
+
+        let flatten = projection == ProjectionKind::Flatten
+            || (projection == ProjectionKind::None && !Self::has_usable_uvs(&object));
+        let mut flattened_size = None;
+        if flatten {
+            if projection == ProjectionKind::None {
+                println!("Mesh has no usable texture coordinates, computing a UV map");
+            }
+            flattened_size = Some(Self::flatten(&mut object));
+        }
+        if let ProjectionKind::Cylindrical { center, axis } = projection {
+            flattened_size = Some(Self::project_cylindrical(&mut object, center, axis));
+        }
+
         if projection == ProjectionKind::OrthographicXZ {
             object.normals.iter_mut().for_each(|n| {
                 n.x = 0.0;
@@ -310,19 +368,208 @@ impl ObjFile {
         let has_inverted_uv_tris = Self::has_inverted_uv_tris(object.clone());
         let target_cell_num = 100.;
         let num_tris = object.geometry[0].shapes.len() as f64;
-        let n = sqrt(num_tris / target_cell_num) as usize;
+        // at least one cell for small meshes
+        let n = (sqrt(num_tris / target_cell_num) as usize).max(1);
         let uv_index = UVIndex::new(&object, n, n);
 
-        let n = pow(num_tris / target_cell_num, 1. / 3.) as usize;
+        let n = (pow(num_tris / target_cell_num, 1. / 3.) as usize).max(1);
         let xyz_index = XYZIndex::new(&object, n);
 
         Self {
             object,
             has_inverted_uv_tris,
+            flattened_size,
             uv_index,
             xyz_index,
         }
     }
+    /// Size of the UV map in voxels if it was computed by flattening or projecting the mesh. In that case, this size
+    /// should be used as the segment size to keep the distortion low.
+    pub fn flattened_size(&self) -> Option<[usize; 2]> {
+        self.flattened_size
+    }
+
+    /// Polylines along which the mesh crosses the plane at `position` along `axis`
+    pub fn plane_intersection(&self, axis: usize, position: f64) -> PlaneIntersection {
+        let coord = |i: usize| {
+            let v = self.object.vertices[i];
+            [v.x, v.y, v.z]
+        };
+        let mut segments = vec![];
+        for s in self.object.geometry[0].shapes.iter() {
+            let Primitive::Triangle(i1, i2, i3) = s.primitive else {
+                continue;
+            };
+            let ids = [i1.0, i2.0, i3.0];
+            segments.extend(triangle_intersection(ids.map(coord), ids, axis, position));
+        }
+        PlaneIntersection::from_segments(axis, position, &segments)
+    }
+
+    /// Distortion of each triangle between UV space, scaled to a segment of the given size, and 3D space
+    pub fn triangle_distortion(&self, width: usize, height: usize) -> Vec<Option<TriangleDistortion>> {
+        let obj = &self.object;
+        obj.geometry[0]
+            .shapes
+            .iter()
+            .map(|s| match s.primitive {
+                Primitive::Triangle(i1, i2, i3) => {
+                    let xyz = [i1, i2, i3].map(|i| {
+                        let v = &obj.vertices[i.0];
+                        [v.x, v.y, v.z]
+                    });
+                    let uv = [i1, i2, i3].map(|i| {
+                        let t = &obj.tex_vertices[i.1.unwrap()];
+                        [t.u * width as f64, t.v * height as f64]
+                    });
+                    TriangleDistortion::new(xyz, uv)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether all triangles have texture coordinates and (almost) all of them have the same orientation in UV space,
+    /// i.e. the UV map does not fold over.
+    fn has_usable_uvs(object: &Object) -> bool {
+        let mut orientations = [0usize; 2];
+        for s in object.geometry[0].shapes.iter() {
+            if let Primitive::Triangle(i1, i2, i3) = s.primitive {
+                let (Some(t1), Some(t2), Some(t3)) = (i1.1, i2.1, i3.1) else {
+                    return false;
+                };
+                let uv = |t: usize| object.tex_vertices.get(t).map(|t| [t.u, t.v]);
+                let (Some(t1), Some(t2), Some(t3)) = (uv(t1), uv(t2), uv(t3)) else {
+                    return false;
+                };
+                let area = orient2d_f64(t1, t2, t3);
+                if area > 0.0 {
+                    orientations[0] += 1;
+                } else if area < 0.0 {
+                    orientations[1] += 1;
+                }
+            }
+        }
+        let total = orientations[0] + orientations[1];
+        total > 0 && orientations[0].min(orientations[1]) as f64 <= total as f64 * 0.02
+    }
+
+    /// Computes vertex normals from the faces if any triangle comes without normals
+    fn complete_normals(object: &mut Object) {
+        let has_normals = object.geometry[0].shapes.iter().all(|s| match s.primitive {
+            Primitive::Triangle(i1, i2, i3) => [i1.2, i2.2, i3.2].iter().all(|n| n.is_some()),
+            _ => true,
+        });
+        if has_normals {
+            return;
+        }
+        object.normals = super::ply::vertex_normals(&object.vertices, &object.geometry[0].shapes);
+        for s in object.geometry[0].shapes.iter_mut() {
+            if let Primitive::Triangle(i1, i2, i3) = s.primitive {
+                let vtn = |i: (usize, Option<usize>, Option<usize>)| (i.0, i.1, Some(i.0));
+                s.primitive = Primitive::Triangle(vtn(i1), vtn(i2), vtn(i3));
+            }
+        }
+    }
+
+    /// Replaces the texture coordinates by a least squares conformal map of the mesh, returns its size in voxels
+    fn flatten(object: &mut Object) -> [usize; 2] {
+        let vertices = object.vertices.iter().map(|v| [v.x, v.y, v.z]).collect::<Vec<_>>();
+        let triangles = object.geometry[0]
+            .shapes
+            .iter()
+            .filter_map(|s| match s.primitive {
+                Primitive::Triangle(i1, i2, i3) => Some([i1.0, i2.0, i3.0]),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let uvs = super::flatten::lscm(&vertices, &triangles);
+        object.tex_vertices = uvs.iter().map(|[u, v]| TVertex { u: *u, v: *v, w: 0.0 }).collect();
+        for s in object.geometry[0].shapes.iter_mut() {
+            if let Primitive::Triangle(i1, i2, i3) = s.primitive {
+                let vtn = |i: (usize, Option<usize>, Option<usize>)| (i.0, Some(i.0), i.2);
+                s.primitive = Primitive::Triangle(vtn(i1), vtn(i2), vtn(i3));
+            }
+        }
+
+        let size = |c: fn(&[f64; 2]) -> f64| {
+            let min = uvs.iter().map(c).fold(f64::INFINITY, f64::min);
+            let max = uvs.iter().map(c).fold(f64::NEG_INFINITY, f64::max);
+            (max - min).round().max(1.0) as usize
+        };
+        [size(|uv| uv[0]), size(|uv| uv[1])]
+    }
+
+    /// Replaces the texture coordinates by angle (scaled by the mean radius) and height around the axis, and the
+    /// normals by the direction away from the axis. Returns the size of the UV map in voxels.
+    fn project_cylindrical(object: &mut Object, center: [f64; 3], axis: [f64; 3]) -> [usize; 2] {
+        let axis = Vector3::from(axis).try_normalize(1e-12).unwrap_or_else(Vector3::z);
+        let reference = if axis.x.abs() < 0.9 { Vector3::x() } else { Vector3::y() };
+        let e1 = axis.cross(&reference).normalize();
+        let e2 = axis.cross(&e1);
+
+        let mut angles = vec![];
+        let mut heights = vec![];
+        let mut normals = vec![];
+        let mut radius_sum = 0.0;
+        for v in object.vertices.iter() {
+            let d = Vector3::new(v.x, v.y, v.z) - Vector3::from(center);
+            let height = d.dot(&axis);
+            let radial = d - height * axis;
+            let angle = atan2(radial.dot(&e2), radial.dot(&e1));
+            angles.push(if angle < 0.0 { angle + 2.0 * PI } else { angle });
+            heights.push(height);
+            let n = if radial.norm() > 0.0 { radial.normalize() } else { e1 };
+            normals.push(Normal { x: n.x, y: n.y, z: n.z });
+            radius_sum += radial.norm();
+        }
+        let radius = (radius_sum / object.vertices.len().max(1) as f64).max(1.0);
+
+        object.normals = normals;
+        object.tex_vertices = angles
+            .iter()
+            .zip(heights.iter())
+            .map(|(a, h)| TVertex {
+                u: a * radius,
+                v: *h,
+                w: 0.0,
+            })
+            .collect();
+        // triangles crossing the seam at angle 0 get extra texture vertices continuing beyond 2 PI
+        let mut wrapped = vec![None; angles.len()];
+        for s in object.geometry[0].shapes.iter_mut() {
+            if let Primitive::Triangle(i1, i2, i3) = s.primitive {
+                let tri = [i1.0, i2.0, i3.0].map(|i| angles[i]);
+                let crosses_seam = tri.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+                    - tri.iter().copied().fold(f64::INFINITY, f64::min)
+                    > PI;
+                let mut vtn = |i: (usize, Option<usize>, Option<usize>)| {
+                    let t = if crosses_seam && angles[i.0] < PI {
+                        *wrapped[i.0].get_or_insert_with(|| {
+                            object.tex_vertices.push(TVertex {
+                                u: (angles[i.0] + 2.0 * PI) * radius,
+                                v: heights[i.0],
+                                w: 0.0,
+                            });
+                            object.tex_vertices.len() - 1
+                        })
+                    } else {
+                        i.0
+                    };
+                    (i.0, Some(t), Some(i.0))
+                };
+                s.primitive = Primitive::Triangle(vtn(i1), vtn(i2), vtn(i3));
+            }
+        }
+
+        let size = |c: fn(&TVertex) -> f64| {
+            let min = object.tex_vertices.iter().map(c).fold(f64::INFINITY, f64::min);
+            let max = object.tex_vertices.iter().map(c).fold(f64::NEG_INFINITY, f64::max);
+            (max - min).round().max(1.0) as usize
+        };
+        [size(|t| t.u), size(|t| t.v)]
+    }
+
     fn has_inverted_uv_tris(obj: Object) -> bool {
         for s in obj.geometry[0].shapes.iter().skip(1) {
             match s.primitive {
@@ -362,6 +609,8 @@ pub struct ObjVolume {
     obj: Arc<ObjFile>,
     width: usize,
     height: usize,
+    /// per triangle distortion, computed when needed
+    distortion: Arc<OnceLock<Vec<Option<TriangleDistortion>>>>,
 }
 impl ObjVolume {
     pub fn load_from_obj(
@@ -371,13 +620,13 @@ impl ObjVolume {
         height: usize,
         transform: &Option<AffineTransform>,
         projection: ProjectionKind,
-    ) -> Self {
-        Self::new(
-            Arc::new(Self::load_obj(obj_file_path, transform, projection)),
+    ) -> Result<Self, VolumeError> {
+        Ok(Self::new(
+            Arc::new(Self::load_obj(obj_file_path, transform, projection)?),
             base_volume,
             width,
             height,
-        )
+        ))
     }
     pub fn new(obj: Arc<ObjFile>, base_volume: Volume, width: usize, height: usize) -> Self {
         Self {
@@ -385,11 +634,19 @@ impl ObjVolume {
             obj,
             width,
             height,
+            distortion: Arc::new(OnceLock::new()),
         }
     }
 
-    pub fn load_obj(file_path: &str, transform: &Option<AffineTransform>, projection: ProjectionKind) -> ObjFile {
-        let obj_file = std::fs::read_to_string(file_path).unwrap();
+    pub fn load_obj(
+        file_path: &str,
+        transform: &Option<AffineTransform>,
+        projection: ProjectionKind,
+    ) -> Result<ObjFile, VolumeError> {
+        if file_path.to_lowercase().ends_with(".ply") {
+            return super::load_ply(file_path, transform, projection);
+        }
+        let obj_file = std::fs::read_to_string(file_path).map_err(|e| VolumeError::Io(file_path.to_string(), e))?;
         // filter out opacity definitions that wavefront_obj does not cope well with
         let (not_faces, faces): (Vec<_>, Vec<_>) = obj_file
             .lines()
@@ -403,7 +660,8 @@ impl ObjVolume {
             .collect::<Vec<_>>()
             .join("\n");
 
-        let obj_set = obj::parse(obj_file).unwrap();
+        let obj_set =
+            obj::parse(obj_file).map_err(|e| VolumeError::InvalidFormat(file_path.to_string(), e.to_string()))?;
 
         let mut objects = obj_set.objects;
         /* println!("Loaded obj file with {} objects", objects.len());
@@ -416,16 +674,116 @@ impl ObjVolume {
             );
         } */
 
+        if objects.is_empty() {
+            return Err(VolumeError::NoData(file_path.to_string()));
+        }
         let object = objects.remove(0);
-        ObjFile::new(object, transform, projection)
+        Ok(ObjFile::new(object, transform, projection))
     }
 
+    pub fn obj(&self) -> Arc<ObjFile> {
+        self.obj.clone()
+    }
     pub fn width(&self) -> usize {
         self.width
     }
     pub fn height(&self) -> usize {
         self.height
     }
+    /// Distortion of each triangle for this segment size, in the order of the triangles in the obj file
+    pub fn triangle_distortion(&self) -> &[Option<TriangleDistortion>] {
+        self.distortion
+            .get_or_init(|| self.obj.triangle_distortion(self.width, self.height))
+    }
+    pub fn distortion_stats(&self) -> DistortionStats {
+        DistortionStats::new(self.triangle_distortion())
+    }
+    /// Writes a PPM file with the volume coordinates and the normal of the surface for every pixel of the segment.
+    ///
+    /// The UV triangles are rasterized and positions and normals are interpolated with barycentric weights. Pixels not
+    /// covered by any triangle are 0. The file is written through a memory map, so that large segments do not need to
+    /// fit into memory.
+    pub fn write_ppm(&self, file_path: &str) -> std::io::Result<()> {
+        use memmap::MmapMut;
+        use std::fs::OpenOptions;
+
+        let (width, height) = (self.width(), self.height());
+        let header = format!(
+            "width: {}\nheight: {}\ndim: 6\nordered: true\ntype: double\nversion: 1\n<>\n",
+            width, height
+        );
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(file_path)?;
+        file.set_len((header.len() + width * height * 6 * 8) as u64)?;
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        map[..header.len()].copy_from_slice(header.as_bytes());
+        // the header length is arbitrary, so values are not necessarily aligned and have to be written bytewise
+        let data = &mut map[header.len()..];
+
+        let obj = &self.obj.object;
+        for s in obj.geometry[0].shapes.iter() {
+            let Primitive::Triangle(i1, i2, i3) = s.primitive else {
+                continue;
+            };
+            let uv = |i: usize| {
+                let t = &obj.tex_vertices[i];
+                [t.u * width as f64, self.v(t.v) * height as f64]
+            };
+            let [p1, p2, p3] = [uv(i1.1.unwrap()), uv(i2.1.unwrap()), uv(i3.1.unwrap())];
+            let area = orient2d_f64(p1, p2, p3);
+            if area == 0.0 {
+                continue;
+            }
+            let min_u = p1[0].min(p2[0]).min(p3[0]).floor().max(0.0) as usize;
+            let max_u = (p1[0].max(p2[0]).max(p3[0]).ceil() as usize).min(width.saturating_sub(1));
+            let min_v = p1[1].min(p2[1]).min(p3[1]).floor().max(0.0) as usize;
+            let max_v = (p1[1].max(p2[1]).max(p3[1]).ceil() as usize).min(height.saturating_sub(1));
+
+            for v in min_v..=max_v {
+                for u in min_u..=max_u {
+                    let p = [u as f64, v as f64];
+                    // barycentric weights, normalized so that they are positive inside regardless of the orientation
+                    let w = [
+                        orient2d_f64(p2, p3, p) / area,
+                        orient2d_f64(p3, p1, p) / area,
+                        orient2d_f64(p1, p2, p) / area,
+                    ];
+                    if w.iter().any(|w| *w < -1e-9) {
+                        continue;
+                    }
+                    let [xyz1, xyz2, xyz3] = [i1.0, i2.0, i3.0].map(|i| obj.vertices[i]);
+                    let [n1, n2, n3] = [i1.2.unwrap(), i2.2.unwrap(), i3.2.unwrap()].map(|i| obj.normals[i]);
+                    let mut normal = [
+                        w[0] * n1.x + w[1] * n2.x + w[2] * n3.x,
+                        w[0] * n1.y + w[1] * n2.y + w[2] * n3.y,
+                        w[0] * n1.z + w[1] * n2.z + w[2] * n3.z,
+                    ];
+                    let len = sqrt(normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]);
+                    if len > 0.0 {
+                        normal = normal.map(|c| c / len);
+                    }
+                    let values = [
+                        w[0] * xyz1.x + w[1] * xyz2.x + w[2] * xyz3.x,
+                        w[0] * xyz1.y + w[1] * xyz2.y + w[2] * xyz3.y,
+                        w[0] * xyz1.z + w[1] * xyz2.z + w[2] * xyz3.z,
+                        normal[0],
+                        normal[1],
+                        normal[2],
+                    ];
+                    let off = (v * width + u) * 6 * 8;
+                    for (i, value) in values.iter().enumerate() {
+                        data[off + i * 8..off + (i + 1) * 8].copy_from_slice(&value.to_le_bytes());
+                    }
+                }
+            }
+        }
+        map.flush()
+    }
+
     pub fn convert_to_volume_coords(&self, coord: [i32; 3]) -> [i32; 3] {
         let u = coord[0];
         let v = coord[1];
@@ -500,147 +858,120 @@ impl ObjVolume {
         [-1, -1, -1]
     }
 
-    fn v(&self, v: f64) -> f64 {
-        if self.obj.has_inverted_uv_tris {
-            v
-        } else {
-            1.0 - v
+    /// The reverse of `convert_to_volume_coords`: finds the surface point closest to a volume position, within
+    /// `max_distance` voxels, and returns its segment coordinates with the offset along the normal as w.
+    pub fn convert_to_uv_coords(&self, xyz: [f64; 3], max_distance: f64) -> Option<[i32; 3]> {
+        let obj = &self.obj.object;
+        let min = xyz.map(|c| c - max_distance);
+        let max = xyz.map(|c| c + max_distance);
+
+        let mut best = None;
+        let mut best_dist2 = max_distance * max_distance;
+        for i in self.obj.xyz_index.in_bounds(min, max) {
+            let Primitive::Triangle(i1, i2, i3) = obj.geometry[0].shapes[i].primitive else {
+                continue;
+            };
+            let tri = [i1, i2, i3].map(|i| {
+                let v = obj.vertices[i.0];
+                [v.x, v.y, v.z]
+            });
+            let Some((p, bary)) = closest_point_on_triangle(xyz, tri) else {
+                continue;
+            };
+            let dist2 = (0..3).map(|k| (xyz[k] - p[k]).powi(2)).sum::<f64>();
+            if dist2 <= best_dist2 {
+                best_dist2 = dist2;
+                best = Some(([i1, i2, i3], p, bary));
+            }
         }
-    }
-}
 
-fn orient2d(u1: i32, v1: i32, u2: i32, v2: i32, u3: i32, v3: i32) -> i32 {
-    (u2 - u1) * (v3 - v1) - (v2 - v1) * (u3 - u1)
-}
+        let (indices, p, bary) = best?;
+        let mut tex = [0.0; 2];
+        let mut normal = [0.0; 3];
+        for (idx, b) in indices.iter().zip(bary) {
+            let t = obj.tex_vertices[idx.1?];
+            tex[0] += b * t.u;
+            tex[1] += b * t.v;
+            if let Some(n) = idx.2.map(|n| obj.normals[n]) {
+                normal[0] += b * n.x;
+                normal[1] += b * n.y;
+                normal[2] += b * n.z;
+            }
+        }
+        let norm = sqrt(normal.iter().map(|n| n * n).sum::<f64>());
+        let w = if norm > 0.0 {
+            (0..3).map(|k| (xyz[k] - p[k]) * normal[k]).sum::<f64>() / norm
+        } else {
+            0.0
+        };
 
-trait CompositionState {
-    fn update(&mut self, a: u8) -> bool;
-    fn result(&self, num_layers: u32) -> u8;
-    fn reset(&mut self);
-}
-struct MaxCompositionState {
-    value: u8,
-}
-impl MaxCompositionState {
-    fn new() -> Self {
-        Self { value: 0 }
+        Some([
+            (tex[0] * self.width() as f64) as i32,
+            (self.v(tex[1]) * self.height() as f64) as i32,
+            w.round() as i32,
+        ])
     }
-}
-impl CompositionState for MaxCompositionState {
-    fn update(&mut self, a: u8) -> bool {
-        self.value = self.value.max(a);
-        true
-    }
-    fn result(&self, _num_layers: u32) -> u8 {
-        self.value
-    }
-    fn reset(&mut self) {
-        self.value = 0;
-    }
-}
-struct NoCompositionState;
-impl CompositionState for NoCompositionState {
-    fn update(&mut self, _a: u8) -> bool {
-        false
-    }
-    fn result(&self, _num_layers: u32) -> u8 {
-        0
-    }
-    fn reset(&mut self) {}
-}
 
-struct AlphaCompositionState {
-    min: f32,
-    max: f32,
-    alpha_cutoff: f32,
-    opacity: f32,
-    value: f32,
-    alpha: f32,
-}
-impl AlphaCompositionState {
-    fn new(min: f32, max: f32, alpha_cutoff: f32, opacity: f32) -> Self {
-        Self {
-            min,
-            max,
-            alpha_cutoff,
-            opacity: opacity,
-            value: 0.0,
-            alpha: 0.0,
+    fn v(&self, v: f64) -> f64 {
+        if self.obj.has_inverted_uv_tris {
+            v
+        } else {
+            1.0 - v
         }
     }
 }
-impl CompositionState for AlphaCompositionState {
-    fn update(&mut self, a: u8) -> bool {
-        let value = ((a as f32 / 255.0 - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
 
-        if value == 0.0 {
-            // speed through empty area
-            return true;
+/// Closest point to `p` on a triangle together with its barycentric coordinates, `None` for degenerate triangles
+pub(super) fn closest_point_on_triangle(p: [f64; 3], tri: [[f64; 3]; 3]) -> Option<([f64; 3], [f64; 3])> {
+    let sub = |a: [f64; 3], b: [f64; 3]| [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    let [a, b, c] = tri;
+    let (ab, ac) = (sub(b, a), sub(c, a));
+
+    // check the vertex and edge regions first, see Ericson, Real-Time Collision Detection, 5.1.5
+    let ap = sub(p, a);
+    let (d1, d2) = (dot(ab, ap), dot(ac, ap));
+    let bary = if d1 <= 0.0 && d2 <= 0.0 {
+        [1.0, 0.0, 0.0]
+    } else {
+        let bp = sub(p, b);
+        let (d3, d4) = (dot(ab, bp), dot(ac, bp));
+        let cp = sub(p, c);
+        let (d5, d6) = (dot(ab, cp), dot(ac, cp));
+        let vc = d1 * d4 - d3 * d2;
+        let vb = d5 * d2 - d1 * d6;
+        let va = d3 * d6 - d5 * d4;
+        if d3 >= 0.0 && d4 <= d3 {
+            [0.0, 1.0, 0.0]
+        } else if d6 >= 0.0 && d5 <= d6 {
+            [0.0, 0.0, 1.0]
+        } else if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            let v = d1 / (d1 - d3);
+            [1.0 - v, v, 0.0]
+        } else if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            let w = d2 / (d2 - d6);
+            [1.0 - w, 0.0, w]
+        } else if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+            let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+            [0.0, 1.0 - w, w]
+        } else {
+            let v = vb / (va + vb + vc);
+            let w = vc / (va + vb + vc);
+            [1.0 - v - w, v, w]
         }
-
-        let weight = (1.0 - self.alpha) * (value * self.opacity).min(1.0);
-        self.value += weight * value;
-        self.alpha += weight;
-
-        return self.alpha < self.alpha_cutoff;
-    }
-    fn result(&self, _num_layers: u32) -> u8 {
-        (self.value * 255.0).clamp(0.0, 255.0) as u8
-    }
-    fn reset(&mut self) {
-        self.value = 0.0;
-        self.alpha = 0.0;
+    };
+    if bary.iter().any(|b| !b.is_finite()) {
+        return None;
     }
+    let point = [0, 1, 2].map(|k| bary[0] * a[k] + bary[1] * b[k] + bary[2] * c[k]);
+    Some((point, bary))
 }
 
-struct AlphaHeightMapCompositionState {
-    min: f32,
-    max: f32,
-    alpha_cutoff: f32,
-    opacity: f32,
-    alpha: f32,
-    depth: f32,
-    weighted_depth: f32,
-}
-impl AlphaHeightMapCompositionState {
-    fn new(min: f32, max: f32, alpha_cutoff: f32, opacity: f32) -> Self {
-        Self {
-            min,
-            max,
-            alpha_cutoff,
-            opacity: opacity,
-            alpha: 0.0,
-            depth: 0.0,
-            weighted_depth: 0.0,
-        }
-    }
+fn orient2d(u1: i32, v1: i32, u2: i32, v2: i32, u3: i32, v3: i32) -> i32 {
+    (u2 - u1) * (v3 - v1) - (v2 - v1) * (u3 - u1)
 }
-impl CompositionState for AlphaHeightMapCompositionState {
-    fn update(&mut self, a: u8) -> bool {
-        let value = ((a as f32 / 255.0 - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
-
-        if value == 0.0 {
-            // speed through empty area
-            self.depth += 1.0;
-            return true;
-        }
-
-        let weight = (1.0 - self.alpha) * (value * self.opacity).min(1.0);
-        self.alpha += weight;
-        self.weighted_depth += weight * self.depth;
-        self.depth += 1.0;
-
-        return self.alpha < self.alpha_cutoff;
-    }
-    fn result(&self, num_layers: u32) -> u8 {
-        //((1.0 - self.weighted_depth * 4.0 / self.alpha / num_layers as f32) * 255.0).clamp(0.0, 255.0) as u8
-        (255.0 - self.weighted_depth / self.alpha * 255.0 / num_layers as f32).clamp(0.0, 255.0) as u8
-    }
-    fn reset(&mut self) {
-        self.depth = 0.0;
-        self.weighted_depth = 0.0;
-        self.alpha = 0.0;
-    }
+fn orient2d_f64(p1: [f64; 2], p2: [f64; 2], p3: [f64; 2]) -> f64 {
+    (p2[0] - p1[0]) * (p3[1] - p1[1]) - (p2[1] - p1[1]) * (p3[0] - p1[0])
 }
 
 impl PaintVolume for ObjVolume {
@@ -662,27 +993,9 @@ impl PaintVolume for ObjVolume {
         assert!(plane_coord == 2);
 
         let draw_outlines = config.draw_xyz_outlines;
-        let composite = config.compositing.mode != CompositingMode::None;
-        let composite_layers_in_front = config.compositing.layers_in_front as i32;
-        let composite_layers_behind = config.compositing.layers_behind as i32;
-        let composite_total_layers = composite_layers_in_front + composite_layers_behind + 1; // +1 for the current layer
-        let mut composition: Box<dyn CompositionState> = match config.compositing.mode {
-            CompositingMode::Max => Box::new(MaxCompositionState::new()),
-            CompositingMode::Alpha => Box::new(AlphaCompositionState::new(
-                config.compositing.alpha_min as f32 / 255.0,
-                config.compositing.alpha_max as f32 / 255.0,
-                config.compositing.alpha_threshold as f32 / 10000.0,
-                config.compositing.opacity as f32 / 100.0,
-            )),
-            CompositingMode::AlphaHeightMap => Box::new(AlphaHeightMapCompositionState::new(
-                config.compositing.alpha_min as f32 / 255.0,
-                config.compositing.alpha_max as f32 / 255.0,
-                config.compositing.alpha_threshold as f32 / 10000.0,
-                config.compositing.opacity as f32 / 100.0,
-            )),
-            CompositingMode::None => Box::new(NoCompositionState {}),
-        };
-        let composite_direction = if config.compositing.reverse_direction { -1 } else { 1 };
+        let mut composition = LayerComposition::new(&config.compositing);
+        let composite = composition.is_enabled();
+        let distortion = config.show_distortion.then(|| self.triangle_distortion());
 
         let real_xyz = if draw_outlines {
             self.convert_to_volume_coords(xyz)
@@ -694,8 +1007,6 @@ impl PaintVolume for ObjVolume {
 
         let ffactor = sfactor as f64;
 
-        let w_factor = xyz[2] as f64;
-
         let min_u = xyz[0] - width as i32 / 2 * paint_zoom as i32;
         let max_u = xyz[0] + width as i32 / 2 * paint_zoom as i32;
         let min_v = xyz[1] - height as i32 / 2 * paint_zoom as i32;
@@ -863,10 +1174,10 @@ impl PaintVolume for ObjVolume {
                                             (nx, ny, nz)
                                         };
 
-                                        let value = if !composite {
-                                            let x = x + w_factor * nx;
-                                            let y = y + w_factor * ny;
-                                            let z = z + w_factor * nz;
+                                        let value = composition.value(xyz[2], |w| {
+                                            let x = x + w * nx;
+                                            let y = y + w * ny;
+                                            let z = z + w * nz;
 
                                             if config.trilinear_interpolation {
                                                 volume.get_interpolated(
@@ -876,44 +1187,25 @@ impl PaintVolume for ObjVolume {
                                             } else {
                                                 volume.get([x / ffactor, y / ffactor, z / ffactor], sfactor as i32)
                                             }
-                                        } else {
-                                            composition.reset();
-                                            let start = xyz[2] + composite_direction * composite_layers_in_front;
-                                            let end = xyz[2] - composite_direction * (composite_layers_behind + 1);
-
-                                            let step = if start < end { 1 } else { -1 };
-
-                                            let mut w = start;
-                                            while w != end {
-                                                let w_factor = w as f64;
-
-                                                let x = x + w_factor * nx;
-                                                let y = y + w_factor * ny;
-                                                let z = z + w_factor * nz;
-
-                                                let new_value = if config.trilinear_interpolation {
-                                                    volume.get_interpolated(
-                                                        [x / ffactor, y / ffactor, z / ffactor],
-                                                        sfactor as i32,
-                                                    )
-                                                } else {
-                                                    volume.get([x / ffactor, y / ffactor, z / ffactor], sfactor as i32)
-                                                };
-
-                                                if !composition.update(new_value) {
-                                                    break;
-                                                }
-
-                                                w += step;
-                                            }
-                                            composition.result(composite_total_layers as u32)
-                                        };
+                                        });
 
                                         buffer.set_gray(
                                             u as usize / paint_zoom as usize,
                                             v as usize / paint_zoom as usize,
                                             value,
                                         );
+                                        if let Some(distortion) = distortion {
+                                            // degenerate triangles are shown like the largest distortion
+                                            let [r, g, b] = distortion[i].map_or([255, 0, 0], |d| d.color());
+                                            let mix = |c: u8| ((value as u16 + c as u16) / 2) as u8;
+                                            buffer.set_rgb(
+                                                u as usize / paint_zoom as usize,
+                                                v as usize / paint_zoom as usize,
+                                                mix(r),
+                                                mix(g),
+                                                mix(b),
+                                            );
+                                        }
 
                                         if draw_outlines {
                                             if (x - real_xyz[0] as f64).abs() < 2.0 {
@@ -958,12 +1250,15 @@ impl PaintVolume for ObjVolume {
         let height = self.height;
         let volume = self.volume.shared();
 
+        let distortion = self.distortion.clone();
+
         Box::new(move || {
             ObjVolume {
                 volume: volume(),
                 obj,
                 width,
                 height,
+                distortion,
             }
             .into_volume()
         })
@@ -1119,7 +1414,8 @@ impl SurfaceVolume for ObjVolume {
                             let (r, g, b, rp, gp, bp) = if should_highlight {
                                 (0xff, 0xaa, 0, 0, 0xff, 0)
                             } else {
-                                (0xff, 0, 0xff, 0, 0, 0xff)
+                                let [r, g, b] = config.segment_outline_color;
+                                (r, g, b, 0, 0, 0xff)
                             };
 
                             line(x0, y0, x1, y1, image, width, height, r, g, b);
@@ -1146,7 +1442,18 @@ fn paint_zoom_align_up(v: i32, paint_zoom: u8) -> i32 {
 }
 
 #[allow(dead_code)] // useful for debugging triangle shapes
-fn line(x0: i32, y0: i32, x1: i32, y1: i32, buffer: &mut Image, width: usize, height: usize, r: u8, g: u8, b: u8) {
+pub(super) fn line(
+    x0: i32,
+    y0: i32,
+    x1: i32,
+    y1: i32,
+    buffer: &mut Image,
+    width: usize,
+    height: usize,
+    r: u8,
+    g: u8,
+    b: u8,
+) {
     // simple bresenham algorithm from https://en.wikipedia.org/wiki/Bresenham%27s_line_algorithm
 
     let dx = (x1 as i32 - x0 as i32).abs();
@@ -1176,7 +1483,7 @@ fn line(x0: i32, y0: i32, x1: i32, y1: i32, buffer: &mut Image, width: usize, he
     }
 }
 
-fn point(x0: i32, y0: i32, buffer: &mut Image, width: usize, r: u8, g: u8, b: u8) {
+pub(super) fn point(x0: i32, y0: i32, buffer: &mut Image, width: usize, r: u8, g: u8, b: u8) {
     let halfw = width as i32 / 2;
     for x in x0 - halfw..x0 + halfw {
         for y in y0 - halfw..y0 + halfw {
@@ -1194,4 +1501,181 @@ impl VoxelVolume for ObjVolume {
     fn reset_for_painting(&self) {
         self.volume.reset_for_painting();
     }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        // the surface is sampled with one pixel per voxel of the base volume
+        let base = self.volume.metadata()?;
+        Some(VolumeMetadata {
+            shape: vec![[self.width, self.height, 1]],
+            ..base
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::test_segment::{self, HEIGHT, WIDTH};
+    use crate::volume::{EmptyVolume, PPMVolume};
+
+    /// Writes a closed ring with radius 50 around x = y = 100 and height 100 as an obj file without texture coordinates
+    fn write_ring(dir: &std::path::Path) -> std::path::PathBuf {
+        let (cols, rows) = (32, 3);
+        let mut obj = String::new();
+        for r in 0..rows {
+            for c in 0..cols {
+                let angle = 2.0 * PI * c as f64 / cols as f64;
+                obj += &format!(
+                    "v {} {} {}\n",
+                    100.0 + 50.0 * angle.cos(),
+                    100.0 + 50.0 * angle.sin(),
+                    r * 50
+                );
+            }
+        }
+        for r in 0..rows - 1 {
+            for c in 0..cols {
+                let i = r * cols + c + 1;
+                let next = r * cols + (c + 1) % cols + 1;
+                obj += &format!(
+                    "f {} {} {}\nf {} {} {}\n",
+                    i,
+                    next,
+                    i + cols,
+                    next,
+                    next + cols,
+                    i + cols
+                );
+            }
+        }
+        let path = dir.join("ring.obj");
+        std::fs::write(&path, obj).unwrap();
+        path
+    }
+
+    #[test]
+    fn cylindrical_projection() {
+        assert_eq!(
+            ProjectionKind::cylindrical_from_spec("100, 100, 0"),
+            Ok(ProjectionKind::Cylindrical {
+                center: [100.0, 100.0, 0.0],
+                axis: [0.0, 0.0, 1.0]
+            })
+        );
+        assert!(ProjectionKind::cylindrical_from_spec("1,2").is_err());
+        assert!(ProjectionKind::cylindrical_from_spec("1,2,3,0,0,0").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_ring(dir.path());
+
+        let projection = ProjectionKind::cylindrical_from_spec("100,100,0").unwrap();
+        let obj = ObjVolume::load_obj(path.to_str().unwrap(), &None, projection).unwrap();
+        let [width, height] = obj.flattened_size().unwrap();
+        assert!((width as f64 - 2.0 * PI * 50.0).abs() < 2.0, "width {}", width);
+        assert_eq!(height, 100);
+
+        let volume = ObjVolume::new(Arc::new(obj), EmptyVolume {}.into_volume(), width, height);
+        let radius = |xyz: [i32; 3]| (((xyz[0] - 100).pow(2) + (xyz[1] - 100).pow(2)) as f64).sqrt();
+        // including the triangles across the seam
+        for u in [10, width as i32 / 2, width as i32 - 2] {
+            let xyz = volume.convert_to_volume_coords([u, 50, 0]);
+            assert!((radius(xyz) - 50.0).abs() < 1.5, "{:?}", xyz);
+            assert_eq!(xyz[2], 50);
+            let xyz = volume.convert_to_volume_coords([u, 50, 5]);
+            assert!((radius(xyz) - 55.0).abs() < 1.5, "{:?}", xyz);
+
+            // and back to the segment
+            let uvw = volume.convert_to_uv_coords(xyz.map(|c| c as f64), 10.0).unwrap();
+            assert!((uvw[0] - u).abs() <= 2 && (uvw[1] - 50).abs() <= 1, "{:?}", uvw);
+            assert!((uvw[2] - 5).abs() <= 1, "{:?}", uvw);
+        }
+        assert_eq!(volume.convert_to_uv_coords([100.0, 100.0, 50.0], 10.0), None);
+    }
+
+    #[test]
+    fn load_obj_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.obj");
+        assert!(matches!(
+            ObjVolume::load_obj(missing.to_str().unwrap(), &None, ProjectionKind::None),
+            Err(VolumeError::Io(_, _))
+        ));
+        let missing = dir.path().join("missing.ply");
+        assert!(matches!(
+            ObjVolume::load_obj(missing.to_str().unwrap(), &None, ProjectionKind::None),
+            Err(VolumeError::Io(_, _))
+        ));
+        let invalid = dir.path().join("invalid.obj");
+        std::fs::write(&invalid, "v 1 2\nf 1 2 3\n").unwrap();
+        assert!(matches!(
+            ObjVolume::load_obj(invalid.to_str().unwrap(), &None, ProjectionKind::None),
+            Err(VolumeError::InvalidFormat(_, _))
+        ));
+    }
+
+    #[test]
+    fn ring_plane_intersection() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_ring(dir.path());
+        let obj = ObjVolume::load_obj(path.to_str().unwrap(), &None, ProjectionKind::None).unwrap();
+
+        // the ring crosses z = 25 in a closed polygon through the 32 vertical edges and 32 diagonals
+        let intersection = obj.plane_intersection(2, 25.0);
+        assert_eq!(intersection.polylines.len(), 1);
+        let polygon = &intersection.polylines[0];
+        assert_eq!(polygon.len(), 65);
+        assert_eq!(polygon.first(), polygon.last());
+        for p in polygon {
+            assert_eq!(p[2], 25.0);
+            assert!((((p[0] - 100.0).powi(2) + (p[1] - 100.0).powi(2)).sqrt() - 50.0).abs() < 1.0);
+        }
+        // the vertices of the middle row lie exactly on z = 50
+        assert_eq!(obj.plane_intersection(2, 50.0).polylines[0].len(), 33);
+
+        // and x = 101 in two open lines along z
+        let intersection = obj.plane_intersection(0, 101.0);
+        assert_eq!(intersection.polylines.len(), 2);
+        for line in &intersection.polylines {
+            let z = line.iter().map(|p| p[2]).collect::<Vec<_>>();
+            assert!(z.contains(&0.0) && z.contains(&100.0), "{:?}", line);
+        }
+        assert!(obj.plane_intersection(2, 150.0).polylines.is_empty());
+    }
+
+    #[test]
+    fn plane_touching_vertices_from_above() {
+        let dir = tempfile::tempdir().unwrap();
+        let obj = ObjVolume::load_obj(write_ring(dir.path()).to_str().unwrap(), &None, ProjectionKind::None).unwrap();
+
+        // the plane touches the top row of the ring at z = 100 from above
+        let intersection = obj.plane_intersection(2, 100.0);
+        assert_eq!(intersection.polylines.len(), 1);
+        let polygon = &intersection.polylines[0];
+        assert_eq!(polygon.len(), 33);
+        assert_eq!(polygon.first(), polygon.last());
+        assert!(polygon.iter().all(|p| p[2] == 100.0));
+    }
+
+    #[test]
+    fn ppm_of_obj_without_normals() {
+        // the test segment with texture coordinates but without normals
+        let mut obj = String::new();
+        for [x, y, z, u, v] in test_segment::vertices() {
+            obj += &format!("v {} {} {}\nvt {} {}\n", x, y, z, u, v);
+        }
+        for [a, b, c, d] in test_segment::quads().into_iter().map(|q| q.map(|i| i + 1)) {
+            obj += &format!("f {0}/{0} {1}/{1} {3}/{3}\nf {1}/{1} {2}/{2} {3}/{3}\n", a, b, c, d);
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("segment.obj");
+        std::fs::write(&path, obj).unwrap();
+
+        let obj = ObjVolume::load_obj(path.to_str().unwrap(), &None, ProjectionKind::None).unwrap();
+        assert_eq!(obj.flattened_size(), None);
+        let volume = ObjVolume::new(Arc::new(obj), EmptyVolume {}.into_volume(), WIDTH, HEIGHT);
+        let ppm_file = dir.path().join("segment.ppm");
+        volume.write_ppm(ppm_file.to_str().unwrap()).unwrap();
+
+        let ppm = PPMVolume::new(ppm_file.to_str().unwrap(), EmptyVolume {}.into_volume());
+        test_segment::assert_volume_coords(|uvw| ppm.convert_to_world_coords(uvw));
+    }
 }
@@ -0,0 +1,340 @@
+use super::{AffineTransform, ObjFile, ProjectionKind, VolumeError};
+use libm::sqrt;
+use wavefront_obj::obj::{Geometry, Normal, Object, Primitive, Shape, TVertex, Vertex};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+impl PlyType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => PlyType::I8,
+            "uchar" | "uint8" => PlyType::U8,
+            "short" | "int16" => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32" => PlyType::I32,
+            "uint" | "uint32" => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return None,
+        })
+    }
+    fn size(&self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PlyProperty {
+    Scalar(String, PlyType),
+    // name, type of the count, type of the items
+    List(String, PlyType, PlyType),
+}
+
+#[derive(Debug, Clone)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+/// Reads values in the order they appear in the body of a PLY file
+enum PlyReader<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary(&'a [u8]),
+}
+impl PlyReader<'_> {
+    fn read(&mut self, ty: PlyType) -> Option<f64> {
+        match self {
+            PlyReader::Ascii(tokens) => tokens.next()?.parse::<f64>().ok(),
+            PlyReader::Binary(data) => {
+                let size = ty.size();
+                if data.len() < size {
+                    return None;
+                }
+                let (bytes, rest) = data.split_at(size);
+                *data = rest;
+                Some(match ty {
+                    PlyType::I8 => bytes[0] as i8 as f64,
+                    PlyType::U8 => bytes[0] as f64,
+                    PlyType::I16 => i16::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    PlyType::U16 => u16::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    PlyType::I32 => i32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    PlyType::U32 => u32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    PlyType::F32 => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    PlyType::F64 => f64::from_le_bytes(bytes.try_into().unwrap()),
+                })
+            }
+        }
+    }
+}
+
+/// Loads a triangle mesh with per-vertex texture coordinates from an ASCII or binary little-endian PLY file.
+///
+/// Texture coordinates are read from `u`/`v`, `s`/`t` or `texture_u`/`texture_v` vertex properties. Normals are read
+/// from `nx`/`ny`/`nz` if available and otherwise computed from the faces. Polygons are split into triangle fans.
+pub fn load_ply(
+    file_path: &str,
+    transform: &Option<AffineTransform>,
+    projection: ProjectionKind,
+) -> Result<ObjFile, VolumeError> {
+    let data = std::fs::read(file_path).map_err(|e| VolumeError::Io(file_path.to_string(), e))?;
+    let object = parse_ply(&data).map_err(|e| VolumeError::InvalidFormat(file_path.to_string(), e))?;
+    Ok(ObjFile::new(object, transform, projection))
+}
+
+fn parse_ply(data: &[u8]) -> Result<Object, String> {
+    // header
+    let header_end = data
+        .windows(b"end_header".len())
+        .position(|w| w == b"end_header")
+        .ok_or("missing end_header")?;
+    let body_start = data[header_end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map(|p| header_end + p + 1)
+        .ok_or("missing end of header")?;
+    let header = std::str::from_utf8(&data[..header_end]).map_err(|_| "header is not valid text")?;
+
+    let mut lines = header.lines();
+    if lines.next().map(|l| l.trim()) != Some("ply") {
+        return Err("not a PLY file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    for line in lines {
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        let unknown_type = |t: &str| format!("unknown property type '{}'", t);
+        match parts.as_slice() {
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", f, _] => return Err(format!("unsupported format '{}'", f)),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid element count '{}'", count))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_ty, item_ty, name] => {
+                let element = elements.last_mut().ok_or("property before element")?;
+                element.properties.push(PlyProperty::List(
+                    name.to_string(),
+                    PlyType::parse(count_ty).ok_or_else(|| unknown_type(count_ty))?,
+                    PlyType::parse(item_ty).ok_or_else(|| unknown_type(item_ty))?,
+                ));
+            }
+            ["property", ty, name] => {
+                let element = elements.last_mut().ok_or("property before element")?;
+                element.properties.push(PlyProperty::Scalar(
+                    name.to_string(),
+                    PlyType::parse(ty).ok_or_else(|| unknown_type(ty))?,
+                ));
+            }
+            _ => {} // comments, obj_info, empty lines
+        }
+    }
+    let format = format.ok_or("missing format")?;
+
+    // body
+    let body = &data[body_start..];
+    let mut reader = match format {
+        PlyFormat::Ascii => PlyReader::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| "body is not valid text")?
+                .split_ascii_whitespace(),
+        ),
+        PlyFormat::BinaryLittleEndian => PlyReader::Binary(body),
+    };
+    let truncated = || "file is truncated".to_string();
+
+    let mut vertices = vec![];
+    let mut tex_vertices = vec![];
+    let mut normals = vec![];
+    let mut faces: Vec<Vec<usize>> = vec![];
+    for element in elements.iter() {
+        for _ in 0..element.count {
+            let mut values = std::collections::HashMap::new();
+            let mut indices = vec![];
+            for property in element.properties.iter() {
+                match property {
+                    PlyProperty::Scalar(name, ty) => {
+                        values.insert(name.as_str(), reader.read(*ty).ok_or_else(truncated)?);
+                    }
+                    PlyProperty::List(name, count_ty, item_ty) => {
+                        let count = reader.read(*count_ty).ok_or_else(truncated)? as usize;
+                        let items = (0..count)
+                            .map(|_| reader.read(*item_ty).ok_or_else(truncated))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        if name == "vertex_indices" || name == "vertex_index" {
+                            indices = items.into_iter().map(|i| i as usize).collect();
+                        }
+                    }
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => {
+                    let get = |names: &[&str]| names.iter().find_map(|n| values.get(n).copied());
+                    vertices.push(Vertex {
+                        x: get(&["x"]).ok_or("vertex without x")?,
+                        y: get(&["y"]).ok_or("vertex without y")?,
+                        z: get(&["z"]).ok_or("vertex without z")?,
+                    });
+                    if let (Some(u), Some(v)) = (get(&["u", "s", "texture_u"]), get(&["v", "t", "texture_v"])) {
+                        tex_vertices.push(TVertex { u, v, w: 0.0 });
+                    }
+                    if let (Some(x), Some(y), Some(z)) = (get(&["nx"]), get(&["ny"]), get(&["nz"])) {
+                        normals.push(Normal { x, y, z });
+                    }
+                }
+                "face" => faces.push(indices),
+                _ => {}
+            }
+        }
+    }
+
+    if tex_vertices.len() != vertices.len() {
+        // replaced by the projection or by flattening the mesh
+        tex_vertices = vec![TVertex { u: 0.0, v: 0.0, w: 0.0 }; vertices.len()];
+    }
+
+    let mut shapes = vec![];
+    for face in faces.iter() {
+        if let Some(i) = face.iter().find(|i| **i >= vertices.len()) {
+            return Err(format!("face references missing vertex {}", i));
+        }
+        for k in 1..face.len().saturating_sub(1) {
+            let vtn = |i: usize| (i, Some(i), Some(i));
+            shapes.push(Shape {
+                primitive: Primitive::Triangle(vtn(face[0]), vtn(face[k]), vtn(face[k + 1])),
+                groups: vec![],
+                smoothing_groups: vec![],
+            });
+        }
+    }
+    if shapes.is_empty() {
+        return Err("no faces".to_string());
+    }
+
+    if normals.len() != vertices.len() {
+        normals = vertex_normals(&vertices, &shapes);
+    }
+
+    Ok(Object {
+        name: "ply".to_string(),
+        vertices,
+        tex_vertices,
+        normals,
+        geometry: vec![Geometry {
+            material_name: None,
+            shapes,
+        }],
+    })
+}
+
+/// Area weighted average of the normals of adjacent faces
+pub(super) fn vertex_normals(vertices: &[Vertex], shapes: &[Shape]) -> Vec<Normal> {
+    let mut sums = vec![[0.0f64; 3]; vertices.len()];
+    for shape in shapes {
+        if let Primitive::Triangle((a, _, _), (b, _, _), (c, _, _)) = shape.primitive {
+            let [pa, pb, pc] = [a, b, c].map(|i| [vertices[i].x, vertices[i].y, vertices[i].z]);
+            let u = [0, 1, 2].map(|i| pb[i] - pa[i]);
+            let v = [0, 1, 2].map(|i| pc[i] - pa[i]);
+            let n = [
+                u[1] * v[2] - u[2] * v[1],
+                u[2] * v[0] - u[0] * v[2],
+                u[0] * v[1] - u[1] * v[0],
+            ];
+            for i in [a, b, c] {
+                for k in 0..3 {
+                    sums[i][k] += n[k];
+                }
+            }
+        }
+    }
+    sums.into_iter()
+        .map(|[x, y, z]| {
+            let len = sqrt(x * x + y * y + z * z);
+            if len > 0.0 {
+                Normal {
+                    x: x / len,
+                    y: y / len,
+                    z: z / len,
+                }
+            } else {
+                Normal { x: 0.0, y: 0.0, z: 1.0 }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::test_segment::{self, HEIGHT, WIDTH};
+    use crate::volume::{EmptyVolume, ObjVolume, VoxelPaintVolume};
+    use std::sync::Arc;
+
+    fn check(path: &std::path::Path) {
+        let obj = load_ply(path.to_str().unwrap(), &None, ProjectionKind::None).unwrap();
+        let volume = ObjVolume::new(Arc::new(obj), EmptyVolume {}.into_volume(), WIDTH, HEIGHT);
+        test_segment::assert_volume_coords(|uvw| volume.convert_to_volume_coords(uvw));
+    }
+
+    #[test]
+    fn ascii_and_binary_ply() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let ascii = dir.path().join("ascii.ply");
+        let mut content = "ply\nformat ascii 1.0\ncomment test\nelement vertex 15\nproperty float x\nproperty float y\nproperty float z\nproperty float s\nproperty float t\nelement face 8\nproperty list uchar int vertex_indices\nend_header\n".to_string();
+        for v in test_segment::vertices() {
+            content += &format!("{} {} {} {} {}\n", v[0], v[1], v[2], v[3], v[4]);
+        }
+        // quads are split into two triangles
+        for q in test_segment::quads() {
+            content += &format!("4 {} {} {} {}\n", q[0], q[1], q[2], q[3]);
+        }
+        std::fs::write(&ascii, content).unwrap();
+        check(&ascii);
+
+        let binary = dir.path().join("binary.ply");
+        let mut content = b"ply\nformat binary_little_endian 1.0\nelement vertex 15\nproperty float x\nproperty float y\nproperty float z\nproperty float nx\nproperty float ny\nproperty float nz\nproperty float u\nproperty float v\nelement face 16\nproperty list uchar uint vertex_indices\nend_header\n".to_vec();
+        for v in test_segment::vertices() {
+            for f in [v[0], v[1], v[2], 0.0, 0.0, 1.0, v[3], v[4]] {
+                content.extend_from_slice(&f.to_le_bytes());
+            }
+        }
+        for q in test_segment::quads() {
+            for face in [[q[0], q[1], q[2]], [q[0], q[2], q[3]]] {
+                content.push(3);
+                for i in face {
+                    content.extend_from_slice(&i.to_le_bytes());
+                }
+            }
+        }
+        std::fs::write(&binary, &content).unwrap();
+        check(&binary);
+
+        std::fs::write(&binary, &content[..content.len() - 4]).unwrap();
+        assert!(load_ply(binary.to_str().unwrap(), &None, ProjectionKind::None).is_err());
+    }
+}
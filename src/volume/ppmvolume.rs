@@ -1,71 +1,180 @@
 use memmap::MmapOptions;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, Seek, SeekFrom};
+use std::io::{BufRead, Seek};
+use std::sync::{Arc, OnceLock};
 
-use super::{AutoPaintVolume, Image, SurfaceVolume, Volume, VoxelVolume};
+use super::composition::LayerComposition;
+use super::intersection::triangle_intersection;
+use super::objvolume::{closest_point_on_triangle, line, point};
+use super::{
+    DrawingConfig, Image, PaintVolume, SurfaceVolume, Volume, VolumeCons, VolumeError, VolumeMetadata,
+    VoxelPaintVolume, VoxelVolume,
+};
 use libm::modf;
 
 pub struct PPMFile {
     pub width: usize,
     pub height: usize,
     map: memmap::Mmap,
+    /// built lazily when the plane intersection is painted for the first time
+    grid_index: OnceLock<GridIndex>,
 }
 impl PPMFile {
-    pub fn new(file_name: &str) -> Option<Self> {
-        let file = File::open(file_name).ok()?;
+    pub fn new(file_name: &str) -> Result<Self, VolumeError> {
+        let invalid = |reason: String| VolumeError::InvalidFormat(file_name.to_string(), reason);
+        let file = File::open(file_name).map_err(|e| VolumeError::Io(file_name.to_string(), e))?;
         let mut reader = std::io::BufReader::new(&file);
 
         let mut header_map = HashMap::new();
         let mut line = String::new();
 
-        let mut end_pos = 0;
-        while let Ok(len) = reader.read_line(&mut line) {
+        let end_pos = loop {
+            let len = reader
+                .read_line(&mut line)
+                .map_err(|e| VolumeError::Io(file_name.to_string(), e))?;
+            if len == 0 {
+                return Err(invalid("missing end of header".to_string()));
+            }
             if line.starts_with("<>\n") {
-                end_pos = reader.seek(SeekFrom::Current(0)).ok()?;
-                break;
+                break reader
+                    .stream_position()
+                    .map_err(|e| VolumeError::Io(file_name.to_string(), e))?;
             }
-            let mut split = line[..len].split(": ");
-            let key = split.next()?.trim().to_string();
-            let value = split.next()?.trim().to_string();
-            header_map.insert(key, value);
+            let Some((key, value)) = line[..len].split_once(": ") else {
+                return Err(invalid(format!("invalid header line '{}'", line.trim())));
+            };
+            header_map.insert(key.trim().to_string(), value.trim().to_string());
             line.clear();
-        }
-        let width = header_map.get("width")?.parse::<usize>().ok()?;
-        let height = header_map.get("height")?.parse::<usize>().ok()?;
+        };
+        let dimension = |name: &str| {
+            header_map
+                .get(name)
+                .and_then(|v| v.parse::<usize>().ok())
+                .ok_or_else(|| invalid(format!("missing or invalid {}", name)))
+        };
+        let width = dimension("width")?;
+        let height = dimension("height")?;
 
-        let map = unsafe { MmapOptions::new().offset(end_pos as u64).map(&file) }.ok();
+        let map = unsafe { MmapOptions::new().offset(end_pos).map(&file) }
+            .map_err(|e| VolumeError::Io(file_name.to_string(), e))?;
+        let expected = width * height * 6 * 8;
+        if map.len() < expected {
+            return Err(invalid(format!(
+                "expected {} bytes of data but found only {}",
+                expected,
+                map.len()
+            )));
+        }
 
-        map.map(|map| Self { width, height, map })
+        Ok(Self {
+            width,
+            height,
+            map,
+            grid_index: OnceLock::new(),
+        })
     }
     pub fn get(&self, u: usize, v: usize) -> [f64; 6] {
-        let map = unsafe { std::slice::from_raw_parts(self.map.as_ptr() as *const f64, self.map.len() / 8) };
-        let off = (v * self.width + u) * 6;
-        [
-            map[off + 0],
-            map[off + 1],
-            map[off + 2],
-            map[off + 3],
-            map[off + 4],
-            map[off + 5],
-        ]
+        // the data starts right after the text header, so it is not necessarily aligned for f64 access
+        let off = (v * self.width + u) * 6 * 8;
+        let Some(bytes) = self.map.get(off..off + 6 * 8) else {
+            return [0.0; 6];
+        };
+        std::array::from_fn(|i| f64::from_le_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap()))
+    }
+    /// Volume coordinates of a pixel, if the surface covers it
+    fn point(&self, u: usize, v: usize) -> Option<[f64; 3]> {
+        if u >= self.width || v >= self.height {
+            return None;
+        }
+        let [x, y, z, ..] = self.get(u, v);
+        (x != 0.0 || y != 0.0 || z != 0.0).then_some([x, y, z])
     }
+    fn grid_index(&self) -> &GridIndex {
+        self.grid_index.get_or_init(|| GridIndex::new(self))
+    }
+}
+
+/// Bounding boxes of blocks of a coarse grid over the PPM, used to quickly find the parts of the surface that
+/// intersect a plane. Only every `STEP`th pixel is considered, which is plenty for drawing outlines.
+struct GridIndex {
+    cols: usize,
+    rows: usize,
+    blocks_u: usize,
+    bounds: Vec<Option<([f64; 3], [f64; 3])>>,
 }
+impl GridIndex {
+    const STEP: usize = 4;
+    /// grid cells per block along each axis
+    const BLOCK: usize = 16;
 
+    fn new(ppm: &PPMFile) -> Self {
+        let cols = (ppm.width + Self::STEP - 1) / Self::STEP;
+        let rows = (ppm.height + Self::STEP - 1) / Self::STEP;
+        let blocks_u = (cols + Self::BLOCK - 1) / Self::BLOCK;
+        let blocks_v = (rows + Self::BLOCK - 1) / Self::BLOCK;
+        let mut bounds: Vec<Option<([f64; 3], [f64; 3])>> = vec![None; blocks_u * blocks_v];
+        for row in 0..rows {
+            for col in 0..cols {
+                let Some(p) = ppm.point(col * Self::STEP, row * Self::STEP) else {
+                    continue;
+                };
+                // cells at the start of a block also use the points of the previous row / column
+                for bv in (row.saturating_sub(1) / Self::BLOCK)..=(row / Self::BLOCK).min(blocks_v - 1) {
+                    for bu in (col.saturating_sub(1) / Self::BLOCK)..=(col / Self::BLOCK).min(blocks_u - 1) {
+                        let b = &mut bounds[bv * blocks_u + bu];
+                        let (min, max) = b.unwrap_or((p, p));
+                        *b = Some(([0, 1, 2].map(|i| min[i].min(p[i])), [0, 1, 2].map(|i| max[i].max(p[i]))));
+                    }
+                }
+            }
+        }
+        Self {
+            cols,
+            rows,
+            blocks_u,
+            bounds,
+        }
+    }
+    /// Grid cells (given by their upper left grid point) of all blocks overlapping the given box
+    fn cells_in_bounds(&self, min: [f64; 3], max: [f64; 3]) -> impl Iterator<Item = [usize; 2]> + '_ {
+        self.bounds
+            .iter()
+            .enumerate()
+            .filter(move |(_, b)| b.is_some_and(|(bmin, bmax)| (0..3).all(|i| bmin[i] <= max[i] && bmax[i] >= min[i])))
+            .flat_map(move |(i, _)| {
+                let (bu, bv) = (i % self.blocks_u, i / self.blocks_u);
+                let cols = bu * Self::BLOCK..((bu + 1) * Self::BLOCK).min(self.cols.saturating_sub(1));
+                let rows = bv * Self::BLOCK..((bv + 1) * Self::BLOCK).min(self.rows.saturating_sub(1));
+                rows.flat_map(move |row| cols.clone().map(move |col| [col, row]))
+            })
+    }
+}
+
+#[derive(Clone)]
 pub struct PPMVolume {
     volume: Volume,
-    ppm: PPMFile,
+    ppm: Arc<PPMFile>,
     /// should the volume use bilinear interpolation
     interpolate: bool,
 }
 impl PPMVolume {
     pub fn new(ppm_file: &str, base_volume: Volume) -> Self {
-        let ppm = PPMFile::new(ppm_file).unwrap();
-
-        Self {
+        Self::try_new(ppm_file, base_volume).unwrap()
+    }
+    pub fn try_new(ppm_file: &str, base_volume: Volume) -> Result<Self, VolumeError> {
+        Ok(Self {
             volume: base_volume,
-            ppm,
+            ppm: Arc::new(PPMFile::new(ppm_file)?),
             interpolate: false,
+        })
+    }
+    /// Shows another volume through the same PPM, e.g. for segment layers
+    pub fn with_base_volume(&self, base_volume: Volume) -> Self {
+        Self {
+            volume: base_volume,
+            ppm: self.ppm.clone(),
+            interpolate: self.interpolate,
         }
     }
     pub fn enable_bilinear_interpolation(&mut self) {
@@ -79,35 +188,69 @@ impl PPMVolume {
             (xyz[2] + coord[2] as f64 * xyz[5]) as i32,
         ]
     }
+    /// The reverse of `convert_to_world_coords`: finds the surface point closest to a volume position, within
+    /// `max_distance` voxels, and returns its segment coordinates with the offset along the normal as w. The surface
+    /// is approximated by the coarse grid of the index.
+    pub fn convert_to_uv_coords(&self, xyz: [f64; 3], max_distance: f64) -> Option<[i32; 3]> {
+        let min = xyz.map(|c| c - max_distance);
+        let max = xyz.map(|c| c + max_distance);
+        let step = GridIndex::STEP;
+
+        let mut best = None;
+        let mut best_dist2 = max_distance * max_distance;
+        for [col, row] in self.ppm.grid_index().cells_in_bounds(min, max) {
+            let (u, v) = (col * step, row * step);
+            let (Some(p00), Some(p10), Some(p01), Some(p11)) = (
+                self.ppm.point(u, v),
+                self.ppm.point(u + step, v),
+                self.ppm.point(u, v + step),
+                self.ppm.point(u + step, v + step),
+            ) else {
+                continue;
+            };
+            let (u0, v0, u1, v1) = (u as f64, v as f64, (u + step) as f64, (v + step) as f64);
+            for (tri, uvs) in [
+                ([p00, p10, p01], [[u0, v0], [u1, v0], [u0, v1]]),
+                ([p10, p11, p01], [[u1, v0], [u1, v1], [u0, v1]]),
+            ] {
+                let Some((p, bary)) = closest_point_on_triangle(xyz, tri) else {
+                    continue;
+                };
+                let dist2 = (0..3).map(|k| (xyz[k] - p[k]).powi(2)).sum::<f64>();
+                if dist2 <= best_dist2 {
+                    best_dist2 = dist2;
+                    best = Some([0, 1].map(|k| bary[0] * uvs[0][k] + bary[1] * uvs[1][k] + bary[2] * uvs[2][k]));
+                }
+            }
+        }
+
+        let [u, v] = best?.map(|c| c.round());
+        let [x, y, z, nx, ny, nz] = self.ppm.get(u as usize, v as usize);
+        let norm = (nx * nx + ny * ny + nz * nz).sqrt();
+        let w = if norm > 0.0 {
+            ((xyz[0] - x) * nx + (xyz[1] - y) * ny + (xyz[2] - z) * nz) / norm
+        } else {
+            0.0
+        };
+        Some([u as i32, v as i32, w.round() as i32])
+    }
     pub fn width(&self) -> usize {
         self.ppm.width
     }
     pub fn height(&self) -> usize {
         self.ppm.height
     }
-}
 
-impl VoxelVolume for PPMVolume {
-    fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
-        let uvw: [i32; 3] = [
-            xyz[0] as i32 * downsampling,
-            xyz[1] as i32 * downsampling,
-            xyz[2] as i32 * downsampling,
-        ];
-
-        if uvw[0] <= 0
-            || uvw[0] >= self.ppm.width as i32
-            || uvw[1] <= 0
-            || uvw[1] >= self.ppm.height as i32
-            || uvw[2].abs() > 45
-        {
-            return 0;
+    /// Position and normal at a (fractional) pixel position, if the surface covers it
+    fn sample(&self, u: f64, v: f64) -> Option<[f64; 6]> {
+        if u < 0.0 || u >= self.ppm.width as f64 || v < 0.0 || v >= self.ppm.height as f64 {
+            return None;
         }
 
         let [x0, y0, z0, nx, ny, nz] = if self.interpolate {
-            let (du, u0) = modf(xyz[0]);
+            let (du, u0) = modf(u);
             let u1 = u0 + 1.0;
-            let (dv, v0) = modf(xyz[1]);
+            let (dv, v0) = modf(v);
             let v1 = v0 + 1.0;
 
             let [x00, y00, z00, nx00, ny00, nz00] = self.ppm.get(u0 as usize, v0 as usize);
@@ -127,16 +270,39 @@ impl VoxelVolume for PPMVolume {
                 interpolate(nz00, nz10, nz01, nz11, du, dv),
             ]
         } else {
-            self.ppm.get(uvw[0] as usize, uvw[1] as usize)
+            self.ppm.get(u as usize, v as usize)
         };
 
         if x0 == 0.0 && y0 == 0.0 && z0 == 0.0 {
+            None
+        } else {
+            Some([x0, y0, z0, nx, ny, nz])
+        }
+    }
+}
+
+impl VoxelVolume for PPMVolume {
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        // the surface is sampled with one pixel per voxel of the base volume
+        let base = self.volume.metadata()?;
+        Some(VolumeMetadata {
+            shape: vec![[self.ppm.width, self.ppm.height, 1]],
+            ..base
+        })
+    }
+    fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        let w = xyz[2] as i32 * downsampling;
+        if w.abs() > 45 {
             return 0;
         }
+        let Some([x0, y0, z0, nx, ny, nz]) = self.sample(xyz[0] * downsampling as f64, xyz[1] * downsampling as f64)
+        else {
+            return 0;
+        };
 
-        let x = x0 + uvw[2] as f64 * nx;
-        let y = y0 + uvw[2] as f64 * ny;
-        let z = z0 + uvw[2] as f64 * nz;
+        let x = x0 + w as f64 * nx;
+        let y = y0 + w as f64 * ny;
+        let z = z0 + w as f64 * nz;
         self.volume.get(
             [
                 x / downsampling as f64,
@@ -146,22 +312,298 @@ impl VoxelVolume for PPMVolume {
             downsampling,
         )
     }
+    fn reset_for_painting(&self) {
+        self.volume.reset_for_painting();
+    }
 }
-impl AutoPaintVolume for PPMVolume {}
+
+impl PaintVolume for PPMVolume {
+    fn paint(
+        &self,
+        xyz: [i32; 3],
+        u_coord: usize,
+        v_coord: usize,
+        plane_coord: usize,
+        width: usize,
+        height: usize,
+        sfactor: u8,
+        paint_zoom: u8,
+        config: &DrawingConfig,
+        buffer: &mut Image,
+    ) {
+        assert!(u_coord == 0);
+        assert!(v_coord == 1);
+        assert!(plane_coord == 2);
+
+        let draw_outlines = config.draw_xyz_outlines;
+        let mut composition = LayerComposition::new(&config.compositing);
+
+        let real_xyz = if draw_outlines {
+            self.convert_to_world_coords(xyz)
+        } else {
+            [0, 0, 0]
+        };
+
+        let ffactor = sfactor as f64;
+        let sample_volume = |x: f64, y: f64, z: f64| {
+            if config.trilinear_interpolation {
+                self.volume
+                    .get_interpolated([x / ffactor, y / ffactor, z / ffactor], sfactor as i32)
+            } else {
+                self.volume.get([x / ffactor, y / ffactor, z / ffactor], sfactor as i32)
+            }
+        };
+
+        for im_v in 0..height {
+            for im_u in 0..width {
+                let u = xyz[0] + (im_u as i32 - width as i32 / 2) * paint_zoom as i32;
+                let v = xyz[1] + (im_v as i32 - height as i32 / 2) * paint_zoom as i32;
+                let Some([x, y, z, nx, ny, nz]) = self.sample(u as f64, v as f64) else {
+                    continue;
+                };
+
+                let value = composition.value(xyz[2], |w| sample_volume(x + w * nx, y + w * ny, z + w * nz));
+                buffer.set_gray(im_u, im_v, value);
+
+                if draw_outlines {
+                    if (x - real_xyz[0] as f64).abs() < 2.0 {
+                        buffer.set_rgb(im_u, im_v, 0, 0, 255);
+                    } else if (y - real_xyz[1] as f64).abs() < 2.0 {
+                        buffer.set_rgb(im_u, im_v, 255, 0, 0);
+                    } else if (z - real_xyz[2] as f64).abs() < 2.0 {
+                        buffer.set_rgb(im_u, im_v, 0, 255, 0);
+                    }
+                }
+            }
+        }
+    }
+    fn shared(&self) -> VolumeCons {
+        let volume = self.volume.shared();
+        let ppm = self.ppm.clone();
+        let interpolate = self.interpolate;
+        Box::new(move || {
+            PPMVolume {
+                volume: volume(),
+                ppm,
+                interpolate,
+            }
+            .into_volume()
+        })
+    }
+}
+
 impl SurfaceVolume for PPMVolume {
     fn paint_plane_intersection(
         &self,
-        _xyz: [i32; 3],
-        _u_coord: usize,
-        _v_coord: usize,
-        _plane_coord: usize,
-        _width: usize,
-        _height: usize,
+        xyz: [i32; 3],
+        u_coord: usize,
+        v_coord: usize,
+        plane_coord: usize,
+        width: usize,
+        height: usize,
         _sfactor: u8,
-        _paint_zoom: u8,
-        _highlight_uv_section: Option<[i32; 3]>,
-        _config: &super::DrawingConfig,
-        _buffer: &mut Image,
+        paint_zoom: u8,
+        highlight_uv_section: Option<[i32; 3]>,
+        config: &DrawingConfig,
+        image: &mut Image,
     ) {
+        let w = xyz[plane_coord] as f64;
+        let min_u = xyz[u_coord] - width as i32 / 2 * paint_zoom as i32;
+        let min_v = xyz[v_coord] - height as i32 / 2 * paint_zoom as i32;
+
+        let mut mins = [w; 3];
+        let mut maxs = [w; 3];
+        mins[u_coord] = min_u as f64;
+        maxs[u_coord] = (min_u + width as i32 * paint_zoom as i32) as f64;
+        mins[v_coord] = min_v as f64;
+        maxs[v_coord] = (min_v + height as i32 * paint_zoom as i32) as f64;
+
+        // section of the segment shown in the segment pane, in pixels
+        let highlight = highlight_uv_section.map(|h| {
+            let half_u = width as i32 / 2 * paint_zoom as i32;
+            let half_v = height as i32 / 2 * paint_zoom as i32;
+            ([h[0] - half_u, h[1] - half_v], [h[0] + half_u, h[1] + half_v])
+        });
+
+        let step = GridIndex::STEP;
+        let index = self.ppm.grid_index();
+        for [col, row] in index.cells_in_bounds(mins, maxs) {
+            let (u, v) = (col * step, row * step);
+            let (Some(p00), Some(p10), Some(p01), Some(p11)) = (
+                self.ppm.point(u, v),
+                self.ppm.point(u + step, v),
+                self.ppm.point(u, v + step),
+                self.ppm.point(u + step, v + step),
+            ) else {
+                continue;
+            };
+            let should_highlight = highlight.is_some_and(|(hmin, hmax)| {
+                (u + step) as i32 >= hmin[0]
+                    && u as i32 <= hmax[0]
+                    && (v + step) as i32 >= hmin[1]
+                    && v as i32 <= hmax[1]
+            });
+            let (r, g, b, rp, gp, bp) = if should_highlight {
+                (0xff, 0xaa, 0, 0, 0xff, 0)
+            } else {
+                let [r, g, b] = config.segment_outline_color;
+                (r, g, b, 0, 0, 0xff)
+            };
+
+            for tri in [[p00, p10, p01], [p10, p11, p01]] {
+                let Some([(_, p1), (_, p2)]) = triangle_intersection(tri, [0, 1, 2], plane_coord, w) else {
+                    continue;
+                };
+                // convert to image coordinates
+                let x0 = ((p1[u_coord] - min_u as f64) / paint_zoom as f64) as i32;
+                let y0 = ((p1[v_coord] - min_v as f64) / paint_zoom as f64) as i32;
+                let x1 = ((p2[u_coord] - min_u as f64) / paint_zoom as f64) as i32;
+                let y1 = ((p2[v_coord] - min_v as f64) / paint_zoom as f64) as i32;
+
+                line(x0, y0, x1, y1, image, width, height, r, g, b);
+                if config.draw_outline_vertices {
+                    point(x0, y0, image, 4, rp, gp, bp);
+                    point(x1, y1, image, 4, rp, gp, bp);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::test_segment::{self, HEIGHT, WIDTH};
+    use crate::volume::{
+        load_ply, CompositingMode, CompositingSettings, EmptyVolume, ObjVolume, ProjectionKind, SyntheticPattern,
+        SyntheticVolume,
+    };
+    use std::sync::Arc;
+
+    /// Writes a PPM of the test segment
+    fn write_test_ppm(dir: &std::path::Path) -> String {
+        let ply_file = dir.join("segment.ply");
+        test_segment::write_ply(&ply_file);
+        let obj = load_ply(ply_file.to_str().unwrap(), &None, ProjectionKind::None).unwrap();
+        let volume = ObjVolume::new(Arc::new(obj), EmptyVolume {}.into_volume(), WIDTH, HEIGHT);
+
+        let ppm_file = dir.join("segment.ppm").to_str().unwrap().to_string();
+        volume.write_ppm(&ppm_file).unwrap();
+        ppm_file
+    }
+
+    #[test]
+    fn write_and_read_ppm() {
+        let dir = tempfile::tempdir().unwrap();
+        let ppm_file = write_test_ppm(dir.path());
+        let ppm = PPMFile::new(&ppm_file).unwrap();
+        assert_eq!((ppm.width, ppm.height), (WIDTH, HEIGHT));
+        assert_eq!(
+            ppm.get(100, 20)[3..].iter().map(|c| c.round()).collect::<Vec<_>>(),
+            [0.0, 0.0, 1.0]
+        );
+        test_segment::assert_volume_coords(|[u, v, w]| {
+            let p = ppm.get(u as usize, v as usize);
+            [0, 1, 2].map(|i| (p[i] + w as f64 * p[i + 3]).round() as i32)
+        });
+        assert_eq!(ppm.get(WIDTH, HEIGHT), [0.0; 6]);
+    }
+
+    #[test]
+    fn invalid_ppm_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let open = |path: &std::path::Path| PPMVolume::try_new(path.to_str().unwrap(), EmptyVolume {}.into_volume());
+        assert!(matches!(
+            open(&dir.path().join("missing.ppm")),
+            Err(VolumeError::Io(_, _))
+        ));
+
+        let ppm_file = write_test_ppm(dir.path());
+        let data = std::fs::read(&ppm_file).unwrap();
+        let truncated = dir.path().join("truncated.ppm");
+        std::fs::write(&truncated, &data[..data.len() - 8]).unwrap();
+        assert!(
+            matches!(open(&truncated), Err(VolumeError::InvalidFormat(_, reason)) if reason.starts_with("expected"))
+        );
+
+        let no_data = dir.path().join("no_data.ppm");
+        std::fs::write(&no_data, "width: 200\nheight: 100\n").unwrap();
+        assert!(matches!(open(&no_data), Err(VolumeError::InvalidFormat(_, _))));
+        std::fs::write(&no_data, "width: 200\n<>\n").unwrap();
+        assert!(
+            matches!(open(&no_data), Err(VolumeError::InvalidFormat(_, reason)) if reason == "missing or invalid height")
+        );
+    }
+
+    #[test]
+    fn locate_on_ppm() {
+        let dir = tempfile::tempdir().unwrap();
+        let ppm = PPMVolume::new(&write_test_ppm(dir.path()), EmptyVolume {}.into_volume());
+        test_segment::assert_volume_coords(|uvw| ppm.convert_to_world_coords(uvw));
+        assert_eq!(ppm.convert_to_uv_coords([110.0, 100.0, 33.0], 5.0), Some([100, 50, 3]));
+        assert_eq!(ppm.convert_to_uv_coords([110.0, 100.0, 40.0], 5.0), None);
+    }
+
+    #[test]
+    fn composite_and_intersect_ppm() {
+        let dir = tempfile::tempdir().unwrap();
+        let ppm_file = write_test_ppm(dir.path());
+        let base = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 2 }, [256; 3]).into_volume();
+        let ppm = PPMVolume::new(&ppm_file, base);
+
+        let paint = |config: &DrawingConfig| {
+            let mut image = Image::new(20, 20);
+            ppm.paint([100, 50, 2], 0, 1, 2, 20, 20, 1, 1, config, &mut image);
+            image.data[10 * 20 + 10].r()
+        };
+        assert_eq!(paint(&DrawingConfig::default()), 32);
+        let config = DrawingConfig {
+            compositing: CompositingSettings {
+                mode: CompositingMode::Max,
+                layers_in_front: 5,
+                layers_behind: 5,
+                ..CompositingSettings::default()
+            },
+            ..DrawingConfig::default()
+        };
+        assert_eq!(paint(&config), 37);
+        assert_eq!(
+            ppm.with_base_volume(EmptyVolume {}.into_volume())
+                .get([100.0, 50.0, 0.0], 1),
+            0
+        );
+
+        // the rectangle crosses the plane x = 111 along y = 50..150 at z = 30
+        let mut image = Image::new(100, 100);
+        ppm.paint_plane_intersection(
+            [111, 100, 30],
+            1,
+            2,
+            0,
+            100,
+            100,
+            1,
+            1,
+            None,
+            &DrawingConfig::default(),
+            &mut image,
+        );
+        let at = |u: usize, v: usize| {
+            let c = image.data[v * 100 + u];
+            [c.r(), c.g(), c.b()]
+        };
+        assert_eq!(at(50, 50), [0xff, 0, 0xff]);
+        assert_eq!(at(10, 50), [0xff, 0, 0xff]);
+        assert_eq!(at(50, 40), [0, 0, 0]);
+
+        // outlines of other segments use their own color
+        let config = DrawingConfig {
+            segment_outline_color: [0, 0xff, 0xff],
+            ..DrawingConfig::default()
+        };
+        let mut image = Image::new(100, 100);
+        ppm.paint_plane_intersection([111, 100, 30], 1, 2, 0, 100, 100, 1, 1, None, &config, &mut image);
+        let c = image.data[50 * 100 + 50];
+        assert_eq!([c.r(), c.g(), c.b()], [0, 0xff, 0xff]);
     }
 }
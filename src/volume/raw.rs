@@ -0,0 +1,400 @@
+use super::{AutoPaintVolume, DataType, VolumeCons, VolumeError, VolumeMetadata, VoxelPaintVolume, VoxelVolume};
+use memmap::MmapOptions;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::ops::Deref;
+use std::sync::Arc;
+
+enum RawData {
+    Mapped(memmap::Mmap),
+    // decompressed data
+    Heap(Vec<u8>),
+}
+impl Deref for RawData {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            RawData::Mapped(data) => data,
+            RawData::Heap(data) => data,
+        }
+    }
+}
+
+/// Sidecar header for raw volumes, read from `<file>.json` or `<file stem>.json`.
+#[derive(Debug, Clone, Deserialize)]
+struct RawHeader {
+    // [x, y, z] with x varying fastest
+    shape: [usize; 3],
+    // `uint8` / `uint16` or numpy style (`|u1`, `<u2`, `>u2`)
+    dtype: String,
+    #[serde(default)]
+    big_endian: bool,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default)]
+    voxel_size_um: Option<f64>,
+    // position of voxel [0, 0, 0] as [x, y, z] in voxels
+    #[serde(default)]
+    origin: Option<[f64; 3]>,
+    // value range of the samples
+    #[serde(default)]
+    min: Option<f64>,
+    #[serde(default)]
+    max: Option<f64>,
+}
+
+/// A volume stored as a single block of uncompressed (or gzip compressed) 8 or 16-bit samples, as found in NRRD
+/// files or raw `.bin` files with a JSON sidecar header.
+#[derive(Clone)]
+pub struct RawVolume {
+    data: Arc<RawData>,
+    shape: [usize; 3],
+    bytes_per_sample: usize,
+    // offset of the most significant byte inside of a sample
+    msb: usize,
+    metadata: VolumeMetadata,
+}
+impl RawVolume {
+    pub fn from_raw(file_name: &str) -> Result<Self, VolumeError> {
+        let header_file = Self::header_file_for(file_name)
+            .ok_or_else(|| VolumeError::InvalidFormat(file_name.to_string(), "no .json header found".to_string()))?;
+        let header = std::fs::read_to_string(&header_file).map_err(|e| VolumeError::Io(header_file.clone(), e))?;
+        let header: RawHeader = serde_json::from_str(&header)
+            .map_err(|e| VolumeError::InvalidFormat(header_file.clone(), e.to_string()))?;
+
+        let dtype = match header.dtype.as_str() {
+            "uint8" => DataType::UInt8,
+            "uint16" => DataType::UInt16,
+            d => DataType::from_numpy_dtype(d),
+        };
+        let big_endian = header.big_endian || header.dtype.starts_with('>');
+        let file = File::open(file_name).map_err(|e| VolumeError::Io(file_name.to_string(), e))?;
+        let data = unsafe { MmapOptions::new().offset(header.offset as u64).map(&file) }
+            .map_err(|e| VolumeError::Io(file_name.to_string(), e))?;
+
+        let mut metadata = VolumeMetadata::new(header.shape, dtype);
+        metadata.voxel_size_um = header.voxel_size_um;
+        metadata.origin = header.origin.unwrap_or_default();
+        if let (Some(min), Some(max)) = (header.min, header.max) {
+            metadata = metadata.with_data_range(min, max);
+        }
+        Self::new(file_name, RawData::Mapped(data), metadata, big_endian)
+    }
+
+    /// Reads NRRD files with raw or gzip encoding, the data may be attached or in a detached data file.
+    pub fn from_nrrd(file_name: &str) -> Result<Self, VolumeError> {
+        let invalid = |reason: String| VolumeError::InvalidFormat(file_name.to_string(), reason);
+        let io_error = |e| VolumeError::Io(file_name.to_string(), e);
+
+        let mut reader = BufReader::new(File::open(file_name).map_err(io_error)?);
+        let mut fields = std::collections::HashMap::new();
+        let mut line = String::new();
+        let mut header_length = 0;
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line).map_err(io_error)?;
+            header_length += read;
+            let l = line.trim_end();
+            if read == 0 || l.is_empty() {
+                break;
+            } else if header_length == read {
+                if !l.starts_with("NRRD") {
+                    return Err(invalid("not a NRRD file".to_string()));
+                }
+            } else if !l.starts_with('#') {
+                if let Some((key, value)) = l.split_once(':') {
+                    // key-value pairs (`key:=value`) are ignored
+                    if !value.starts_with('=') {
+                        fields.insert(key.trim().to_lowercase(), value.trim().to_string());
+                    }
+                }
+            }
+        }
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .ok_or_else(|| invalid(format!("missing field '{}'", name)))
+        };
+
+        let dtype = match field("type")?.as_str() {
+            "uchar" | "unsigned char" | "uint8" | "uint8_t" => DataType::UInt8,
+            "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => DataType::UInt16,
+            t => return Err(invalid(format!("unsupported type '{}'", t))),
+        };
+        if field("dimension")? != "3" {
+            return Err(invalid(format!(
+                "expected 3 dimensions but got {}",
+                field("dimension")?
+            )));
+        }
+        let sizes = field("sizes")?
+            .split_whitespace()
+            .map(|s| s.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(format!("invalid sizes: {}", e)))?;
+        let shape: [usize; 3] = sizes.try_into().map_err(|_| invalid("expected 3 sizes".to_string()))?;
+        let big_endian = fields.get("endian").is_some_and(|e| e == "big");
+
+        let detached = match fields.get("data file").or(fields.get("datafile")) {
+            Some(data_file) => {
+                let dir = std::path::Path::new(file_name)
+                    .parent()
+                    .unwrap_or(std::path::Path::new("."));
+                Some(dir.join(data_file).to_string_lossy().to_string())
+            }
+            None => None,
+        };
+        let data = match field("encoding")?.as_str() {
+            "raw" => match &detached {
+                Some(data_file) => {
+                    let file = File::open(data_file).map_err(|e| VolumeError::Io(data_file.clone(), e))?;
+                    let mmap = unsafe { MmapOptions::new().map(&file) }.map_err(io_error)?;
+                    RawData::Mapped(mmap)
+                }
+                None => {
+                    let file = File::open(file_name).map_err(io_error)?;
+                    let mmap =
+                        unsafe { MmapOptions::new().offset(header_length as u64).map(&file) }.map_err(io_error)?;
+                    RawData::Mapped(mmap)
+                }
+            },
+            "gzip" | "gz" => {
+                let mut data = vec![];
+                match &detached {
+                    Some(data_file) => {
+                        let file = File::open(data_file).map_err(|e| VolumeError::Io(data_file.clone(), e))?;
+                        flate2::read::GzDecoder::new(file).read_to_end(&mut data)
+                    }
+                    None => flate2::read::GzDecoder::new(reader).read_to_end(&mut data),
+                }
+                .map_err(io_error)?;
+                RawData::Heap(data)
+            }
+            e => return Err(invalid(format!("unsupported encoding '{}'", e))),
+        };
+
+        let mut metadata = VolumeMetadata::new(shape, dtype);
+        metadata.voxel_size_um = Self::nrrd_voxel_size_um(&fields);
+        let field_value = |key: &str| fields.get(key).and_then(|v| v.trim().parse::<f64>().ok());
+        if let (Some(min), Some(max)) = (field_value("min"), field_value("max")) {
+            metadata = metadata.with_data_range(min, max);
+        }
+        Self::new(file_name, data, metadata, big_endian)
+    }
+
+    fn new(file_name: &str, data: RawData, metadata: VolumeMetadata, big_endian: bool) -> Result<Self, VolumeError> {
+        let bytes_per_sample = match metadata.dtype {
+            DataType::UInt8 => 1,
+            DataType::UInt16 => 2,
+            d => {
+                return Err(VolumeError::InvalidFormat(
+                    file_name.to_string(),
+                    format!("unsupported data type {}", d.label()),
+                ))
+            }
+        };
+        let shape = metadata.full_shape();
+        let expected = shape.iter().product::<usize>() * bytes_per_sample;
+        if data.len() < expected {
+            return Err(VolumeError::InvalidFormat(
+                file_name.to_string(),
+                format!("expected {} bytes of data but found only {}", expected, data.len()),
+            ));
+        }
+        println!("Loaded raw volume {} with shape {:?}", file_name, shape);
+
+        Ok(Self {
+            data: Arc::new(data),
+            shape,
+            bytes_per_sample,
+            msb: if big_endian { 0 } else { bytes_per_sample - 1 },
+            metadata,
+        })
+    }
+
+    fn header_file_for(file_name: &str) -> Option<String> {
+        let path = std::path::Path::new(file_name);
+        [
+            format!("{}.json", file_name),
+            path.with_extension("json").to_string_lossy().to_string(),
+        ]
+        .into_iter()
+        .find(|f| std::path::Path::new(f).exists())
+    }
+
+    /// Voxel size from `spacings` or `space directions`, if the units are known
+    fn nrrd_voxel_size_um(fields: &std::collections::HashMap<String, String>) -> Option<f64> {
+        let unit = fields.get("space units").or(fields.get("units"))?;
+        let unit = unit.split_whitespace().next()?.trim_matches('"');
+        let factor = match unit {
+            "mm" | "millimeter" | "millimeters" => 1000.0,
+            "um" | "µm" | "micron" | "microns" | "micrometer" | "micrometers" => 1.0,
+            "nm" | "nanometer" | "nanometers" => 0.001,
+            _ => return None,
+        };
+        let spacing = if let Some(spacings) = fields.get("spacings") {
+            spacings.split_whitespace().next()?.parse::<f64>().ok()?
+        } else {
+            // first vector of e.g. `(7.91,0,0) (0,7.91,0) (0,0,7.91)`
+            let directions = fields.get("space directions")?;
+            let first = directions.split(')').next()?.trim_start_matches([' ', '(']);
+            let v = first
+                .split(',')
+                .map(|c| c.trim().parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            v.iter().map(|c| c * c).sum::<f64>().sqrt()
+        };
+        Some(spacing * factor)
+    }
+}
+impl VoxelVolume for RawVolume {
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        Some(self.metadata.clone())
+    }
+    fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        let [x, y, z] = xyz.map(|v| v as i32 * downsampling);
+        if x < 0
+            || y < 0
+            || z < 0
+            || x as usize >= self.shape[0]
+            || y as usize >= self.shape[1]
+            || z as usize >= self.shape[2]
+        {
+            0
+        } else {
+            let idx = (z as usize * self.shape[1] + y as usize) * self.shape[0] + x as usize;
+            self.data[idx * self.bytes_per_sample + self.msb]
+        }
+    }
+}
+
+impl AutoPaintVolume for RawVolume {
+    fn shared_volume(&self) -> VolumeCons {
+        let volume = self.clone();
+        Box::new(move || volume.into_volume())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn attached_raw_nrrd() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_name = dir.path().join("test.nrrd");
+        let mut file = File::create(&file_name).unwrap();
+        write!(
+            file,
+            "NRRD0004\n# comment\ntype: uint16\ndimension: 3\nsizes: 4 3 2\nendian: little\nencoding: raw\nspace units: \"um\" \"um\" \"um\"\nspace directions: (7.91,0,0) (0,7.91,0) (0,0,7.91)\nmin: 0\nmax: 5888\n\n"
+        )
+        .unwrap();
+        let data = (0..24u16).flat_map(|i| (i << 8).to_le_bytes()).collect::<Vec<_>>();
+        file.write_all(&data).unwrap();
+        drop(file);
+
+        let volume = RawVolume::from_nrrd(file_name.to_str().unwrap()).unwrap();
+        let metadata = volume.metadata().unwrap();
+        assert_eq!(metadata.full_shape(), [4, 3, 2]);
+        assert_eq!(metadata.dtype, DataType::UInt16);
+        assert_eq!(metadata.voxel_size_um, Some(7.91));
+        assert_eq!(metadata.data_range, Some([0.0, 5888.0]));
+        assert_eq!(volume.get([1.0, 0.0, 0.0], 1), 1);
+        assert_eq!(volume.get([3.0, 2.0, 1.0], 1), 23);
+        assert_eq!(volume.get([4.0, 0.0, 0.0], 1), 0);
+    }
+
+    #[test]
+    fn raw_bin_with_json_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let value = |x: usize, y: usize, z: usize| (x + 10 * y + 100 * z) as u8;
+        let bin = dir.path().join("volume.bin");
+        // 3 bytes of file header before the data
+        let data = [0u8; 3]
+            .into_iter()
+            .chain((0..2).flat_map(|z| (0..3).flat_map(move |y| (0..5).map(move |x| value(x, y, z)))))
+            .collect::<Vec<_>>();
+        std::fs::write(&bin, data).unwrap();
+        std::fs::write(
+            dir.path().join("volume.json"),
+            r#"{"shape": [5, 3, 2], "dtype": "uint8", "offset": 3, "voxel_size_um": 2.5}"#,
+        )
+        .unwrap();
+
+        let volume = RawVolume::from_raw(bin.to_str().unwrap()).unwrap();
+        let metadata = volume.metadata().unwrap();
+        assert_eq!(metadata.full_shape(), [5, 3, 2]);
+        assert_eq!(metadata.dtype, DataType::UInt8);
+        assert_eq!(metadata.voxel_size_um, Some(2.5));
+        assert_eq!(volume.get([0.0, 0.0, 0.0], 1), 0);
+        assert_eq!(volume.get([2.0, 1.0, 1.0], 1), value(2, 1, 1));
+        assert_eq!(volume.get([4.0, 2.0, 1.0], 1), value(4, 2, 1));
+        assert_eq!(volume.get([5.0, 0.0, 0.0], 1), 0);
+        assert_eq!(volume.get([0.0, 3.0, 0.0], 1), 0);
+        assert_eq!(volume.get([0.0, 0.0, 2.0], 1), 0);
+        assert_eq!(volume.get([-1.0, 0.0, 0.0], 1), 0);
+        // downsampled coordinates
+        assert_eq!(volume.get([2.0, 1.0, 0.0], 2), value(4, 2, 0));
+    }
+
+    #[test]
+    fn raw_bin_big_endian_16_bit() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("volume16.bin");
+        let data = (0..8u16)
+            .flat_map(|i| ((i << 8) | 0xff).to_be_bytes())
+            .collect::<Vec<_>>();
+        std::fs::write(&bin, data).unwrap();
+        std::fs::write(
+            dir.path().join("volume16.bin.json"),
+            r#"{"shape": [2, 2, 2], "dtype": ">u2"}"#,
+        )
+        .unwrap();
+
+        let volume = RawVolume::from_raw(bin.to_str().unwrap()).unwrap();
+        assert_eq!(volume.metadata().unwrap().dtype, DataType::UInt16);
+        assert_eq!(volume.get([1.0, 0.0, 0.0], 1), 1);
+        assert_eq!(volume.get([1.0, 1.0, 1.0], 1), 7);
+    }
+
+    #[test]
+    fn raw_bin_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("short.bin");
+        std::fs::write(&bin, [0u8; 10]).unwrap();
+        let bin = bin.to_str().unwrap();
+
+        assert!(
+            matches!(RawVolume::from_raw(bin), Err(VolumeError::InvalidFormat(_, reason)) if reason.contains("header"))
+        );
+
+        // header declares more data than the file holds
+        std::fs::write(
+            dir.path().join("short.json"),
+            r#"{"shape": [4, 4, 4], "dtype": "uint8"}"#,
+        )
+        .unwrap();
+        let error = RawVolume::from_raw(bin).err().unwrap();
+        assert!(matches!(&error, VolumeError::InvalidFormat(file, _) if file == bin));
+        assert!(error
+            .to_string()
+            .contains("expected 64 bytes of data but found only 10"));
+
+        std::fs::write(
+            dir.path().join("short.json"),
+            r#"{"shape": [2, 2, 2], "dtype": "float32"}"#,
+        )
+        .unwrap();
+        assert!(
+            matches!(RawVolume::from_raw(bin), Err(VolumeError::InvalidFormat(_, reason)) if reason.contains("unsupported"))
+        );
+
+        std::fs::write(dir.path().join("short.json"), r#"{"dtype": "uint8"}"#).unwrap();
+        assert!(
+            matches!(RawVolume::from_raw(bin), Err(VolumeError::InvalidFormat(file, _)) if file.ends_with("short.json"))
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use super::{AutoPaintVolume, DataType, VolumeCons, VolumeError, VolumeMetadata, VoxelPaintVolume, VoxelVolume};
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntheticPattern {
+    /// A spiral of papyrus sheets rolled around the z axis
+    RolledSheets {
+        /// distance between neighbouring windings in voxels
+        spacing: f64,
+        /// thickness of a sheet in voxels
+        thickness: f64,
+        /// amount of per-voxel noise (0..1)
+        noise: f64,
+    },
+    /// Alternating dark and bright cubes of `size` voxels
+    Checkerboard { size: usize },
+    /// Value grows linearly with the coordinate along `axis` (0 = x, 1 = y, 2 = z)
+    Gradient { axis: usize },
+}
+
+/// A procedurally generated volume to test rendering and tools deterministically without any data.
+///
+/// Volumes are created from specs like `rolled-sheets`, `checkerboard,cell=32` or `gradient,axis=z`. All patterns
+/// accept `size=<n>` to set the edge length of the (cubic) volume.
+#[derive(Debug, Clone)]
+pub struct SyntheticVolume {
+    pattern: SyntheticPattern,
+    shape: [usize; 3],
+    seed: u64,
+}
+impl SyntheticVolume {
+    pub fn new(pattern: SyntheticPattern, shape: [usize; 3]) -> Self {
+        Self {
+            pattern,
+            shape,
+            seed: 0,
+        }
+    }
+    pub fn from_spec(spec: &str) -> Result<Self, VolumeError> {
+        let invalid = |reason: String| VolumeError::InvalidFormat(format!("synthetic:{}", spec), reason);
+
+        let mut parts = spec.split(',');
+        let name = parts.next().unwrap_or("").trim();
+        let mut params = std::collections::HashMap::new();
+        for part in parts {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("expected key=value but got '{}'", part)))?;
+            params.insert(key.trim(), value.trim());
+        }
+        let param = |key: &str, default: f64| -> Result<f64, VolumeError> {
+            params.get(key).map_or(Ok(default), |v| {
+                v.parse::<f64>()
+                    .map_err(|_| invalid(format!("invalid value '{}' for {}", v, key)))
+            })
+        };
+
+        let pattern = match name {
+            "rolled-sheets" => SyntheticPattern::RolledSheets {
+                spacing: param("spacing", 24.0)?,
+                thickness: param("thickness", 8.0)?,
+                noise: param("noise", 0.15)?,
+            },
+            "checkerboard" => SyntheticPattern::Checkerboard {
+                size: param("cell", 64.0)?.max(1.0) as usize,
+            },
+            "gradient" => SyntheticPattern::Gradient {
+                axis: match params.get("axis").copied().unwrap_or("x") {
+                    "x" => 0,
+                    "y" => 1,
+                    "z" => 2,
+                    a => return Err(invalid(format!("unknown axis '{}'", a))),
+                },
+            },
+            p => {
+                return Err(invalid(format!(
+                    "unknown pattern '{}', use one of rolled-sheets, checkerboard, gradient",
+                    p
+                )))
+            }
+        };
+        let size = param("size", 2048.0)?.max(1.0) as usize;
+        let mut volume = Self::new(pattern, [size; 3]);
+        volume.seed = param("seed", 0.0)? as u64;
+        Ok(volume)
+    }
+
+    /// Deterministic pseudo random value in [0, 1) for a voxel
+    fn noise_at(&self, x: i64, y: i64, z: i64) -> f64 {
+        // splitmix64 over the packed coordinates
+        let mut h = self.seed
+            ^ (x as u64).wrapping_mul(0x9e3779b97f4a7c15)
+            ^ (y as u64).wrapping_mul(0xc2b2ae3d27d4eb4f)
+            ^ (z as u64).wrapping_mul(0x165667b19e3779f9);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+        h ^= h >> 31;
+        (h >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn value_at(&self, x: i64, y: i64, z: i64) -> u8 {
+        match self.pattern {
+            SyntheticPattern::RolledSheets {
+                spacing,
+                thickness,
+                noise,
+            } => {
+                let cx = self.shape[0] as f64 / 2.0;
+                let cy = self.shape[1] as f64 / 2.0;
+                let (dx, dy) = (x as f64 + 0.5 - cx, y as f64 + 0.5 - cy);
+                let r = (dx * dx + dy * dy).sqrt();
+                if r > cx.min(cy) * 0.9 {
+                    return 0;
+                }
+                // distance to the closest winding of an archimedean spiral
+                let turns = dy.atan2(dx) / (2.0 * PI);
+                let phase = (r / spacing - turns).rem_euclid(1.0) * spacing;
+                let distance = phase.min(spacing - phase);
+                // soft edges of one voxel width
+                let sheet = (thickness / 2.0 + 0.5 - distance).clamp(0.0, 1.0);
+                let v = 40.0 + 180.0 * sheet + noise * 255.0 * (self.noise_at(x, y, z) - 0.5);
+                v.clamp(1.0, 255.0) as u8
+            }
+            SyntheticPattern::Checkerboard { size } => {
+                let size = size as i64;
+                if (x.div_euclid(size) + y.div_euclid(size) + z.div_euclid(size)) % 2 == 0 {
+                    64
+                } else {
+                    192
+                }
+            }
+            SyntheticPattern::Gradient { axis } => {
+                let c = [x, y, z][axis];
+                (c * 256 / self.shape[axis] as i64).clamp(0, 255) as u8
+            }
+        }
+    }
+}
+impl VoxelVolume for SyntheticVolume {
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        Some(VolumeMetadata::new(self.shape, DataType::UInt8))
+    }
+    fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        let [x, y, z] = xyz.map(|v| v as i64 * downsampling as i64);
+        if x < 0
+            || y < 0
+            || z < 0
+            || x as usize >= self.shape[0]
+            || y as usize >= self.shape[1]
+            || z as usize >= self.shape[2]
+        {
+            0
+        } else {
+            self.value_at(x, y, z)
+        }
+    }
+}
+
+impl AutoPaintVolume for SyntheticVolume {
+    fn shared_volume(&self) -> VolumeCons {
+        let volume = self.clone();
+        Box::new(move || volume.into_volume())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn synthetic_patterns() {
+        let sheets = SyntheticVolume::from_spec("rolled-sheets,size=256,noise=0").unwrap();
+        assert_eq!(sheets.metadata().unwrap().full_shape(), [256; 3]);
+        // along the positive x axis windings are at multiples of the spacing from the center
+        assert!(sheets.get([128.0 + 48.0, 128.0, 10.0], 1) > 200);
+        assert!(sheets.get([128.0 + 60.0, 128.0, 10.0], 1) < 50);
+        // outside of the scroll
+        assert_eq!(sheets.get([2.0, 2.0, 10.0], 1), 0);
+
+        let noisy = SyntheticVolume::from_spec("rolled-sheets,size=256,seed=3").unwrap();
+        assert_eq!(noisy.get([100.0, 90.0, 3.0], 1), noisy.get([100.0, 90.0, 3.0], 1));
+
+        let checkerboard = SyntheticVolume::from_spec("checkerboard,cell=4,size=64").unwrap();
+        assert_eq!(checkerboard.get([0.0, 0.0, 0.0], 1), 64);
+        assert_eq!(checkerboard.get([4.0, 0.0, 0.0], 1), 192);
+        assert_eq!(checkerboard.get([2.0, 0.0, 0.0], 2), 192);
+
+        let gradient = SyntheticVolume::from_spec("gradient,axis=y,size=512").unwrap();
+        assert_eq!(gradient.get([7.0, 256.0, 1.0], 1), 128);
+
+        assert!(SyntheticVolume::from_spec("spheres").is_err());
+        assert!(SyntheticVolume::from_spec("gradient,axis=w").is_err());
+    }
+}
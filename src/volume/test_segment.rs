@@ -0,0 +1,52 @@
+//! A flat test segment shared by the mesh, PPM, surface and flattening tests: a 200 x 100 voxel rectangle at z = 30,
+//! starting at x = 10, y = 50, as a grid of 4 x 2 quads.
+
+pub const WIDTH: usize = 200;
+pub const HEIGHT: usize = 100;
+
+/// x, y, z, u, v of the 5 x 3 grid vertices, row by row
+pub fn vertices() -> Vec<[f32; 5]> {
+    (0..3)
+        .flat_map(|r| {
+            (0..5).map(move |c| {
+                [
+                    10.0 + c as f32 * 50.0,
+                    50.0 + r as f32 * 50.0,
+                    30.0,
+                    c as f32 / 4.0,
+                    r as f32 / 2.0,
+                ]
+            })
+        })
+        .collect()
+}
+/// Vertex indices of the quads, counter-clockwise in uv
+pub fn quads() -> Vec<[u32; 4]> {
+    (0..2)
+        .flat_map(|r| (0..4).map(move |c| [r * 5 + c, r * 5 + c + 1, (r + 1) * 5 + c + 1, (r + 1) * 5 + c]))
+        .collect()
+}
+
+/// Writes the segment as an ASCII PLY file with texture coordinates
+pub fn write_ply(path: &std::path::Path) {
+    let mut ply = "ply\nformat ascii 1.0\nelement vertex 15\nproperty float x\nproperty float y\nproperty float z\nproperty float u\nproperty float v\nelement face 8\nproperty list uchar int vertex_indices\nend_header\n".to_string();
+    for [x, y, z, u, v] in vertices() {
+        ply += &format!("{} {} {} {} {}\n", x, y, z, u, v);
+    }
+    for [a, b, c, d] in quads() {
+        ply += &format!("4 {} {} {} {}\n", a, b, c, d);
+    }
+    std::fs::write(path, ply).unwrap();
+}
+
+/// Volume coordinates of the point at `u`, `v` and `w` along the normal of the segment
+pub fn volume_coords([u, v, w]: [i32; 3]) -> [i32; 3] {
+    [10 + u, 50 + v, 30 + w]
+}
+
+/// Checks that `convert` maps segment coordinates onto the rectangle
+pub fn assert_volume_coords(convert: impl Fn([i32; 3]) -> [i32; 3]) {
+    for uvw in [[0, 0, 0], [100, 20, 0], [100, 20, 2], [100, 50, 3]] {
+        assert_eq!(convert(uvw), volume_coords(uvw), "at {:?}", uvw);
+    }
+}
@@ -0,0 +1,599 @@
+use super::{CacheConfig, DataType, VolumeMetadata};
+use dashmap::DashMap;
+use memmap::MmapOptions;
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tiff::decoder::ifd::Value;
+use tiff::decoder::{ChunkType, Decoder, DecodingResult};
+use tiff::tags::{ResolutionUnit, Tag};
+use tiff::ColorType;
+
+static NEXT_FILE_ID: AtomicUsize = AtomicUsize::new(0);
+
+struct TiffChunkCacheEntry {
+    /// `None` if the chunk could not be decoded, until the failure is forgotten
+    data: Option<Arc<Vec<u8>>>,
+    last_access: AtomicU64,
+}
+impl TiffChunkCacheEntry {
+    fn len(&self) -> usize {
+        self.data.as_ref().map_or(0, |d| d.len())
+    }
+}
+
+/// Keeps decoded strips / tiles of compressed TIFF files, evicting the least recently used ones
+/// when more entries or bytes than configured are cached.
+pub(crate) struct TiffChunkCache {
+    // (file id, page, chunk) -> decoded 8-bit data
+    entries: DashMap<(usize, usize, u32), TiffChunkCacheEntry>,
+    access_counter: AtomicU64,
+    bytes: AtomicUsize,
+    max_entries: AtomicUsize,
+    max_bytes: AtomicUsize,
+}
+impl TiffChunkCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            entries: DashMap::new(),
+            access_counter: AtomicU64::new(0),
+            bytes: AtomicUsize::new(0),
+            max_entries: AtomicUsize::new(config.max_entries),
+            max_bytes: AtomicUsize::new(config.max_bytes),
+        }
+    }
+    /// Changes the limits of a cache already shared by the pages of a volume
+    pub fn set_config(&self, config: CacheConfig) {
+        self.max_entries.store(config.max_entries, Ordering::Relaxed);
+        self.max_bytes.store(config.max_bytes, Ordering::Relaxed);
+        while self.over_budget() && !self.entries.is_empty() {
+            self.purge();
+        }
+    }
+    fn over_budget(&self) -> bool {
+        self.bytes.load(Ordering::Relaxed) > self.max_bytes.load(Ordering::Relaxed)
+            || self.entries.len() > self.max_entries.load(Ordering::Relaxed)
+    }
+    fn get_or_load(&self, key: (usize, usize, u32), load: impl FnOnce() -> Option<Vec<u8>>) -> Option<Arc<Vec<u8>>> {
+        let counter = self.access_counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(e) = self.entries.get(&key) {
+            e.last_access.store(counter, Ordering::Relaxed);
+            return e.data.clone();
+        }
+
+        let entry = TiffChunkCacheEntry {
+            data: load().map(Arc::new),
+            last_access: AtomicU64::new(counter),
+        };
+        let data = entry.data.clone();
+        self.bytes.fetch_add(entry.len(), Ordering::Relaxed);
+        if let Some(old) = self.entries.insert(key, entry) {
+            // loaded concurrently by another thread
+            self.bytes.fetch_sub(old.len(), Ordering::Relaxed);
+        }
+        if self.over_budget() {
+            self.purge();
+        }
+        data
+    }
+    /// Drops chunks that could not be decoded, so that they are tried again
+    pub fn forget_failures(&self) {
+        self.entries.retain(|_, e| e.data.is_some());
+    }
+    fn purge(&self) {
+        // purge oldest 20% of entries
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|e| (*e.key(), e.last_access.load(Ordering::Relaxed)))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, last_access)| *last_access);
+        let n = entries.len() / 5 + 1;
+        for (k, _) in entries.into_iter().take(n) {
+            if let Some((_, e)) = self.entries.remove(&k) {
+                self.bytes.fetch_sub(e.len(), Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+enum TiffStorage {
+    // uncompressed pages stored in one contiguous strip each, with the same spacing between pages
+    Mapped {
+        data: memmap::Mmap,
+        page_spacing: usize,
+    },
+    // compressed and / or tiled pages, decoded chunk by chunk when first accessed
+    Chunked {
+        file_id: usize,
+        decoder: Box<Mutex<Decoder<File>>>,
+        chunk_width: usize,
+        chunk_height: usize,
+        cache: Arc<TiffChunkCache>,
+    },
+}
+
+/// Equally sized gray-scale pages of a TIFF file, e.g. a single slice of a layer stack or a stack of slices
+/// making up a cell. Only the most significant 8 bits of each sample are returned.
+pub(crate) struct TiffPages {
+    pub width: usize,
+    pub height: usize,
+    pub dtype: DataType,
+    /// from the resolution tags, only known if they declare a physical unit
+    pub voxel_size_um: Option<f64>,
+    /// from the MinSampleValue / MaxSampleValue tags
+    pub data_range: Option<[f64; 2]>,
+    bytes_per_sample: usize,
+    // offset of the most significant byte inside of a sample
+    msb: usize,
+    storage: TiffStorage,
+}
+impl TiffPages {
+    pub fn open(file_name: &str, cache: &Arc<TiffChunkCache>) -> Option<Self> {
+        let mut file = File::open(file_name).ok()?;
+        let mut byte_order = [0u8; 2];
+        file.read_exact(&mut byte_order).ok()?;
+        let big_endian = &byte_order == b"MM";
+        let mut decoder = Decoder::new(File::open(file_name).ok()?).ok()?;
+
+        let (width, height) = decoder.dimensions().ok()?;
+        let (width, height) = (width as usize, height as usize);
+        let bits = match decoder.colortype().ok()? {
+            ColorType::Gray(bits @ (8 | 16)) => bits,
+            c => {
+                println!("Unsupported color type {:?} in {}", c, file_name);
+                return None;
+            }
+        };
+        let bytes_per_sample = bits as usize / 8;
+        let msb = if big_endian { 0 } else { bytes_per_sample - 1 };
+        let dtype = DataType::from_bits_per_sample(bits as u32);
+        let compression = decoder.get_tag_u32(Tag::Compression).unwrap_or(1 /* None */);
+        let voxel_size_um = Self::read_voxel_size_um(&mut decoder);
+        let data_range = Self::read_data_range(&mut decoder);
+
+        let mapped_layout = if compression == 1 && decoder.get_chunk_type() == ChunkType::Strip {
+            Self::mapped_layout(&mut decoder, width * height * bytes_per_sample)
+        } else {
+            None
+        };
+
+        let storage = if let Some((first_offset, page_spacing)) = mapped_layout {
+            let file = File::open(file_name).ok()?;
+            let data = unsafe { MmapOptions::new().offset(first_offset as u64).map(&file) }.ok()?;
+            TiffStorage::Mapped { data, page_spacing }
+        } else {
+            // checking the layout may have moved the decoder to a later page
+            decoder.seek_to_image(0).ok()?;
+            let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+            TiffStorage::Chunked {
+                file_id: NEXT_FILE_ID.fetch_add(1, Ordering::Relaxed),
+                decoder: Box::new(Mutex::new(decoder)),
+                chunk_width: chunk_width as usize,
+                chunk_height: chunk_height as usize,
+                cache: cache.clone(),
+            }
+        };
+
+        Some(Self {
+            width,
+            height,
+            dtype,
+            voxel_size_um,
+            data_range,
+            bytes_per_sample,
+            msb,
+            storage,
+        })
+    }
+
+    /// Metadata of a volume made of these pages with the given shape
+    pub fn metadata(&self, shape: [usize; 3]) -> VolumeMetadata {
+        let mut metadata = VolumeMetadata::new(shape, self.dtype);
+        metadata.voxel_size_um = self.voxel_size_um;
+        metadata.data_range = self.data_range;
+        metadata
+    }
+
+    /// Data range over several files, only known if all of them record it
+    pub fn combined_data_range<'a>(pages: impl IntoIterator<Item = &'a TiffPages>) -> Option<[f64; 2]> {
+        pages.into_iter().try_fold(None, |range: Option<[f64; 2]>, p| {
+            let [min, max] = p.data_range?;
+            Some(Some(range.map_or([min, max], |[a, b]| [a.min(min), b.max(max)])))
+        })?
+    }
+
+    /// Pixel spacing from the XResolution and ResolutionUnit tags. ImageJ writes the unit into the image description
+    /// instead, e.g. `unit=micron`.
+    fn read_voxel_size_um(decoder: &mut Decoder<File>) -> Option<f64> {
+        let pixels_per_unit = match decoder.find_tag(Tag::XResolution).ok()?? {
+            Value::Rational(n, d) if n > 0 && d > 0 => n as f64 / d as f64,
+            _ => return None,
+        };
+        let unit = decoder
+            .find_tag_unsigned::<u16>(Tag::ResolutionUnit)
+            .ok()
+            .flatten()
+            .and_then(ResolutionUnit::from_u16);
+        let unit_um = match unit {
+            Some(ResolutionUnit::Inch) => 25400.0,
+            Some(ResolutionUnit::Centimeter) => 10000.0,
+            _ => {
+                let description = decoder.get_tag_ascii_string(Tag::ImageDescription).ok()?;
+                Self::imagej_unit_um(&description)?
+            }
+        };
+        Some(unit_um / pixels_per_unit)
+    }
+
+    /// Length of the `unit` of an ImageJ image description in µm
+    fn imagej_unit_um(description: &str) -> Option<f64> {
+        let unit = description.lines().find_map(|l| l.strip_prefix("unit="))?.trim();
+        match unit {
+            "micron" | "um" | "µm" | "\\u00B5m" => Some(1.0),
+            "nm" | "nanometer" => Some(0.001),
+            "mm" | "millimeter" => Some(1000.0),
+            _ => None,
+        }
+    }
+
+    fn read_data_range(decoder: &mut Decoder<File>) -> Option<[f64; 2]> {
+        let min = decoder.find_tag_unsigned::<u64>(Tag::MinSampleValue).ok()??;
+        let max = decoder.find_tag_unsigned::<u64>(Tag::MaxSampleValue).ok()??;
+        Some([min as f64, max as f64])
+    }
+
+    /// Counts the pages (image file directories) of a TIFF file
+    pub fn count_pages(file_name: &str) -> Option<usize> {
+        let mut decoder = Decoder::new(File::open(file_name).ok()?).ok()?;
+        let mut pages = 1;
+        while decoder.more_images() {
+            decoder.next_image().ok()?;
+            pages += 1;
+        }
+        Some(pages)
+    }
+
+    /// Returns the offset of the image data if all strips of the current page directly follow each other
+    fn contiguous_strips_offset(decoder: &mut Decoder<File>) -> Option<usize> {
+        let offsets = decoder.get_tag_u64_vec(Tag::StripOffsets).ok()?;
+        let byte_counts = decoder.get_tag_u64_vec(Tag::StripByteCounts).ok()?;
+        let contiguous = offsets
+            .windows(2)
+            .zip(byte_counts.iter())
+            .all(|(o, count)| o[0] + count == o[1]);
+        if contiguous {
+            offsets.first().map(|o| *o as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the offset of the first page and the spacing between pages if all pages are uncompressed, stored in
+    /// contiguous strips and equally spaced, so that the whole file can be memory mapped. Starts at the first page
+    /// and leaves the decoder at the last page checked.
+    fn mapped_layout(decoder: &mut Decoder<File>, page_bytes: usize) -> Option<(usize, usize)> {
+        let dimensions = decoder.dimensions().ok()?;
+        let mut offsets = vec![Self::contiguous_strips_offset(decoder)?];
+        while decoder.more_images() {
+            decoder.next_image().ok()?;
+            let compression = decoder.get_tag_u32(Tag::Compression).unwrap_or(1 /* None */);
+            if compression != 1
+                || decoder.get_chunk_type() != ChunkType::Strip
+                || decoder.dimensions().ok()? != dimensions
+            {
+                return None;
+            }
+            offsets.push(Self::contiguous_strips_offset(decoder)?);
+        }
+
+        let page_spacing = match offsets.as_slice() {
+            [first, second, ..] => second.checked_sub(*first).filter(|s| *s >= page_bytes)?,
+            _ => 0,
+        };
+        let equally_spaced = offsets.windows(2).all(|o| o[1].checked_sub(o[0]) == Some(page_spacing));
+        equally_spaced.then_some((offsets[0], page_spacing))
+    }
+
+    pub fn get(&self, x: usize, y: usize, page: usize) -> u8 {
+        if x >= self.width || y >= self.height {
+            return 0;
+        }
+        match &self.storage {
+            TiffStorage::Mapped { data, page_spacing } => {
+                let off = page_spacing * page + (y * self.width + x) * self.bytes_per_sample + self.msb;
+                if off >= data.len() {
+                    0
+                } else {
+                    data[off]
+                }
+            }
+            TiffStorage::Chunked {
+                file_id,
+                decoder,
+                chunk_width,
+                chunk_height,
+                cache,
+            } => {
+                let chunks_across = (self.width + chunk_width - 1) / chunk_width;
+                let (cx, cy) = (x / chunk_width, y / chunk_height);
+                let chunk = (cy * chunks_across + cx) as u32;
+                let data = cache.get_or_load((*file_id, page, chunk), || {
+                    let mut decoder = decoder.lock().unwrap();
+                    Self::decode_chunk(&mut decoder, page, chunk)
+                        .map_err(|e| println!("Failed to decode chunk {} of page {}: {}", chunk, page, e))
+                        .ok()
+                });
+                // edge chunks only contain the part inside of the image
+                let data_width = (*chunk_width).min(self.width - cx * chunk_width);
+                let off = (y % chunk_height) * data_width + x % chunk_width;
+                data.and_then(|d| d.get(off).copied()).unwrap_or(0)
+            }
+        }
+    }
+
+    fn decode_chunk(decoder: &mut Decoder<File>, page: usize, chunk: u32) -> tiff::TiffResult<Vec<u8>> {
+        decoder.seek_to_image(page)?;
+        Ok(match decoder.read_chunk(chunk)? {
+            DecodingResult::U8(data) => data,
+            DecodingResult::U16(data) => data.into_iter().map(|v| (v >> 8) as u8).collect(),
+            _ => vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tiff::encoder::{colortype, Compression, Rational, TiffEncoder};
+
+    #[test]
+    fn compressed_and_uncompressed_pages() {
+        let (width, height) = (70u32, 50u32);
+        let value = |x: u32, y: u32| ((x * 3 + y * 5) % 256) as u8;
+        let cache = Arc::new(TiffChunkCache::new(CacheConfig::default()));
+        let dir = tempfile::tempdir().unwrap();
+
+        let deflate_file = dir.path().join("deflate.tif");
+        let data16 = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (value(x, y) as u16) << 8 | 0x7f))
+            .collect::<Vec<_>>();
+        let mut encoder = TiffEncoder::new(File::create(&deflate_file).unwrap())
+            .unwrap()
+            .with_compression(Compression::Deflate(Default::default()));
+        let mut image = encoder.new_image::<colortype::Gray16>(width, height).unwrap();
+        image.rows_per_strip(16).unwrap();
+        image.write_data(&data16).unwrap();
+
+        let raw_file = dir.path().join("raw.tif");
+        let data8 = (0..height)
+            .flat_map(|y| (0..width).map(move |x| value(x, y)))
+            .collect::<Vec<_>>();
+        TiffEncoder::new(File::create(&raw_file).unwrap())
+            .unwrap()
+            .write_image::<colortype::Gray8>(width, height, &data8)
+            .unwrap();
+
+        let deflate = TiffPages::open(deflate_file.to_str().unwrap(), &cache).unwrap();
+        let raw = TiffPages::open(raw_file.to_str().unwrap(), &cache).unwrap();
+        assert!(matches!(deflate.storage, TiffStorage::Chunked { .. }));
+        assert!(matches!(raw.storage, TiffStorage::Mapped { .. }));
+        assert_eq!(deflate.dtype, DataType::UInt16);
+        assert_eq!(raw.dtype, DataType::UInt8);
+        for y in 0..height {
+            for x in 0..width {
+                assert_eq!(deflate.get(x as usize, y as usize, 0), value(x, y));
+                assert_eq!(raw.get(x as usize, y as usize, 0), value(x, y));
+            }
+        }
+        assert_eq!(raw.get(width as usize, 0, 0), 0);
+    }
+
+    #[test]
+    fn resolution_and_sample_range_tags() {
+        let (width, height) = (8u32, 4u32);
+        let data = vec![17u8; (width * height) as usize];
+        let cache = Arc::new(TiffChunkCache::new(CacheConfig::default()));
+        let dir = tempfile::tempdir().unwrap();
+
+        // 2500 pixels per cm -> 4µm
+        let tagged_file = dir.path().join("tagged.tif");
+        let mut encoder = TiffEncoder::new(File::create(&tagged_file).unwrap()).unwrap();
+        let mut image = encoder.new_image::<colortype::Gray8>(width, height).unwrap();
+        image.resolution(ResolutionUnit::Centimeter, Rational { n: 2500, d: 1 });
+        image.encoder().write_tag(Tag::MinSampleValue, 3u16).unwrap();
+        image.encoder().write_tag(Tag::MaxSampleValue, 200u16).unwrap();
+        image.write_data(&data).unwrap();
+
+        // ImageJ stores the unit in the description, 2 pixels per micron -> 0.5µm
+        let imagej_file = dir.path().join("imagej.tif");
+        let mut encoder = TiffEncoder::new(File::create(&imagej_file).unwrap()).unwrap();
+        let mut image = encoder.new_image::<colortype::Gray8>(width, height).unwrap();
+        image.resolution(ResolutionUnit::None, Rational { n: 2, d: 1 });
+        image
+            .encoder()
+            .write_tag(Tag::ImageDescription, "ImageJ=1.54f\nunit=micron\n")
+            .unwrap();
+        image.write_data(&data).unwrap();
+
+        // no physical unit at all
+        let plain_file = dir.path().join("plain.tif");
+        TiffEncoder::new(File::create(&plain_file).unwrap())
+            .unwrap()
+            .write_image::<colortype::Gray8>(width, height, &data)
+            .unwrap();
+
+        let tagged = TiffPages::open(tagged_file.to_str().unwrap(), &cache).unwrap();
+        let imagej = TiffPages::open(imagej_file.to_str().unwrap(), &cache).unwrap();
+        let plain = TiffPages::open(plain_file.to_str().unwrap(), &cache).unwrap();
+        assert_eq!(tagged.voxel_size_um, Some(4.0));
+        assert_eq!(tagged.data_range, Some([3.0, 200.0]));
+        assert_eq!(imagej.voxel_size_um, Some(0.5));
+        assert_eq!(imagej.data_range, None);
+        assert_eq!(plain.voxel_size_um, None);
+
+        let metadata = tagged.metadata([8, 4, 1]);
+        assert_eq!(metadata.voxel_size_um, Some(4.0));
+        assert_eq!(metadata.data_range, Some([3.0, 200.0]));
+        assert_eq!(TiffPages::combined_data_range([&tagged, &tagged]), Some([3.0, 200.0]));
+        assert_eq!(TiffPages::combined_data_range([&tagged, &plain]), None);
+    }
+
+    /// Writes an uncompressed 8-bit little-endian TIFF with the given tile size, the encoder only writes strips
+    fn write_tiled_tiff(file: &std::path::Path, width: u32, height: u32, tile: u32, value: impl Fn(u32, u32) -> u8) {
+        let (tiles_x, tiles_y) = (width.div_ceil(tile), height.div_ceil(tile));
+        let tiles = tiles_x * tiles_y;
+        let tags: [(u16, u16, u32); 10] = [
+            (256, 3, width),
+            (257, 3, height),
+            (258, 3, 8),
+            (259, 3, 1),
+            (262, 3, 1),
+            (277, 3, 1),
+            (322, 3, tile),
+            (323, 3, tile),
+            (324, 4, 0), // tile offsets, filled in below
+            (325, 4, 0), // tile byte counts
+        ];
+        let offsets_at = 8 + 2 + tags.len() as u32 * 12 + 4;
+        let counts_at = offsets_at + 4 * tiles;
+        let data_at = counts_at + 4 * tiles;
+
+        let mut out = b"II".to_vec();
+        out.extend(42u16.to_le_bytes());
+        out.extend(8u32.to_le_bytes());
+        out.extend((tags.len() as u16).to_le_bytes());
+        for (tag, typ, value) in tags {
+            let value = match tag {
+                324 => offsets_at,
+                325 => counts_at,
+                _ => value,
+            };
+            let count = if tag >= 324 { tiles } else { 1 };
+            out.extend(tag.to_le_bytes());
+            out.extend(typ.to_le_bytes());
+            out.extend(count.to_le_bytes());
+            out.extend(value.to_le_bytes());
+        }
+        out.extend(0u32.to_le_bytes());
+        for t in 0..tiles {
+            out.extend((data_at + t * tile * tile).to_le_bytes());
+        }
+        for _ in 0..tiles {
+            out.extend((tile * tile).to_le_bytes());
+        }
+        // tiles are always stored in full size, padded beyond the image
+        for ty in 0..tiles_y {
+            for tx in 0..tiles_x {
+                for y in ty * tile..(ty + 1) * tile {
+                    for x in tx * tile..(tx + 1) * tile {
+                        out.push(if x < width && y < height { value(x, y) } else { 0 });
+                    }
+                }
+            }
+        }
+        std::fs::write(file, out).unwrap();
+    }
+
+    #[test]
+    fn tiled_pages() {
+        let (width, height) = (40u32, 20u32);
+        let value = |x: u32, y: u32| (x * 7 + y * 3 + 1) as u8;
+        let cache = Arc::new(TiffChunkCache::new(CacheConfig::default()));
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("tiled.tif");
+        write_tiled_tiff(&file, width, height, 16, value);
+
+        let tiled = TiffPages::open(file.to_str().unwrap(), &cache).unwrap();
+        assert!(matches!(
+            tiled.storage,
+            TiffStorage::Chunked {
+                chunk_width: 16,
+                chunk_height: 16,
+                ..
+            }
+        ));
+        assert_eq!((tiled.width, tiled.height), (40, 20));
+        for y in 0..height {
+            for x in 0..width {
+                assert_eq!(tiled.get(x as usize, y as usize, 0), value(x, y), "at {},{}", x, y);
+            }
+        }
+        assert_eq!(tiled.get(40, 0, 0), 0);
+        assert_eq!(cache.entries.len(), 6);
+    }
+
+    #[test]
+    fn mapped_only_with_equally_spaced_pages() {
+        let (width, height) = (30u32, 10u32);
+        let value = |x: u32, y: u32, page: u32| (x + y * 2 + page * 50) as u8;
+        let cache = Arc::new(TiffChunkCache::new(CacheConfig::default()));
+        let dir = tempfile::tempdir().unwrap();
+        let write_pages = |name: &str, description_for: &dyn Fn(u32) -> Option<String>| {
+            let file = dir.path().join(name);
+            let mut encoder = TiffEncoder::new(File::create(&file).unwrap()).unwrap();
+            for page in 0..3 {
+                let data = (0..height)
+                    .flat_map(|y| (0..width).map(move |x| value(x, y, page)))
+                    .collect::<Vec<_>>();
+                let mut image = encoder.new_image::<colortype::Gray8>(width, height).unwrap();
+                if let Some(description) = description_for(page) {
+                    image
+                        .encoder()
+                        .write_tag(Tag::ImageDescription, description.as_str())
+                        .unwrap();
+                }
+                image.write_data(&data).unwrap();
+            }
+            TiffPages::open(file.to_str().unwrap(), &cache).unwrap()
+        };
+
+        let equal = write_pages("equal.tif", &|_| None);
+        // a longer directory after the second page moves the third page further away
+        let unequal = write_pages("unequal.tif", &|page| (page == 1).then(|| "x".repeat(100)));
+        assert!(matches!(equal.storage, TiffStorage::Mapped { .. }));
+        assert!(matches!(unequal.storage, TiffStorage::Chunked { .. }));
+        for page in 0..3 {
+            for (x, y) in [(0, 0), (29, 0), (0, 9), (29, 9), (13, 5)] {
+                assert_eq!(equal.get(x as usize, y as usize, page as usize), value(x, y, page));
+                assert_eq!(unequal.get(x as usize, y as usize, page as usize), value(x, y, page));
+            }
+        }
+    }
+
+    #[test]
+    fn chunk_cache_respects_config() {
+        let cache = TiffChunkCache::new(CacheConfig {
+            max_entries: 4,
+            max_bytes: 1 << 20,
+        });
+        for chunk in 0..10 {
+            cache.get_or_load((0, 0, chunk), || Some(vec![chunk as u8; 100]));
+            assert!(cache.entries.len() <= 4);
+        }
+        cache.set_config(CacheConfig {
+            max_entries: 100,
+            max_bytes: 250,
+        });
+        assert!(cache.bytes.load(Ordering::Relaxed) <= 250);
+        // the most recently used chunk is kept
+        assert!(cache.entries.contains_key(&(0, 0, 9)));
+
+        // failed chunks do not count and are retried once forgotten
+        let bytes = cache.bytes.load(Ordering::Relaxed);
+        assert_eq!(cache.get_or_load((0, 1, 0), || None), None);
+        assert_eq!(cache.get_or_load((0, 1, 0), || Some(vec![1])), None);
+        assert_eq!(cache.bytes.load(Ordering::Relaxed), bytes);
+        cache.forget_failures();
+        assert_eq!(cache.get_or_load((0, 1, 0), || Some(vec![1])), Some(Arc::new(vec![1])));
+        assert_eq!(cache.bytes.load(Ordering::Relaxed), bytes + 1);
+
+        // a chunk decoded concurrently by another thread only counts once
+        cache.get_or_load((0, 2, 0), || {
+            cache.get_or_load((0, 2, 0), || Some(vec![2; 10]));
+            Some(vec![2; 10])
+        });
+        assert_eq!(cache.bytes.load(Ordering::Relaxed), bytes + 11);
+    }
+}
@@ -0,0 +1,280 @@
+use super::{AffineTransform, ObjFile, ProjectionKind, VolumeError};
+use libm::sqrt;
+use std::fs::File;
+use std::path::Path;
+use tiff::decoder::{Decoder, DecodingResult};
+use wavefront_obj::obj::{Geometry, Normal, Object, Primitive, Shape, TVertex, Vertex};
+
+/// A surface stored in the "tifxyz" format as written by VC3D / villa: a directory with `x.tif`, `y.tif` and
+/// `z.tif` float32 grids of volume coordinates and a `meta.json` with the `scale` of the grid (grid points per
+/// voxel). Grid points with negative coordinates are invalid, i.e. not part of the surface.
+pub struct TifXyzSurface {
+    pub cols: usize,
+    pub rows: usize,
+    pub scale: [f64; 2],
+    points: Vec<[f32; 3]>,
+    /// Range of grid columns and rows (inclusive) that contain valid points
+    extent: Option<([usize; 2], [usize; 2])>,
+}
+impl TifXyzSurface {
+    /// Whether `path` is a directory containing a tifxyz surface
+    pub fn is_tifxyz(path: &str) -> bool {
+        let dir = Path::new(path);
+        dir.is_dir() && ["x.tif", "y.tif", "z.tif"].iter().all(|f| dir.join(f).is_file())
+    }
+
+    pub fn load(dir: &str) -> Result<Self, VolumeError> {
+        let meta_file = format!("{}/meta.json", dir);
+        let meta = std::fs::read_to_string(&meta_file).map_err(|e| VolumeError::Io(meta_file.clone(), e))?;
+        let meta: serde_json::Value =
+            serde_json::from_str(&meta).map_err(|e| VolumeError::InvalidFormat(meta_file.clone(), e.to_string()))?;
+        let scale = meta["scale"]
+            .as_array()
+            .and_then(|s| Some([s.first()?.as_f64()?, s.get(1)?.as_f64()?]))
+            .filter(|s| s[0] > 0.0 && s[1] > 0.0)
+            .ok_or_else(|| VolumeError::InvalidFormat(meta_file, "missing or invalid 'scale'".to_string()))?;
+
+        let (cols, rows, xs) = Self::read_grid(dir, "x")?;
+        let (_, _, ys) = Self::read_grid(dir, "y")?;
+        let (_, _, zs) = Self::read_grid(dir, "z")?;
+        if xs.len() != cols * rows || ys.len() != xs.len() || zs.len() != xs.len() {
+            return Err(VolumeError::InvalidFormat(
+                dir.to_string(),
+                "x.tif, y.tif and z.tif differ in size".to_string(),
+            ));
+        }
+        let points = (0..xs.len()).map(|i| [xs[i], ys[i], zs[i]]).collect();
+
+        let mut surface = Self {
+            cols,
+            rows,
+            scale,
+            points,
+            extent: None,
+        };
+        surface.extent = surface.valid_extent();
+        Ok(surface)
+    }
+    fn read_grid(dir: &str, coord: &str) -> Result<(usize, usize, Vec<f32>), VolumeError> {
+        let file_name = format!("{}/{}.tif", dir, coord);
+        let invalid = |e: tiff::TiffError| VolumeError::InvalidFormat(file_name.clone(), e.to_string());
+        let file = File::open(&file_name).map_err(|e| VolumeError::Io(file_name.clone(), e))?;
+        let mut decoder = Decoder::new(file).map_err(invalid)?;
+        let (width, height) = decoder.dimensions().map_err(invalid)?;
+        let data = match decoder.read_image().map_err(invalid)? {
+            DecodingResult::F32(data) => data,
+            DecodingResult::F64(data) => data.into_iter().map(|v| v as f32).collect(),
+            _ => {
+                return Err(VolumeError::InvalidFormat(
+                    file_name.clone(),
+                    "expected float32 samples".to_string(),
+                ))
+            }
+        };
+        Ok((width as usize, height as usize, data))
+    }
+
+    /// Volume coordinates of a grid point, if it is part of the surface
+    pub fn get(&self, col: usize, row: usize) -> Option<[f64; 3]> {
+        let p = self.points[row * self.cols + col];
+        (p[0] >= 0.0 && p[1] >= 0.0 && p[2] >= 0.0).then_some(p.map(|c| c as f64))
+    }
+
+    fn valid_extent(&self) -> Option<([usize; 2], [usize; 2])> {
+        let mut extent: Option<([usize; 2], [usize; 2])> = None;
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                if self.get(col, row).is_some() {
+                    let ([c0, c1], [r0, r1]) = extent.unwrap_or(([col, col], [row, row]));
+                    extent = Some(([c0.min(col), c1.max(col)], [r0.min(row), r1.max(row)]));
+                }
+            }
+        }
+        extent
+    }
+    /// Width of the flattened surface in voxels
+    pub fn width(&self) -> usize {
+        self.extent
+            .map_or(0, |([c0, c1], _)| ((c1 - c0) as f64 / self.scale[0]).round() as usize)
+    }
+    /// Height of the flattened surface in voxels
+    pub fn height(&self) -> usize {
+        self.extent
+            .map_or(0, |(_, [r0, r1])| ((r1 - r0) as f64 / self.scale[1]).round() as usize)
+    }
+
+    /// Triangulates the grid, so that the surface can be browsed and rendered like an obj file. Texture coordinates
+    /// follow the grid, normals are estimated from neighbouring grid points.
+    pub fn to_obj_file(
+        &self,
+        transform: &Option<AffineTransform>,
+        projection: ProjectionKind,
+    ) -> Result<ObjFile, VolumeError> {
+        let mut vertices = vec![];
+        let mut tex_vertices = vec![];
+        let mut normals = vec![];
+        let mut index = vec![None; self.points.len()];
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                let Some([x, y, z]) = self.get(col, row) else {
+                    continue;
+                };
+                index[row * self.cols + col] = Some(vertices.len());
+                vertices.push(Vertex { x, y, z });
+                tex_vertices.push(TVertex {
+                    u: col as f64,
+                    v: row as f64,
+                    w: 0.0,
+                });
+                let [x, y, z] = self.normal(col, row);
+                normals.push(Normal { x, y, z });
+            }
+        }
+
+        let mut shapes = vec![];
+        let vtn = |i: usize| (i, Some(i), Some(i));
+        let triangle = |a: usize, b: usize, c: usize| Shape {
+            primitive: Primitive::Triangle(vtn(a), vtn(b), vtn(c)),
+            groups: vec![],
+            smoothing_groups: vec![],
+        };
+        for row in 0..self.rows.saturating_sub(1) {
+            for col in 0..self.cols.saturating_sub(1) {
+                let at = |c: usize, r: usize| index[r * self.cols + c];
+                let (Some(p00), Some(p10), Some(p01), Some(p11)) =
+                    (at(col, row), at(col + 1, row), at(col, row + 1), at(col + 1, row + 1))
+                else {
+                    continue;
+                };
+                // counter-clockwise in uv, so that v is not flipped by ObjVolume
+                shapes.push(triangle(p00, p10, p01));
+                shapes.push(triangle(p10, p11, p01));
+            }
+        }
+        if shapes.is_empty() {
+            return Err(VolumeError::NoData("tifxyz surface".to_string()));
+        }
+
+        let object = Object {
+            name: "tifxyz".to_string(),
+            vertices,
+            tex_vertices,
+            normals,
+            geometry: vec![Geometry {
+                material_name: None,
+                shapes,
+            }],
+        };
+        Ok(ObjFile::new(object, transform, projection))
+    }
+
+    fn normal(&self, col: usize, row: usize) -> [f64; 3] {
+        // central differences where possible, falling back to one-sided differences at borders and holes
+        let diff = |a: Option<[f64; 3]>, b: Option<[f64; 3]>| {
+            let (a, b) = (a?, b?);
+            Some([0, 1, 2].map(|i| b[i] - a[i]))
+        };
+        let center = self.get(col, row);
+        let left = col.checked_sub(1).and_then(|c| self.get(c, row));
+        let right = (col + 1 < self.cols).then(|| self.get(col + 1, row)).flatten();
+        let up = row.checked_sub(1).and_then(|r| self.get(col, r));
+        let down = (row + 1 < self.rows).then(|| self.get(col, row + 1)).flatten();
+
+        let du = diff(left, right).or(diff(center, right)).or(diff(left, center));
+        let dv = diff(up, down).or(diff(center, down)).or(diff(up, center));
+        match (du, dv) {
+            (Some(u), Some(v)) => {
+                let n = [
+                    u[1] * v[2] - u[2] * v[1],
+                    u[2] * v[0] - u[0] * v[2],
+                    u[0] * v[1] - u[1] * v[0],
+                ];
+                let len = sqrt(n[0] * n[0] + n[1] * n[1] + n[2] * n[2]);
+                if len > 0.0 {
+                    n.map(|c| c / len)
+                } else {
+                    [0.0, 0.0, 1.0]
+                }
+            }
+            _ => [0.0, 0.0, 1.0],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::test_segment::{self, HEIGHT, WIDTH};
+    use crate::volume::{
+        DrawingConfig, EmptyVolume, Image, ObjVolume, PaintVolume, SyntheticPattern, SyntheticVolume, VoxelPaintVolume,
+    };
+    use std::sync::Arc;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    #[test]
+    fn load_tifxyz_surface() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cols, rows) = (11u32, 6u32);
+        // the test segment with one grid point per 20 voxels and a hole in the last row
+        let coord = |c: u32, r: u32, i: usize| {
+            if r == rows - 1 && c == 3 {
+                -1.0
+            } else {
+                [10.0 + c as f32 * 20.0, 50.0 + r as f32 * 20.0, 30.0][i]
+            }
+        };
+        for (i, name) in ["x", "y", "z"].iter().enumerate() {
+            let data = (0..rows)
+                .flat_map(|r| (0..cols).map(move |c| coord(c, r, i)))
+                .collect::<Vec<_>>();
+            TiffEncoder::new(File::create(dir.path().join(format!("{}.tif", name))).unwrap())
+                .unwrap()
+                .write_image::<colortype::Gray32Float>(cols, rows, &data)
+                .unwrap();
+        }
+        std::fs::write(
+            dir.path().join("meta.json"),
+            r#"{"type": "seg", "format": "tifxyz", "scale": [0.05, 0.05]}"#,
+        )
+        .unwrap();
+
+        let path = dir.path().to_str().unwrap();
+        assert!(TifXyzSurface::is_tifxyz(path));
+        let surface = TifXyzSurface::load(path).unwrap();
+        assert_eq!((surface.cols, surface.rows), (11, 6));
+        assert_eq!(surface.get(2, 1), Some([50.0, 70.0, 30.0]));
+        assert_eq!(surface.get(3, 5), None);
+        assert_eq!((surface.width(), surface.height()), (WIDTH, HEIGHT));
+
+        // sample the x and y coordinates through the surface
+        let obj = Arc::new(surface.to_obj_file(&None, ProjectionKind::None).unwrap());
+        let paint = |axis: usize, u: usize, v: usize| {
+            let base = SyntheticVolume::new(SyntheticPattern::Gradient { axis }, [256; 3]).into_volume();
+            let volume = ObjVolume::new(obj.clone(), base, surface.width(), surface.height());
+            let mut image = Image::new(WIDTH, HEIGHT);
+            volume.paint(
+                [100, 50, 0],
+                0,
+                1,
+                2,
+                WIDTH,
+                HEIGHT,
+                1,
+                1,
+                &DrawingConfig::default(),
+                &mut image,
+            );
+            image.data[v * WIDTH + u].r()
+        };
+        let [x, y, _] = test_segment::volume_coords([100, 20, 0]);
+        assert_eq!(paint(0, 100, 20), x as u8);
+        assert_eq!(paint(1, 100, 20), y as u8);
+        let volume = ObjVolume::new(
+            obj.clone(),
+            EmptyVolume {}.into_volume(),
+            surface.width(),
+            surface.height(),
+        );
+        test_segment::assert_volume_coords(|uvw| volume.convert_to_volume_coords(uvw));
+    }
+}
@@ -32,6 +32,16 @@ impl AffineTransform {
         }
     }
 
+    /// Apply this transformation to a point
+    pub fn apply(&self, xyz: [f64; 3]) -> [f64; 3] {
+        let m = &self.matrix;
+        [
+            m[0][0] * xyz[0] + m[0][1] * xyz[1] + m[0][2] * xyz[2] + m[0][3],
+            m[1][0] * xyz[0] + m[1][1] * xyz[1] + m[1][2] * xyz[2] + m[1][3],
+            m[2][0] * xyz[0] + m[2][1] * xyz[1] + m[2][2] * xyz[2] + m[2][3],
+        ]
+    }
+
     /// Invert this affine transformation matrix
     pub fn invert(&self) -> Result<Self, Error> {
         let m = &self.matrix;
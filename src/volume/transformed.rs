@@ -0,0 +1,195 @@
+use super::{
+    AffineTransform, CacheStats, DrawingConfig, Image, PaintVolume, Volume, VolumeError, VolumeMetadata,
+    VoxelPaintVolume, VoxelVolume,
+};
+
+/// Shows an underlying volume resampled through an affine transform, e.g. to browse a scan in the coordinate
+/// frame of another scan of the same scroll.
+///
+/// As for obj files, `transform` maps coordinates of the underlying volume into the coordinate frame the volume
+/// is browsed in.
+pub struct TransformedVolume {
+    volume: Volume,
+    transform: AffineTransform,
+    /// maps coordinates from the browsed frame back into the underlying volume
+    inverse: AffineTransform,
+}
+impl TransformedVolume {
+    pub fn new(volume: Volume, transform: AffineTransform) -> Result<Self, VolumeError> {
+        let inverse = transform
+            .invert()
+            .map_err(|e| VolumeError::InvalidTransform(e.to_string()))?;
+        Ok(Self {
+            volume,
+            transform,
+            inverse,
+        })
+    }
+    pub fn transform(&self) -> &AffineTransform {
+        &self.transform
+    }
+
+    fn source_coords(&self, xyz: [f64; 3], downsampling: i32) -> [f64; 3] {
+        // xyz is given in downsampled coordinates, but the transform is defined on full resolution coordinates
+        let ds = downsampling as f64;
+        let [x, y, z] = self.inverse.apply([xyz[0] * ds, xyz[1] * ds, xyz[2] * ds]);
+        [x / ds, y / ds, z / ds]
+    }
+}
+
+impl VoxelVolume for TransformedVolume {
+    fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        let source = self.source_coords(xyz, downsampling);
+        if source[0] < 0.0 || source[1] < 0.0 || source[2] < 0.0 {
+            return 0;
+        }
+        self.volume.get(source, downsampling)
+    }
+    fn try_get(&self, xyz: [f64; 3], downsampling: i32) -> Option<u8> {
+        let source = self.source_coords(xyz, downsampling);
+        if source[0] < 0.0 || source[1] < 0.0 || source[2] < 0.0 {
+            return Some(0);
+        }
+        self.volume.try_get(source, downsampling)
+    }
+    fn get_interpolated(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        let source = self.source_coords(xyz, downsampling);
+        if source[0] < 0.0 || source[1] < 0.0 || source[2] < 0.0 {
+            return 0;
+        }
+        self.volume.get_interpolated(source, downsampling)
+    }
+    fn reset_for_painting(&self) {
+        self.volume.reset_for_painting();
+    }
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.volume.cache_stats()
+    }
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        let base = self.volume.metadata()?;
+        // bounding box of the transformed base volume
+        let [sx, sy, sz] = base.full_shape().map(|s| s as f64);
+        let mut max = [0.0f64; 3];
+        for corner in [
+            [0.0, 0.0, 0.0],
+            [sx, 0.0, 0.0],
+            [0.0, sy, 0.0],
+            [0.0, 0.0, sz],
+            [sx, sy, 0.0],
+            [sx, 0.0, sz],
+            [0.0, sy, sz],
+            [sx, sy, sz],
+        ] {
+            let t = self.transform.apply(corner);
+            for i in 0..3 {
+                max[i] = max[i].max(t[i]);
+            }
+        }
+        let levels = base.shape.len();
+        Some(
+            VolumeMetadata {
+                shape: vec![max.map(|m| m.ceil() as usize)],
+                ..base
+            }
+            .with_pyramid(levels),
+        )
+    }
+}
+
+impl PaintVolume for TransformedVolume {
+    fn paint(
+        &self,
+        xyz: [i32; 3],
+        u_coord: usize,
+        v_coord: usize,
+        plane_coord: usize,
+        width: usize,
+        height: usize,
+        sfactor: u8,
+        paint_zoom: u8,
+        config: &DrawingConfig,
+        buffer: &mut Image,
+    ) {
+        let fi32 = sfactor as f64;
+
+        for im_v in 0..height {
+            for im_u in 0..width {
+                let im_rel_u = (im_u as i32 - width as i32 / 2) * paint_zoom as i32;
+                let im_rel_v = (im_v as i32 - height as i32 / 2) * paint_zoom as i32;
+
+                let mut uvw: [f64; 3] = [0.; 3];
+                uvw[u_coord] = (xyz[u_coord] + im_rel_u) as f64 / fi32;
+                uvw[v_coord] = (xyz[v_coord] + im_rel_v) as f64 / fi32;
+                uvw[plane_coord] = (xyz[plane_coord]) as f64 / fi32;
+
+                let v = if config.trilinear_interpolation {
+                    self.get_interpolated(uvw, sfactor as i32)
+                } else {
+                    self.get(uvw, sfactor as i32)
+                };
+                // keep what lower resolutions have painted if data is not available (yet)
+                if v != 0 {
+                    buffer.set_gray(im_u, im_v, v);
+                }
+            }
+        }
+    }
+
+    fn shared(&self) -> super::VolumeCons {
+        let volume = self.volume.shared();
+        let transform = self.transform.clone();
+        let inverse = self.inverse.clone();
+        Box::new(move || {
+            TransformedVolume {
+                volume: volume(),
+                transform,
+                inverse,
+            }
+            .into_volume()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::volume::{SyntheticPattern, SyntheticVolume};
+
+    fn translation(dx: f64, dy: f64, dz: f64) -> AffineTransform {
+        AffineTransform {
+            matrix: [[1.0, 0.0, 0.0, dx], [0.0, 1.0, 0.0, dy], [0.0, 0.0, 1.0, dz]],
+        }
+    }
+
+    #[test]
+    fn samples_through_the_inverse_transform() {
+        let base = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 0 }, [256; 3]).into_volume();
+        let volume = TransformedVolume::new(base, translation(20.0, 0.0, 0.0)).unwrap();
+        assert_eq!(volume.get([120.0, 5.0, 5.0], 1), 100);
+        // downsampled coordinates are scaled before applying the full resolution transform
+        assert_eq!(volume.get([30.0, 1.0, 1.0], 4), 100);
+        // everything in front of the translated volume is empty
+        assert_eq!(volume.get([10.0, 5.0, 5.0], 1), 0);
+        assert_eq!(volume.try_get([10.0, 5.0, 5.0], 1), Some(0));
+    }
+
+    #[test]
+    fn metadata_covers_the_transformed_volume() {
+        let base = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 0 }, [256, 128, 64]).into_volume();
+        let volume = TransformedVolume::new(base, translation(20.0, 10.0, 0.0)).unwrap();
+        let metadata = volume.metadata().unwrap();
+        assert_eq!(metadata.full_shape(), [276, 138, 64]);
+    }
+
+    #[test]
+    fn rejects_non_invertible_transforms() {
+        let base = SyntheticVolume::new(SyntheticPattern::Gradient { axis: 0 }, [16; 3]).into_volume();
+        let flat = AffineTransform {
+            matrix: [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 0.0, 0.0]],
+        };
+        assert!(matches!(
+            TransformedVolume::new(base, flat),
+            Err(VolumeError::InvalidTransform(_))
+        ));
+    }
+}
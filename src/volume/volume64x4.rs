@@ -1,11 +1,11 @@
-use super::{DrawingConfig, Image, VoxelVolume};
+use super::{CacheConfig, CacheStats, DrawingConfig, Image, VolumeError, VolumeMetadata, VoxelVolume};
 use crate::downloader::*;
 use crate::model::Quality;
 use crate::volume::{PaintVolume, VoxelPaintVolume};
 use dashmap::DashMap;
 use libm::modf;
 use std::cell::RefCell;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime};
 
@@ -17,14 +17,29 @@ pub(crate) enum TileState {
     TryLater(SystemTime),
 }
 
+impl TileState {
+    fn bytes(&self) -> usize {
+        match self {
+            TileState::Loaded(map) => map.len(),
+            _ => 0,
+        }
+    }
+}
+
 struct TileStateEntry {
     state: Arc<TileState>,
     last_access: AtomicU64,
 }
 
+/// Shared cache of tile states. When it grows beyond the configured number of entries or mapped bytes, the least
+/// recently used entries are dropped.
 struct TileCache {
     cache: DashMap<(usize, usize, usize, usize), Option<TileStateEntry>>,
     access_counter: AtomicU64,
+    config: CacheConfig,
+    bytes: AtomicUsize,
+    misses: AtomicU64,
+    evictions: AtomicU64,
 }
 
 struct LocalState {
@@ -32,10 +47,58 @@ struct LocalState {
     last_tile: Option<Arc<TileState>>,
 }
 impl TileCache {
-    fn new() -> Self {
+    fn new(config: CacheConfig) -> Self {
         Self {
             cache: DashMap::new(),
             access_counter: AtomicU64::new(0),
+            config,
+            bytes: AtomicUsize::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        let accesses = self.access_counter.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheStats {
+            entries: self.cache.len(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            hits: accesses.saturating_sub(misses),
+            misses,
+            evictions: self.evictions.load(Ordering::Relaxed),
+            config: Some(self.config),
+        }
+    }
+
+    fn over_limit(&self) -> bool {
+        self.cache.len() > self.config.max_entries || self.bytes.load(Ordering::Relaxed) > self.config.max_bytes
+    }
+
+    /// Drops least recently used entries until the cache is below 90% of its limits
+    fn evict(&self) {
+        let mut entries = self
+            .cache
+            .iter()
+            .map(|e| {
+                (
+                    *e.key(),
+                    e.value().as_ref().map_or(0, |e| e.last_access.load(Ordering::Relaxed)),
+                )
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, last_access)| *last_access);
+
+        let target_entries = self.config.max_entries - self.config.max_entries / 10;
+        let target_bytes = self.config.max_bytes - self.config.max_bytes / 10;
+        for (key, _) in entries {
+            if self.cache.len() <= target_entries && self.bytes.load(Ordering::Relaxed) <= target_bytes {
+                break;
+            }
+            if let Some((_, Some(entry))) = self.cache.remove(&key) {
+                self.bytes.fetch_sub(entry.state.bytes(), Ordering::Relaxed);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
         }
     }
 
@@ -50,9 +113,26 @@ impl TileCache {
         let key = (x, y, z, quality.downsampling_factor as usize);
         let counter = self.access_counter.fetch_add(1, Ordering::Relaxed);
 
+        let tile = self.load_tile_state(volume, key, counter, quality);
+        if self.over_limit() {
+            self.evict();
+        }
+        tile
+    }
+
+    fn load_tile_state(
+        &self,
+        volume: &VolumeGrid64x4Mapped,
+        key: (usize, usize, usize, usize),
+        counter: u64,
+        quality: Quality,
+    ) -> Option<Arc<TileState>> {
+        let (x, y, z, _) = key;
         let mut entry = self.cache.entry(key).or_insert_with(|| {
+            self.misses.fetch_add(1, Ordering::Relaxed);
             // Try to load from disk first
             if let Some(state) = VolumeGrid64x4Mapped::map_for(&volume.data_dir, x, y, z, quality) {
+                self.bytes.fetch_add(state.bytes(), Ordering::Relaxed);
                 Some(TileStateEntry {
                     state: Arc::new(state),
                     last_access: AtomicU64::new(counter),
@@ -124,6 +204,8 @@ impl TileCache {
             };
 
             if let Some(new_state) = new_state {
+                self.bytes.fetch_add(new_state.bytes(), Ordering::Relaxed);
+                self.bytes.fetch_sub(tile_entry.state.bytes(), Ordering::Relaxed);
                 tile_entry.state = new_state;
             }
 
@@ -139,6 +221,7 @@ pub struct VolumeGrid64x4Mapped {
     downloader: Arc<dyn Downloader>,
     tile_cache: Arc<TileCache>,
     local_state: RefCell<LocalState>,
+    metadata: Option<VolumeMetadata>,
 }
 impl VolumeGrid64x4Mapped {
     fn map_for(data_dir: &str, x: usize, y: usize, z: usize, quality: Quality) -> Option<TileState> {
@@ -168,20 +251,30 @@ impl VolumeGrid64x4Mapped {
         self.tile_cache.try_loading_tile(&self, x, y, z, quality)
     }
 
-    pub fn from_data_dir(data_dir: &str, downloader: Arc<dyn Downloader>) -> VolumeGrid64x4Mapped {
+    pub fn from_data_dir(data_dir: &str, downloader: Arc<dyn Downloader>) -> Result<VolumeGrid64x4Mapped, VolumeError> {
         if !std::path::Path::new(data_dir).exists() {
-            panic!("Data directory {} does not exist", data_dir);
+            return Err(VolumeError::NotFound(data_dir.to_string()));
         }
 
-        VolumeGrid64x4Mapped {
+        Ok(VolumeGrid64x4Mapped {
             data_dir: data_dir.to_string(),
             downloader,
-            tile_cache: Arc::new(TileCache::new()),
+            tile_cache: Arc::new(TileCache::new(CacheConfig::default())),
             local_state: RefCell::new(LocalState {
                 last_tile_key: (0, 0, 0, 0),
                 last_tile: None,
             }),
-        }
+            metadata: None,
+        })
+    }
+    pub fn with_cache_config(mut self, config: CacheConfig) -> Self {
+        self.tile_cache = Arc::new(TileCache::new(config));
+        self
+    }
+    /// The 64-4 format does not carry any metadata, so it needs to be provided from the outside
+    pub fn with_metadata(mut self, metadata: Option<VolumeMetadata>) -> Self {
+        self.metadata = metadata;
+        self
     }
     fn tile_at(&self, x: usize, y: usize, z: usize, downsampling: usize) -> Option<Arc<TileState>> {
         let tile_x = x / 64;
@@ -225,7 +318,16 @@ impl VolumeGrid64x4Mapped {
 }
 
 impl VoxelVolume for VolumeGrid64x4Mapped {
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        self.metadata.clone()
+    }
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.tile_cache.stats())
+    }
     fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
+        self.try_get(xyz, downsampling).unwrap_or(0)
+    }
+    fn try_get(&self, xyz: [f64; 3], downsampling: i32) -> Option<u8> {
         let x = xyz[0] as usize;
         let y = xyz[1] as usize;
         let z = xyz[2] as usize;
@@ -248,7 +350,7 @@ impl VoxelVolume for VolumeGrid64x4Mapped {
 
                 let index = off_x + off_y * 4 + off_z * 16 + block * 64;
 
-                tile[index]
+                Some(tile[index])
             } else if let TileState::Downloading(_state) = r.as_ref() {
                 /* match *_state.lock().unwrap() {
                     DownloadState::Downloading => 255,
@@ -256,12 +358,14 @@ impl VoxelVolume for VolumeGrid64x4Mapped {
                     DownloadState::Delayed => 100,
                     _ => 0,
                 } */
-                0
+                None
+            } else if let TileState::Missing = r.as_ref() {
+                Some(0)
             } else {
-                0
+                None
             }
         } else {
-            0
+            None
         }
     }
 
@@ -394,7 +498,7 @@ impl PaintVolume for VolumeGrid64x4Mapped {
         canvas_height: usize,
         _sfactor: u8,
         paint_zoom: u8,
-        config: &DrawingConfig,
+        _config: &DrawingConfig,
         buffer: &mut Image,
     ) {
         // drop last_tile, which we do not use for area painting and may get in the way of accessing tilestate otherwise
@@ -403,9 +507,6 @@ impl PaintVolume for VolumeGrid64x4Mapped {
         let width = paint_zoom as usize * canvas_width;
         let height = paint_zoom as usize * canvas_height;
 
-        let mask = config.bit_mask();
-        let filters_active = config.filters_active();
-
         let sfactor = _sfactor as i32;
         let tilesize = 64 * sfactor as i32;
         let blocksize = 4 * sfactor as i32;
@@ -496,18 +597,6 @@ impl PaintVolume for VolumeGrid64x4Mapped {
                                                 panic!("off: {} tile.len(): {}", off, tile.len());
                                             }
                                             let value = tile[off as usize];
-
-                                            if filters_active {
-                                                let pluscon = ((value as i32 - config.threshold_min as i32).max(0)
-                                                    * 255
-                                                    / (255 - (config.threshold_min + config.threshold_max) as i32))
-                                                    .min(255)
-                                                    as u8;
-
-                                                (((pluscon & mask) as f32) / (mask as f32) * 255.0) as u8
-                                            } else {
-                                                value
-                                            };
                                             buffer.set_gray(u as usize, v as usize, value);
                                         }
                                     }
@@ -523,6 +612,7 @@ impl PaintVolume for VolumeGrid64x4Mapped {
         let data_dir = self.data_dir.clone();
         let downloader = self.downloader.clone();
         let tile_cache = self.tile_cache.clone();
+        let metadata = self.metadata.clone();
         Box::new(move || {
             VolumeGrid64x4Mapped {
                 data_dir,
@@ -532,8 +622,68 @@ impl PaintVolume for VolumeGrid64x4Mapped {
                     last_tile_key: (0, 0, 0, 0),
                     last_tile: None,
                 }),
+                metadata,
             }
             .into_volume()
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct NoDownloader;
+    impl Downloader for NoDownloader {
+        fn queue(&self, _task: DownloadTask) {}
+    }
+
+    #[test]
+    fn eviction_keeps_cache_within_budget() {
+        const TILE_BYTES: usize = 64 * 64 * 64;
+        let dir = tempfile::tempdir().unwrap();
+        let tile_dir = dir.path().join("64-4/d01/z000");
+        std::fs::create_dir_all(&tile_dir).unwrap();
+        for x in 0..8 {
+            let file = tile_dir.join(format!("xyz-{:03}-000-000-b255-d01.bin", x));
+            std::fs::write(file, vec![x as u8 + 1; TILE_BYTES]).unwrap();
+        }
+
+        let config = CacheConfig {
+            max_entries: 5,
+            max_bytes: 3 * TILE_BYTES,
+        };
+        let volume = VolumeGrid64x4Mapped::from_data_dir(dir.path().to_str().unwrap(), Arc::new(NoDownloader))
+            .unwrap()
+            .with_cache_config(config);
+        for round in 0..2 {
+            for x in 0..8 {
+                assert_eq!(volume.get([x as f64 * 64.0 + 5.0, 5.0, 5.0], 1), x as u8 + 1);
+                let stats = volume.cache_stats().unwrap();
+                assert!(
+                    stats.entries <= config.max_entries,
+                    "round {} tile {}: {:?}",
+                    round,
+                    x,
+                    stats
+                );
+                assert!(
+                    stats.bytes <= config.max_bytes,
+                    "round {} tile {}: {:?}",
+                    round,
+                    x,
+                    stats
+                );
+            }
+        }
+        // tiles that are not on disk only count as entries
+        for y in 1..8 {
+            volume.get([5.0, y as f64 * 64.0, 5.0], 1);
+            assert!(volume.cache_stats().unwrap().entries <= config.max_entries);
+        }
+
+        let stats = volume.cache_stats().unwrap();
+        assert!(stats.evictions > 0);
+        assert_eq!(stats.misses, 16 + 7);
+    }
+}
@@ -1,3 +1,4 @@
+use super::ZarrError;
 use crate::zstd_decompress;
 use derive_more::Debug;
 use memmap::MmapOptions;
@@ -33,13 +34,19 @@ pub struct BloscHeader {
     pub compressor: BloscCompressor,
 }
 impl BloscHeader {
-    fn from_bytes(bytes: &[u8]) -> Self {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ZarrError> {
+        if bytes.len() < 16 {
+            return Err(ZarrError::InvalidChunk(format!(
+                "Blosc header too short: {} bytes",
+                bytes.len()
+            )));
+        }
         let flags = bytes[2];
         let shuffle = match flags & 0x7 {
             0 | 1 => BloscShuffle::None,
             2 => BloscShuffle::Byte,
             4 => BloscShuffle::Bit,
-            x => panic!("Invalid shuffle value {x}"),
+            x => return Err(ZarrError::InvalidChunk(format!("Invalid shuffle value {x}"))),
         };
         let compressor = match flags >> 5 {
             0 => BloscCompressor::Blosclz,
@@ -47,10 +54,10 @@ impl BloscHeader {
             2 => BloscCompressor::Snappy,
             3 => BloscCompressor::Zlib,
             4 => BloscCompressor::Zstd,
-            x => panic!("Invalid compressor value {x}"),
+            x => return Err(ZarrError::InvalidChunk(format!("Invalid compressor value {x}"))),
         };
 
-        BloscHeader {
+        Ok(BloscHeader {
             version: bytes[0],
             version_lz: bytes[1],
             flags,
@@ -60,7 +67,7 @@ impl BloscHeader {
             cbytes: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]) as usize,
             shuffle,
             compressor,
-        }
+        })
     }
 
     fn num_blocks(&self) -> usize {
@@ -84,14 +91,20 @@ pub struct BloscChunk<T> {
 }
 
 impl BloscChunk<u8> {
-    pub fn load_from_file(file: &File) -> Self {
-        let chunk = unsafe { MmapOptions::new().map(file) }.unwrap();
+    pub fn load_from_file(file: &File) -> Result<Self, ZarrError> {
+        let chunk = unsafe { MmapOptions::new().map(file) }.map_err(|e| ZarrError::Io("blosc chunk".to_string(), e))?;
 
         // parse 16 byte blosc header
-        let header = BloscHeader::from_bytes(&chunk[0..16]);
+        let header = BloscHeader::from_bytes(&chunk)?;
         let num_blocks = header.num_blocks();
+        if chunk.len() < 16 + num_blocks * 4 {
+            return Err(ZarrError::InvalidChunk(format!(
+                "Blosc chunk too short for {} block offsets",
+                num_blocks
+            )));
+        }
         let mut offsets = vec![];
-        for i in 0..num_blocks as usize {
+        for i in 0..num_blocks {
             offsets.push(u32::from_le_bytes([
                 chunk[16 + i * 4],
                 chunk[16 + i * 4 + 1],
@@ -100,65 +113,73 @@ impl BloscChunk<u8> {
             ]));
         }
 
-        BloscChunk {
+        Ok(BloscChunk {
             header,
             offsets,
             data: chunk,
             file_name: "from_file".to_string(),
             phantom_t: std::marker::PhantomData,
-        }
+        })
     }
-    pub fn load(filename: &str) -> Self {
-        let file = File::open(filename).unwrap();
-        Self::load_from_file(&file)
+    pub fn load(filename: &str) -> Result<Self, ZarrError> {
+        let file = File::open(filename).map_err(|e| ZarrError::Io(filename.to_string(), e))?;
+        let mut chunk = Self::load_from_file(&file)?;
+        chunk.file_name = filename.to_string();
+        Ok(chunk)
     }
 
-    pub fn load_data_from_file(file: &File) -> Vec<u8> {
-        let chunk = Self::load_from_file(file);
+    pub fn load_data_from_file(file: &File) -> Result<Vec<u8>, ZarrError> {
+        let chunk = Self::load_from_file(file)?;
         let mut data = vec![];
         for i in 0..chunk.header.num_blocks() {
-            let block = chunk.load_block(i);
+            let block = chunk.load_block(i)?;
             data.extend(block);
         }
-        data
+        Ok(data)
     }
-    pub fn load_data(filename: &str) -> Vec<u8> {
-        let file = File::open(filename).unwrap();
+    pub fn load_data(filename: &str) -> Result<Vec<u8>, ZarrError> {
+        let file = File::open(filename).map_err(|e| ZarrError::Io(filename.to_string(), e))?;
         Self::load_data_from_file(&file)
     }
-    fn load_block(&self, block_idx: usize) -> Vec<u8> {
+    fn load_block(&self, block_idx: usize) -> Result<Vec<u8>, ZarrError> {
         self.decompress(block_idx)
         // FIXME: add deshuffling
     }
-    fn decompress(&self, block_idx: usize) -> Vec<u8> {
-        /* if block_idx >= self.num_blocks {
-            panic!("Block index out of bounds for block {}", &self.file_name);
-        } */
+    fn decompress(&self, block_idx: usize) -> Result<Vec<u8>, ZarrError> {
         let block_offset = self.offsets[block_idx] as usize;
         if block_offset + 4 >= self.data.len() {
-            panic!("Block offset out of bounds for block {}", &self.file_name);
+            return Err(ZarrError::InvalidChunk(format!(
+                "Block offset out of bounds for block {} in {}",
+                block_idx, self.file_name
+            )));
         }
         let block_compressed_length =
             u32::from_le_bytes(self.data[block_offset..block_offset + 4].try_into().unwrap()) as usize;
+        if block_offset + 4 + block_compressed_length > self.data.len() {
+            return Err(ZarrError::InvalidChunk(format!(
+                "Block length out of bounds for block {} in {}",
+                block_idx, self.file_name
+            )));
+        }
         let block_compressed_data = &self.data[block_offset + 4..block_offset + block_compressed_length + 4];
 
         match self.header.compressor {
             BloscCompressor::Lz4 => match lz4_compression::decompress::decompress(&block_compressed_data) {
-                Ok(decompressed) => decompressed,
+                Ok(decompressed) => Ok(decompressed),
                 Err(e) => {
                     println!(
                         "Failed to decompress block {} in file {}: {:?}",
                         block_idx, self.file_name, e
                     );
-                    vec![0; self.header.blocksize]
+                    Ok(vec![0; self.header.blocksize])
                 }
             },
 
-            BloscCompressor::Zstd => zstd_decompress(block_compressed_data),
-            _ => panic!(
-                "Unsupported compressor: {:?} in file {:?}",
+            BloscCompressor::Zstd => Ok(zstd_decompress(block_compressed_data)),
+            _ => Err(ZarrError::UnsupportedCompressor(format!(
+                "{:?} in file {}",
                 self.header.compressor, self.file_name
-            ),
+            ))),
         }
     }
 }
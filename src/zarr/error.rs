@@ -0,0 +1,37 @@
+use std::fmt;
+
+/// Errors that can occur when opening a zarr array or reading its chunks.
+#[derive(Debug)]
+pub enum ZarrError {
+    Io(String, std::io::Error),
+    // url and reason
+    Download(String, String),
+    // file and parse error
+    InvalidJson(String, serde_json::Error),
+    InvalidMetadata(String),
+    UnsupportedCompressor(String),
+    InvalidChunk(String),
+}
+
+impl fmt::Display for ZarrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZarrError::Io(path, e) => write!(f, "Failed to access {}: {}", path, e),
+            ZarrError::Download(url, reason) => write!(f, "Failed to download {}: {}", url, reason),
+            ZarrError::InvalidJson(file, e) => write!(f, "Failed to parse {}: {}", file, e),
+            ZarrError::InvalidMetadata(msg) => write!(f, "Invalid zarr metadata: {}", msg),
+            ZarrError::UnsupportedCompressor(id) => write!(f, "Unsupported compressor: {}", id),
+            ZarrError::InvalidChunk(msg) => write!(f, "Invalid chunk: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ZarrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ZarrError::Io(_, e) => Some(e),
+            ZarrError::InvalidJson(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -1,14 +1,16 @@
 mod blosc;
+mod error;
 mod ome;
 #[cfg(test)]
 mod test;
 
-use crate::volume::{PaintVolume, VoxelPaintVolume, VoxelVolume};
+use crate::volume::{CacheConfig, CacheStats, DataType, PaintVolume, VolumeMetadata, VoxelPaintVolume, VoxelVolume};
 use blosc::BloscChunk;
 use dashmap::DashMap;
 use derive_more::with_trait::Debug;
 use directories::BaseDirs;
 use ehttp::Request;
+pub use error::ZarrError;
 use fxhash::{FxHashMap, FxHashSet};
 use libm::modf;
 pub use ome::OmeZarrContext;
@@ -20,7 +22,7 @@ use sha2::Sha256;
 use std::cell::RefCell;
 use std::fs::File;
 use std::io::Write;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::{
     ops::Deref,
     sync::{Arc, Mutex},
@@ -29,6 +31,8 @@ use std::{
 type HashMap<K, V> = FxHashMap<K, V>;
 type HashSet<K> = FxHashSet<K>;
 
+static NEXT_ARRAY_ID: AtomicUsize = AtomicUsize::new(0);
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum ZarrDataType {
     #[serde(rename = "|u1")]
@@ -88,15 +92,32 @@ struct ZarrArrayDef {
     dimension_separator: Option<String>,
 }
 
+/// Optional attributes (`.zattrs`) of a plain zarr array describing the physical layout and value range of the data
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+struct ZarrAttrs {
+    /// edge length of a voxel in µm
+    voxel_size_um: Option<f64>,
+    /// position of voxel [0, 0, 0] as [x, y, z] in voxels
+    origin: Option<[f64; 3]>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
 #[derive(Clone)]
 pub struct ZarrArray<const N: usize, T> {
+    // distinguishes chunks of different arrays sharing a cache
+    id: usize,
     access: Arc<dyn ZarrFileAccess>,
     def: ZarrArrayDef,
+    attrs: ZarrAttrs,
     phantom_t: std::marker::PhantomData<T>,
 }
 
 trait ZarrFileAccess: Send + Sync + Debug {
-    fn load_array_def(&self) -> ZarrArrayDef;
+    fn load_array_def(&self) -> Result<ZarrArrayDef, ZarrError>;
+    /// Attributes are optional, missing or unreadable ones are treated as empty
+    fn load_attrs(&self) -> ZarrAttrs;
     fn chunk_file_for(&self, array_def: &ZarrArrayDef, chunk_no: &[usize]) -> Option<Arc<File>>;
     fn cache_missing(&self) -> bool;
 }
@@ -106,9 +127,16 @@ struct ZarrDirectory {
     path: String,
 }
 impl ZarrFileAccess for ZarrDirectory {
-    fn load_array_def(&self) -> ZarrArrayDef {
-        let zarray = std::fs::read_to_string(format!("{}/.zarray", self.path)).unwrap();
-        serde_json::from_str::<ZarrArrayDef>(&zarray).unwrap()
+    fn load_array_def(&self) -> Result<ZarrArrayDef, ZarrError> {
+        read_json(&format!("{}/.zarray", self.path))
+    }
+    fn load_attrs(&self) -> ZarrAttrs {
+        let file = format!("{}/.zattrs", self.path);
+        if std::path::Path::new(&file).exists() {
+            read_json(&file).unwrap_or_default()
+        } else {
+            ZarrAttrs::default()
+        }
     }
 
     fn chunk_file_for(&self, array_def: &ZarrArrayDef, chunk_no: &[usize]) -> Option<Arc<File>> {
@@ -124,7 +152,7 @@ impl ZarrFileAccess for ZarrDirectory {
         if !std::path::Path::new(&chunk_path).exists() {
             None
         } else {
-            Some(File::open(chunk_path).unwrap().into())
+            File::open(chunk_path).ok().map(Arc::new)
         }
     }
     fn cache_missing(&self) -> bool {
@@ -190,19 +218,13 @@ struct RemoteZarrDirectory {
     downloader: Arc<dyn Downloader>,
 }
 impl ZarrFileAccess for RemoteZarrDirectory {
-    fn load_array_def(&self) -> ZarrArrayDef {
+    fn load_array_def(&self) -> Result<ZarrArrayDef, ZarrError> {
         let target_file = format!("{}/.zarray", self.local_cache_dir);
-        if !std::path::Path::new(&target_file).exists() {
-            let data = ehttp::fetch_blocking(&Request::get(&format!("{}/.zarray", self.url)))
-                .unwrap()
-                .bytes
-                .to_vec();
-            std::fs::create_dir_all(std::path::Path::new(&target_file).parent().unwrap()).unwrap();
-            std::fs::write(&target_file, &data).unwrap();
-        }
-
-        let zarray = std::fs::read_to_string(&target_file).unwrap();
-        serde_json::from_str::<ZarrArrayDef>(&zarray).unwrap()
+        fetch_to_cache(&format!("{}/.zarray", self.url), &target_file)?;
+        read_json(&target_file)
+    }
+    fn load_attrs(&self) -> ZarrAttrs {
+        load_remote_attrs(&self.url, &self.local_cache_dir)
     }
 
     fn chunk_file_for(&self, array_def: &ZarrArrayDef, chunk_no: &[usize]) -> Option<Arc<File>> {
@@ -213,7 +235,7 @@ impl ZarrFileAccess for RemoteZarrDirectory {
         );
 
         if std::path::Path::new(&target_file).exists() {
-            Some(File::open(target_file).unwrap().into())
+            File::open(target_file).ok().map(Arc::new)
         } else {
             let target_url = format!(
                 "{}/{}",
@@ -252,24 +274,13 @@ impl BlockingRemoteZarrDirectory {
     }
 }
 impl ZarrFileAccess for BlockingRemoteZarrDirectory {
-    fn load_array_def(&self) -> ZarrArrayDef {
+    fn load_array_def(&self) -> Result<ZarrArrayDef, ZarrError> {
         let target_file = format!("{}/.zarray", self.local_cache_dir);
-        if !std::path::Path::new(&target_file).exists() {
-            let zarray_url = format!("{}/.zarray", self.url);
-            let res = ehttp::fetch_blocking(&Request::get(&zarray_url)).unwrap();
-
-            if res.status != 200 {
-                panic!("Failed to download .zarray from {}, status: {}", zarray_url, res.status);
-            }
-            let data = res.bytes.to_vec();
-
-            std::fs::create_dir_all(std::path::Path::new(&target_file).parent().unwrap()).unwrap();
-            std::fs::write(&target_file, &data).unwrap();
-        }
-
-        let zarray = std::fs::read_to_string(&target_file).unwrap();
-        serde_json::from_str::<ZarrArrayDef>(&zarray)
-            .expect(format!("Failed to parse .zarray from {}", target_file).as_str())
+        fetch_to_cache(&format!("{}/.zarray", self.url), &target_file)?;
+        read_json(&target_file)
+    }
+    fn load_attrs(&self) -> ZarrAttrs {
+        load_remote_attrs(&self.url, &self.local_cache_dir)
     }
 
     fn chunk_file_for(&self, array_def: &ZarrArrayDef, chunk_no: &[usize]) -> Option<Arc<File>> {
@@ -280,7 +291,7 @@ impl ZarrFileAccess for BlockingRemoteZarrDirectory {
         );
 
         if std::path::Path::new(&target_file).exists() {
-            Some(File::open(target_file).unwrap().into())
+            File::open(target_file).ok().map(Arc::new)
         } else {
             let missing_marker_file = format!("{}.missing", target_file);
             if std::path::Path::new(&missing_marker_file).exists() {
@@ -311,7 +322,14 @@ impl ZarrFileAccess for BlockingRemoteZarrDirectory {
             let target_url = format!("{}/{}", self.url, chunk_str);
             //println!("Downloading chunk from {}", target_url);
             // run request with reqwest blocking
-            let response = self.client.get(&target_url).send().unwrap();
+            let response = match self.client.get(&target_url).send() {
+                Ok(response) => response,
+                Err(e) => {
+                    println!("Failed to download chunk from {}: {}", target_url, e);
+                    self.downloading.lock().unwrap().remove(&chunk_str);
+                    return None;
+                }
+            };
             if response.status() != 200 {
                 /* println!(
                     "Failed to download chunk from {}, status {}",
@@ -345,6 +363,32 @@ impl ZarrFileAccess for BlockingRemoteZarrDirectory {
     }
 }
 
+/// Downloads `url` to `target_file` if it is not already cached
+fn fetch_to_cache(url: &str, target_file: &str) -> Result<(), ZarrError> {
+    if !std::path::Path::new(target_file).exists() {
+        let res = ehttp::fetch_blocking(&Request::get(url)).map_err(|e| ZarrError::Download(url.to_string(), e))?;
+        if res.status != 200 {
+            return Err(ZarrError::Download(url.to_string(), format!("status {}", res.status)));
+        }
+        let io_error = |e| ZarrError::Io(target_file.to_string(), e);
+        std::fs::create_dir_all(std::path::Path::new(target_file).parent().unwrap()).map_err(io_error)?;
+        std::fs::write(target_file, &res.bytes).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+fn load_remote_attrs(url: &str, local_cache_dir: &str) -> ZarrAttrs {
+    let target_file = format!("{}/.zattrs", local_cache_dir);
+    fetch_to_cache(&format!("{}/.zattrs", url), &target_file)
+        .and_then(|_| read_json(&target_file))
+        .unwrap_or_default()
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(file: &str) -> Result<T, ZarrError> {
+    let json = std::fs::read_to_string(file).map_err(|e| ZarrError::Io(file.to_string(), e))?;
+    serde_json::from_str(&json).map_err(|e| ZarrError::InvalidJson(file.to_string(), e))
+}
+
 pub fn default_cache_dir_for_url(url: &str) -> String {
     let canonical_url = if url.ends_with("/") { &url[..url.len() - 1] } else { url };
     let sha256 = format!("{:x}", Sha256::digest(canonical_url.as_bytes()));
@@ -361,28 +405,29 @@ pub fn default_cache_dir_for_url(url: &str) -> String {
 
 impl<const N: usize> ZarrArray<N, u8> {
     fn load_chunk_context(&self, chunk_no: [usize; N]) -> Option<ChunkContext> {
-        self.access
-            .chunk_file_for(&self.def, &chunk_no)
-            .map(|chunk_file| match &self.def.compressor {
-                Some(compressor) => match compressor.id.as_str() {
-                    "blosc" => ChunkContext::Heap(BloscChunk::load_data_from_file(&chunk_file)),
-                    _ => panic!("Unsupported compressor: {}", compressor.id),
-                },
-                _ => ChunkContext::Raw(RawContext::load_from_file(&chunk_file)),
-            })
+        self.access.chunk_file_for(&self.def, &chunk_no).and_then(|chunk_file| {
+            // the compressor has been checked when the array was opened
+            let ctx = match &self.def.compressor {
+                Some(_) => BloscChunk::load_data_from_file(&chunk_file).map(ChunkContext::Heap),
+                _ => RawContext::load_from_file(&chunk_file).map(ChunkContext::Raw),
+            };
+            // treat broken chunks like missing ones
+            ctx.map_err(|e| println!("Failed to load chunk {:?}: {}", chunk_no, e))
+                .ok()
+        })
     }
-    pub fn from_path(path: &str) -> Self {
+    pub fn from_path(path: &str) -> Result<Self, ZarrError> {
         //println!("Loading ZarrArray from path: {}", path);
         Self::from_access(Arc::new(ZarrDirectory { path: path.to_string() }))
     }
-    pub fn from_url_blocking(url: &str, local_cache_dir: &str, client: Client) -> Self {
+    pub fn from_url_blocking(url: &str, local_cache_dir: &str, client: Client) -> Result<Self, ZarrError> {
         //println!("Loading ZarrArray from url: {}", url);
         Self::from_access(Arc::new(BlockingRemoteZarrDirectory::new(url, local_cache_dir, client)))
     }
-    pub fn from_url_to_default_cache_dir_blocking(url: &str, client: Client) -> Self {
+    pub fn from_url_to_default_cache_dir_blocking(url: &str, client: Client) -> Result<Self, ZarrError> {
         Self::from_url_blocking(url, default_cache_dir_for_url(&url).as_str(), client)
     }
-    pub fn from_url(url: &str, local_cache_dir: &str) -> Self {
+    pub fn from_url(url: &str, local_cache_dir: &str) -> Result<Self, ZarrError> {
         //println!("Loading ZarrArray from url: {} to: {} ", url, local_cache_dir);
         Self::from_access(Arc::new(RemoteZarrDirectory {
             url: url.to_string(),
@@ -390,20 +435,39 @@ impl<const N: usize> ZarrArray<N, u8> {
             downloader: Arc::new(SimpleDownloader::new()),
         }))
     }
-    pub fn from_url_to_default_cache_dir(url: &str) -> Self {
+    pub fn from_url_to_default_cache_dir(url: &str) -> Result<Self, ZarrError> {
         Self::from_url(url, &default_cache_dir_for_url(url))
     }
-    fn from_access(access: Arc<dyn ZarrFileAccess>) -> Self {
-        let def = access.load_array_def();
-        ZarrArray {
+    fn from_access(access: Arc<dyn ZarrFileAccess>) -> Result<Self, ZarrError> {
+        let def = access.load_array_def()?;
+        if def.shape.len() != N || def.chunks.len() != N {
+            return Err(ZarrError::InvalidMetadata(format!(
+                "expected {} dimensions but got shape {:?}",
+                N, def.shape
+            )));
+        }
+        if let Some(compressor) = &def.compressor {
+            if compressor.id != "blosc" {
+                return Err(ZarrError::UnsupportedCompressor(compressor.id.clone()));
+            }
+        }
+        let attrs = access.load_attrs();
+        Ok(ZarrArray {
+            id: NEXT_ARRAY_ID.fetch_add(1, Ordering::Relaxed),
             access,
             def,
+            attrs,
             phantom_t: std::marker::PhantomData,
-        }
+        })
     }
 
     pub fn into_ctx(self) -> ZarrContextBase<N> {
-        let cache = Arc::new(ZarrContextCache::new());
+        self.into_ctx_with_cache(&Arc::new(ZarrContextCache::new(CacheConfig::default())))
+    }
+    /// Creates a context using the given chunk cache, e.g. to share one memory budget between the levels of an
+    /// OME-Zarr volume
+    pub fn into_ctx_with_cache(self, cache: &Arc<ZarrContextCache<N>>) -> ZarrContextBase<N> {
+        let cache = cache.clone();
         let cache_missing = self.access.cache_missing();
         ZarrContextBase {
             array: self,
@@ -437,12 +501,12 @@ pub struct RawContext {
     data: memmap::Mmap,
 }
 impl RawContext {
-    fn load_from_file(chunk_file: &File) -> RawContext {
-        let data = unsafe { memmap::Mmap::map(chunk_file).unwrap() };
-        RawContext { data }
+    fn load_from_file(chunk_file: &File) -> Result<RawContext, ZarrError> {
+        let data = unsafe { memmap::Mmap::map(chunk_file) }.map_err(|e| ZarrError::Io("raw chunk".to_string(), e))?;
+        Ok(RawContext { data })
     }
-    fn load(chunk_file: &str) -> RawContext {
-        let file = std::fs::File::open(chunk_file).unwrap();
+    fn load(chunk_file: &str) -> Result<RawContext, ZarrError> {
+        let file = std::fs::File::open(chunk_file).map_err(|e| ZarrError::Io(chunk_file.to_string(), e))?;
         Self::load_from_file(&file)
     }
     fn get(&self, idx: usize) -> u8 {
@@ -467,6 +531,14 @@ struct ZarrContextCacheEntry {
     ctx: Arc<ChunkContext>,
     last_access: AtomicU64,
 }
+impl ZarrContextCacheEntry {
+    fn heap_bytes(&self) -> usize {
+        match self.ctx.as_ref() {
+            ChunkContext::Heap(data) => data.len(),
+            ChunkContext::Raw(_) => 0,
+        }
+    }
+}
 impl Deref for ZarrContextCacheEntry {
     type Target = ChunkContext;
     fn deref(&self) -> &Self::Target {
@@ -474,68 +546,100 @@ impl Deref for ZarrContextCacheEntry {
     }
 }
 
-struct ZarrContextCache<const N: usize> {
-    cache: DashMap<[usize; N], Option<ZarrContextCacheEntry>>,
+/// Chunks loaded by `ZarrContext`s. Memory-mapped chunks are left to the OS page cache, so only decompressed chunks
+/// count against the byte budget. When the budget or the maximum number of loaded chunks is exceeded, the least
+/// recently used chunks are dropped.
+pub struct ZarrContextCache<const N: usize> {
+    // (array id, chunk no) -> loaded chunk or None if missing
+    cache: DashMap<(usize, [usize; N]), Option<ZarrContextCacheEntry>>,
     access_counter: AtomicU64,
-    non_empty_entries: AtomicU64,
+    non_empty_entries: AtomicUsize,
+    config: CacheConfig,
+    bytes: AtomicUsize,
+    misses: AtomicU64,
+    evictions: AtomicU64,
 }
 impl<const N: usize> ZarrContextCache<N> {
-    fn new() -> Self {
+    pub fn new(config: CacheConfig) -> Self {
         ZarrContextCache {
             cache: DashMap::with_shard_amount(1024),
             access_counter: AtomicU64::new(0),
-            non_empty_entries: AtomicU64::new(0),
+            non_empty_entries: AtomicUsize::new(0),
+            config,
+            bytes: AtomicUsize::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
         }
     }
-    // TODO: for now we expect chunks to be uncompressed and memmapped so we piggy back on the OS page cache
-    // for zarrs that are compressed, we currently have a memory leak that might need fixing in the future
-    // To do that, cache entries should report on their memory usage and we should limit the total amount of memory used
-    // in the cache.
-    /*
-    fn cleanup(&mut self) {
-        if self.non_empty_entries > self.max_entries {
-            // FIXME: make configurable
-            // purge oldest n% of entries
-            let mut entries = self
-                .cache
-                .iter()
-                .filter_map(|(k, e)| e.as_ref().map(|e| (*k, e.last_access)))
-                .collect::<Vec<_>>();
-            entries.sort_by_key(|(_, e)| *e);
-            let n = (self.non_empty_entries as f64 * 0.2) as usize; // FIXME: make configurable
-            let _before = self.non_empty_entries;
-            let _sorted_entries_len = entries.len();
-            for (k, _) in entries.into_iter().take(n) {
-                if self.cache.remove(&k).is_some() {
-                    self.non_empty_entries -= 1;
-                }
+    pub fn stats(&self) -> CacheStats {
+        let accesses = self.access_counter.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        CacheStats {
+            entries: self.non_empty_entries.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            hits: accesses.saturating_sub(misses),
+            misses,
+            evictions: self.evictions.load(Ordering::Relaxed),
+            config: Some(self.config),
+        }
+    }
+    fn over_limit(&self) -> bool {
+        self.non_empty_entries.load(Ordering::Relaxed) > self.config.max_entries
+            || self.bytes.load(Ordering::Relaxed) > self.config.max_bytes
+    }
+    /// Drops least recently used chunks until the cache is below 90% of its limits
+    fn evict(&self) {
+        let mut entries = self
+            .cache
+            .iter()
+            .filter_map(|e| {
+                e.value()
+                    .as_ref()
+                    .map(|v| (*e.key(), v.last_access.load(Ordering::Relaxed)))
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, last_access)| *last_access);
+
+        let target_entries = self.config.max_entries - self.config.max_entries / 10;
+        let target_bytes = self.config.max_bytes - self.config.max_bytes / 10;
+        for (key, _) in entries {
+            if self.non_empty_entries.load(Ordering::Relaxed) <= target_entries
+                && self.bytes.load(Ordering::Relaxed) <= target_bytes
+            {
+                break;
+            }
+            if let Some((_, Some(entry))) = self.cache.remove(&key) {
+                self.non_empty_entries.fetch_sub(1, Ordering::Relaxed);
+                self.bytes.fetch_sub(entry.heap_bytes(), Ordering::Relaxed);
+                self.evictions.fetch_add(1, Ordering::Relaxed);
             }
-            /* println!(
-                "Purged {} entries {}/{} from {} (sorted: {})",
-                n, self.non_empty_entries, self.max_entries, before, sorted_entries_len
-            ); */
         }
-    }*/
+    }
     fn get(&self, array: &ZarrArray<N, u8>, chunk_no: [usize; N]) -> Option<Arc<ChunkContext>> {
-        let mut entry = self.cache.entry(chunk_no).or_insert_with(|| {
-            let ctx = array.load_chunk_context(chunk_no);
-            if ctx.is_none() {
-                None
-            } else {
-                self.non_empty_entries.fetch_add(1, Ordering::Relaxed);
-                Some(ZarrContextCacheEntry {
-                    ctx: Arc::new(ctx.unwrap()),
-                    last_access: AtomicU64::new(0),
-                })
-            }
-        });
         let counter = self.access_counter.fetch_add(1, Ordering::Relaxed);
-        if let Some(e) = entry.value_mut() {
-            e.last_access.store(counter, Ordering::Relaxed);
-            Some(e.ctx.clone())
-        } else {
-            None
+        let chunk = {
+            let entry = self.cache.entry((array.id, chunk_no)).or_insert_with(|| {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                array.load_chunk_context(chunk_no).map(|ctx| {
+                    let entry = ZarrContextCacheEntry {
+                        ctx: Arc::new(ctx),
+                        last_access: AtomicU64::new(counter),
+                    };
+                    self.non_empty_entries.fetch_add(1, Ordering::Relaxed);
+                    self.bytes.fetch_add(entry.heap_bytes(), Ordering::Relaxed);
+                    entry
+                })
+            });
+            entry.value().as_ref().map(|e| {
+                e.last_access.store(counter, Ordering::Relaxed);
+                e.ctx.clone()
+            })
+        };
+        // entry must be released before evicting to avoid dead-locking on the shard
+        if self.over_limit() {
+            self.evict();
         }
+        chunk
     }
     fn purge_missing(&self) {
         self.cache.retain(|_, e| if e.is_none() { false } else { true });
@@ -801,6 +905,20 @@ impl PaintVolume for ZarrContext<3> {
     }
 }
 impl VoxelVolume for ZarrContext<3> {
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        let shape = &self.array.def.shape;
+        let attrs = &self.array.attrs;
+        let mut metadata = VolumeMetadata::new(
+            [shape[2], shape[1], shape[0]],
+            DataType::from_numpy_dtype(&self.array.def.dtype),
+        );
+        metadata.voxel_size_um = attrs.voxel_size_um;
+        metadata.origin = attrs.origin.unwrap_or_default();
+        if let (Some(min), Some(max)) = (attrs.min, attrs.max) {
+            metadata = metadata.with_data_range(min, max);
+        }
+        Some(metadata)
+    }
     fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
         self.get([
             (xyz[2] * downsampling as f64) as usize,
@@ -809,6 +927,21 @@ impl VoxelVolume for ZarrContext<3> {
         ])
         .unwrap_or(0)
     }
+    fn try_get(&self, xyz: [f64; 3], downsampling: i32) -> Option<u8> {
+        let index = [
+            (xyz[2] * downsampling as f64) as usize,
+            (xyz[1] * downsampling as f64) as usize,
+            (xyz[0] * downsampling as f64) as usize,
+        ];
+        if index.iter().zip(self.array.def.shape.iter()).any(|(i, s)| i >= s) {
+            return Some(0);
+        }
+        // without caching missing chunks, they might still be downloading
+        match self.get(index) {
+            None if self.cache_missing => Some(0),
+            value => value,
+        }
+    }
     fn get_interpolated(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
         self.get_interpolated([
             (xyz[2] * downsampling as f64) as f64,
@@ -820,4 +953,7 @@ impl VoxelVolume for ZarrContext<3> {
     fn reset_for_painting(&self) {
         self.cache.purge_missing();
     }
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.cache.stats())
+    }
 }
@@ -1,12 +1,14 @@
 #![allow(dead_code)]
-use super::{ZarrArray, ZarrContext};
+use super::{fetch_to_cache, read_json, ZarrArray, ZarrContext, ZarrContextCache, ZarrError};
 use crate::volume::PaintVolume;
+use crate::volume::VolumeMetadata;
 use crate::volume::VoxelPaintVolume;
 use crate::volume::VoxelVolume;
+use crate::volume::{CacheConfig, CacheStats};
 use crate::zarr::default_cache_dir_for_url;
 use egui::Color32;
-use ehttp::Request;
 use serde::Deserialize;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct OmeMultiScale {
@@ -20,6 +22,8 @@ pub struct OmeMultiScale {
 pub struct OmeAxis {
     pub name: String,
     pub r#type: String,
+    #[serde(default)]
+    pub unit: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -34,6 +38,8 @@ pub struct OmeDataset {
 pub enum OmeCoordinateTransformation {
     #[allow(non_camel_case_types)]
     scale(OmeScale),
+    #[allow(non_camel_case_types)]
+    translation(OmeTranslation),
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -41,15 +47,43 @@ pub struct OmeScale {
     pub scale: Vec<f64>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct OmeTranslation {
+    pub translation: Vec<f64>,
+}
+
+/// Rendering settings of OME-Zarr images, the window records the value range of a channel
+#[derive(Debug, Clone, Deserialize)]
+pub struct OmeOmero {
+    pub channels: Vec<OmeChannel>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OmeChannel {
+    #[serde(default)]
+    pub window: Option<OmeWindow>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OmeWindow {
+    pub min: f64,
+    pub max: f64,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct OmeZarrAttrs {
     pub multiscales: Vec<OmeMultiScale>,
+    #[serde(default)]
+    pub omero: Option<OmeOmero>,
 }
 
 impl OmeZarrAttrs {
     pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
         let multiscales: Vec<OmeMultiScale> = serde_json::from_str(json)?;
-        Ok(OmeZarrAttrs { multiscales })
+        Ok(OmeZarrAttrs {
+            multiscales,
+            omero: None,
+        })
     }
 }
 
@@ -87,73 +121,78 @@ impl ColorScheme for GrayScale {
 }
 
 impl<C: ColorScheme> OmeZarrContext<C> {
-    pub fn from_url(url: &str, local_cache_dir: &str) -> Self {
-        let attrs = Self::load_attrs(url, local_cache_dir);
+    pub fn from_url(url: &str, local_cache_dir: &str) -> Result<Self, ZarrError> {
+        let attrs = Self::load_attrs(url, local_cache_dir)?;
 
         let ome_zarr = OmeZarr { attrs };
-        let zarr_contexts = ome_zarr.attrs.multiscales[0]
-            .datasets
+        let cache = Arc::new(ZarrContextCache::new(CacheConfig::default()));
+        let zarr_contexts = Self::datasets(&ome_zarr)?
             .iter()
             .map(|dataset| {
                 let url_path = format!("{}/{}", url, dataset.path);
                 let cache_path = format!("{}/{}", local_cache_dir, dataset.path);
-                ZarrArray::from_url(&url_path, &cache_path).into_ctx().into_ctx()
+                Ok(ZarrArray::from_url(&url_path, &cache_path)?
+                    .into_ctx_with_cache(&cache)
+                    .into_ctx())
             })
             .take(4) // FIXME
-            .collect();
+            .collect::<Result<Vec<_>, ZarrError>>()?;
 
-        Self {
+        Ok(Self {
             ome_zarr,
             zarr_contexts,
             cache_missing: false,
             phantom: std::marker::PhantomData,
-        }
+        })
     }
-    pub fn from_url_to_default_cache_dir(url: &str) -> Self {
+    pub fn from_url_to_default_cache_dir(url: &str) -> Result<Self, ZarrError> {
         let url = if url.ends_with("/") { &url[..url.len() - 1] } else { url };
         Self::from_url(url, &default_cache_dir_for_url(url))
     }
-    pub fn from_path(path: &str) -> Self {
-        let attrs = {
-            let target_file = format!("{}/.zattrs", path);
-            let zarray = std::fs::read_to_string(&target_file).unwrap();
-            println!("zarray: {}", zarray);
-            serde_json::from_str::<OmeZarrAttrs>(&zarray).unwrap()
-        };
+    pub fn from_path(path: &str) -> Result<Self, ZarrError> {
+        let attrs = read_json::<OmeZarrAttrs>(&format!("{}/.zattrs", path))?;
 
         let ome_zarr = OmeZarr { attrs };
-        let zarr_contexts = ome_zarr.attrs.multiscales[0]
-            .datasets
+        let cache = Arc::new(ZarrContextCache::new(CacheConfig::default()));
+        let zarr_contexts = Self::datasets(&ome_zarr)?
             .iter()
             .map(|dataset| {
                 let path = format!("{}/{}", path, dataset.path);
-                ZarrArray::from_path(&path).into_ctx().into_ctx()
+                Ok(ZarrArray::from_path(&path)?.into_ctx_with_cache(&cache).into_ctx())
             })
             .take(4) // FIXME
-            .collect();
+            .collect::<Result<Vec<_>, ZarrError>>()?;
 
-        Self {
+        Ok(Self {
             ome_zarr,
             zarr_contexts,
             cache_missing: false,
             phantom: std::marker::PhantomData,
-        }
+        })
     }
 
-    fn load_attrs(url: &str, local_cache_dir: &str) -> OmeZarrAttrs {
-        let target_file = format!("{}/.zattrs", local_cache_dir);
-        if !std::path::Path::new(&target_file).exists() {
-            let data = ehttp::fetch_blocking(&Request::get(&format!("{}/.zattrs", url)))
-                .unwrap()
-                .bytes
-                .to_vec();
-            std::fs::create_dir_all(std::path::Path::new(&target_file).parent().unwrap()).unwrap();
-            std::fs::write(&target_file, &data).unwrap();
+    /// Replaces the chunk cache shared by all levels with an empty one using the given limits
+    pub fn with_cache_config(mut self, config: CacheConfig) -> Self {
+        let cache = Arc::new(ZarrContextCache::new(config));
+        self.zarr_contexts = self
+            .zarr_contexts
+            .iter()
+            .map(|ctx| ctx.array.clone().into_ctx_with_cache(&cache).into_ctx())
+            .collect();
+        self
+    }
+
+    fn datasets(ome_zarr: &OmeZarr) -> Result<&[OmeDataset], ZarrError> {
+        match ome_zarr.attrs.multiscales.first() {
+            Some(multiscale) if !multiscale.datasets.is_empty() => Ok(&multiscale.datasets),
+            _ => Err(ZarrError::InvalidMetadata("no multiscale datasets found".to_string())),
         }
+    }
 
-        let zarray = std::fs::read_to_string(&target_file).unwrap();
-        println!("zarray: {}", zarray);
-        serde_json::from_str::<OmeZarrAttrs>(&zarray).unwrap()
+    fn load_attrs(url: &str, local_cache_dir: &str) -> Result<OmeZarrAttrs, ZarrError> {
+        let target_file = format!("{}/.zattrs", local_cache_dir);
+        fetch_to_cache(&format!("{}/.zattrs", url), &target_file)?;
+        read_json(&target_file)
     }
 
     fn get(&self, xyz: [usize; 3], scale: u8) -> u8 {
@@ -186,6 +225,55 @@ impl<C: ColorScheme> OmeZarrContext<C> {
         }
         0
     }
+    /// Voxel size from the scale of the full resolution level, only known if the x axis declares a unit
+    fn voxel_size_um(&self) -> Option<f64> {
+        let multiscale = &self.ome_zarr.attrs.multiscales[0];
+        let x_axis = multiscale.axes.iter().position(|a| a.name == "x")?;
+        let factor = match multiscale.axes[x_axis].unit.as_deref()? {
+            "micrometer" => 1.0,
+            "nanometer" => 0.001,
+            "millimeter" => 1000.0,
+            _ => return None,
+        };
+        self.full_resolution_scale()?.get(x_axis).map(|s| s * factor)
+    }
+    fn full_resolution_scale(&self) -> Option<&[f64]> {
+        let dataset = &self.ome_zarr.attrs.multiscales[0].datasets[0];
+        dataset.coordinate_transformations.iter().find_map(|t| match t {
+            OmeCoordinateTransformation::scale(scale) => Some(scale.scale.as_slice()),
+            _ => None,
+        })
+    }
+    /// Position of the first voxel in full resolution voxels, from the translation of the full resolution level
+    fn origin(&self) -> [f64; 3] {
+        let multiscale = &self.ome_zarr.attrs.multiscales[0];
+        let translation = multiscale.datasets[0]
+            .coordinate_transformations
+            .iter()
+            .find_map(|t| match t {
+                OmeCoordinateTransformation::translation(translation) => Some(&translation.translation),
+                _ => None,
+            });
+        let Some(translation) = translation else {
+            return [0.0; 3];
+        };
+        let scale = self.full_resolution_scale();
+        ["x", "y", "z"].map(|name| {
+            multiscale
+                .axes
+                .iter()
+                .position(|a| a.name == name)
+                .and_then(|axis| {
+                    let s = scale.and_then(|s| s.get(axis)).copied().unwrap_or(1.0);
+                    Some(translation.get(axis)? / s)
+                })
+                .unwrap_or(0.0)
+        })
+    }
+    fn data_range(&self) -> Option<[f64; 2]> {
+        let window = self.ome_zarr.attrs.omero.as_ref()?.channels.first()?.window.as_ref()?;
+        Some([window.min, window.max])
+    }
 }
 
 impl<C: ColorScheme + 'static + Send + Sync> PaintVolume for OmeZarrContext<C> {
@@ -252,6 +340,20 @@ impl<C: ColorScheme + 'static + Send + Sync> PaintVolume for OmeZarrContext<C> {
 }
 
 impl<C: ColorScheme> VoxelVolume for OmeZarrContext<C> {
+    fn metadata(&self) -> Option<VolumeMetadata> {
+        let levels = self
+            .zarr_contexts
+            .iter()
+            .map(|ctx| ctx.metadata())
+            .collect::<Option<Vec<_>>>()?;
+        let mut metadata = levels.first()?.clone();
+        metadata.shape = levels.iter().map(|m| m.full_shape()).collect();
+
+        metadata.voxel_size_um = self.voxel_size_um();
+        metadata.origin = self.origin();
+        metadata.data_range = self.data_range().or(metadata.data_range);
+        Some(metadata)
+    }
     fn get(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
         let scale = downsampling.trailing_zeros() as u8;
         self.get(
@@ -263,6 +365,13 @@ impl<C: ColorScheme> VoxelVolume for OmeZarrContext<C> {
             scale,
         )
     }
+    /// Only the requested level counts, the fallback to coarser levels of `get` is only for display
+    fn try_get(&self, xyz: [f64; 3], downsampling: i32) -> Option<u8> {
+        let scale = downsampling.trailing_zeros() as usize;
+        let max = self.zarr_contexts.len() - 1;
+        // coordinates of a level are downsampled by its scale
+        self.zarr_contexts[scale.min(max)].try_get(xyz, 1 << scale.saturating_sub(max))
+    }
     fn get_interpolated(&self, xyz: [f64; 3], downsampling: i32) -> u8 {
         let scale = downsampling.trailing_zeros() as u8;
         self.get_interpolated(
@@ -279,4 +388,8 @@ impl<C: ColorScheme> VoxelVolume for OmeZarrContext<C> {
             ctx.reset_for_painting();
         });
     }
+    fn cache_stats(&self) -> Option<CacheStats> {
+        // all levels share the same cache
+        self.zarr_contexts.first()?.cache_stats()
+    }
 }
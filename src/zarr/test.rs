@@ -494,7 +494,8 @@ fn write_points2(array: &mut ZarrContext<3>) -> SparsePointCloud {
 
 #[test]
 pub fn test_zarr() {
-    let zarr: ZarrArray<3, u8> = ZarrArray::from_path("/home/johannes/tmp/pap/bruniss/mask-fiber-only_rescaled.zarr");
+    let zarr: ZarrArray<3, u8> =
+        ZarrArray::from_path("/home/johannes/tmp/pap/bruniss/mask-fiber-only_rescaled.zarr").unwrap();
     let mut zarr = zarr.into_ctx();
     //write_points2(&mut zarr);
 
@@ -578,3 +579,127 @@ b4 02 69 00 cbytes = 0x6902b4 = 6881972
 
 
 */
+
+#[test]
+pub fn chunk_cache_evicts_least_recently_used() {
+    use super::ZarrContextCache;
+    use crate::volume::CacheConfig;
+    use std::sync::Arc;
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join(".zarray"),
+        r#"{"chunks": [2, 2, 2], "compressor": null, "dtype": "|u1", "fill_value": 0, "filters": null, "order": "C", "shape": [2, 2, 8], "zarr_format": 2}"#,
+    )
+    .unwrap();
+    for x in 0..4 {
+        std::fs::write(dir.path().join(format!("0.0.{}", x)), [x as u8 + 1; 8]).unwrap();
+    }
+
+    let cache = Arc::new(ZarrContextCache::new(CacheConfig {
+        max_entries: 2,
+        max_bytes: 1 << 20,
+    }));
+    let array: ZarrArray<3, u8> = ZarrArray::from_path(dir.path().to_str().unwrap()).unwrap();
+    let ctx = array.into_ctx_with_cache(&cache).into_ctx();
+    for x in 0..4 {
+        assert_eq!(ctx.get([0, 0, x * 2]), Some(x as u8 + 1));
+    }
+    // same chunk again
+    assert_eq!(ctx.get([1, 1, 7]), Some(4));
+
+    let stats = cache.stats();
+    assert!(stats.entries <= 2);
+    assert_eq!(stats.misses, 4);
+    assert_eq!(stats.evictions, 2);
+    // evicted chunks are loaded again
+    assert_eq!(ctx.get([0, 0, 0]), Some(1));
+    assert_eq!(cache.stats().misses, 5);
+}
+
+#[test]
+pub fn metadata_from_zattrs() {
+    use crate::volume::VoxelVolume;
+
+    let dir = tempfile::tempdir().unwrap();
+    let zarray = r#"{"chunks": [2, 2, 2], "compressor": null, "dtype": "|u1", "fill_value": 0, "filters": null, "order": "C", "shape": [4, 3, 8], "zarr_format": 2}"#;
+    let plain = dir.path().join("plain");
+    std::fs::create_dir(&plain).unwrap();
+    std::fs::write(plain.join(".zarray"), zarray).unwrap();
+
+    let with_attrs = dir.path().join("attrs");
+    std::fs::create_dir(&with_attrs).unwrap();
+    std::fs::write(with_attrs.join(".zarray"), zarray).unwrap();
+    std::fs::write(
+        with_attrs.join(".zattrs"),
+        r#"{"voxel_size_um": 7.91, "origin": [10, 20, 30], "min": 2, "max": 250, "other": "ignored"}"#,
+    )
+    .unwrap();
+
+    let metadata = |path: &std::path::Path| {
+        let array: ZarrArray<3, u8> = ZarrArray::from_path(path.to_str().unwrap()).unwrap();
+        array.into_ctx().into_ctx().metadata().unwrap()
+    };
+
+    let plain = metadata(&plain);
+    assert_eq!(plain.full_shape(), [8, 3, 4]);
+    assert_eq!(plain.voxel_size_um, None);
+    assert_eq!(plain.origin, [0.0; 3]);
+    assert_eq!(plain.data_range, None);
+
+    let with_attrs = metadata(&with_attrs);
+    assert_eq!(with_attrs.voxel_size_um, Some(7.91));
+    assert_eq!(with_attrs.origin, [10.0, 20.0, 30.0]);
+    assert_eq!(with_attrs.data_range, Some([2.0, 250.0]));
+}
+
+#[test]
+pub fn metadata_from_ome_zarr_attrs() {
+    use super::ome::{GrayScale, OmeZarrContext};
+    use crate::volume::VoxelVolume;
+
+    let dir = tempfile::tempdir().unwrap();
+    for (level, shape) in [(0, [8, 8, 16]), (1, [4, 4, 8])] {
+        let path = dir.path().join(level.to_string());
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(
+            path.join(".zarray"),
+            format!(
+                r#"{{"chunks": [4, 4, 4], "compressor": null, "dtype": "|u1", "fill_value": 0, "filters": null, "order": "C", "shape": {:?}, "zarr_format": 2}}"#,
+                shape
+            ),
+        )
+        .unwrap();
+    }
+    std::fs::write(
+        dir.path().join(".zattrs"),
+        r#"{
+            "multiscales": [{
+                "axes": [
+                    {"name": "z", "type": "space", "unit": "micrometer"},
+                    {"name": "y", "type": "space", "unit": "micrometer"},
+                    {"name": "x", "type": "space", "unit": "micrometer"}
+                ],
+                "datasets": [
+                    {"path": "0", "coordinateTransformations": [
+                        {"type": "scale", "scale": [2.0, 2.0, 2.0]},
+                        {"type": "translation", "translation": [6.0, 4.0, 2.0]}
+                    ]},
+                    {"path": "1", "coordinateTransformations": [{"type": "scale", "scale": [4.0, 4.0, 4.0]}]}
+                ],
+                "name": "test",
+                "version": "0.4"
+            }],
+            "omero": {"channels": [{"window": {"min": 5, "max": 120, "start": 5, "end": 120}}]}
+        }"#,
+    )
+    .unwrap();
+
+    let ctx = OmeZarrContext::<GrayScale>::from_path(dir.path().to_str().unwrap()).unwrap();
+    let metadata = ctx.metadata().unwrap();
+    assert_eq!(metadata.full_shape(), [16, 8, 8]);
+    assert_eq!(metadata.voxel_size_um, Some(2.0));
+    // translation is given in µm, the origin in full resolution voxels
+    assert_eq!(metadata.origin, [1.0, 2.0, 3.0]);
+    assert_eq!(metadata.data_range, Some([5.0, 120.0]));
+}